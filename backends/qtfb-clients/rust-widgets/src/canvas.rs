@@ -0,0 +1,166 @@
+use ab_glyph::{Font, FontArc, PxScale, ScaleFont};
+use anyhow::{bail, Result};
+use qtfb_client::{
+    constants,
+    pixel::{bytes_per_pixel, rgb565},
+    ClientConnection,
+};
+
+use crate::Rect;
+
+/// Grayscale drawing on top of a raw framebuffer in any of the `FBFMT_*` layouts.
+pub struct Canvas<'a> {
+    buf: &'a mut [u8],
+    shm_type: u8,
+    width: i32,
+    height: i32,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(buf: &'a mut [u8], shm_type: u8, width: u16, height: u16) -> Result<Self> {
+        let bpp = bytes_per_pixel(shm_type)?;
+        if buf.len() < usize::from(width) * usize::from(height) * bpp {
+            bail!("Framebuffer smaller than {width}x{height}");
+        }
+        Ok(Self {
+            buf,
+            shm_type,
+            width: width.into(),
+            height: height.into(),
+        })
+    }
+
+    pub fn from_connection(connection: &'a mut ClientConnection<'_>) -> Result<Self> {
+        let (shm_type, width, height) = (
+            connection.shm_type(),
+            connection.width(),
+            connection.height(),
+        );
+        Self::new(connection.shm, shm_type, width, height)
+    }
+
+    pub const fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    fn offset(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        let bpp = bytes_per_pixel(self.shm_type).ok()?;
+        Some((y as usize * self.width as usize + x as usize) * bpp)
+    }
+
+    pub fn get_pixel(&self, x: i32, y: i32) -> Option<u8> {
+        let i = self.offset(x, y)?;
+        Some(match self.shm_type {
+            constants::FBFMT_RMPP_RGB888 | constants::FBFMT_RMPP_RGBA8888 => self.buf[i + 1],
+            _ => {
+                let rgb565 = u16::from_ne_bytes([self.buf[i], self.buf[i + 1]]);
+                (((rgb565 >> 5) & 0x3f) << 2) as u8
+            }
+        })
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32, gray: u8) {
+        let Some(i) = self.offset(x, y) else {
+            return;
+        };
+        match self.shm_type {
+            constants::FBFMT_RMPP_RGB888 => self.buf[i..i + 3].fill(gray),
+            constants::FBFMT_RMPP_RGBA8888 => {
+                self.buf[i..i + 3].fill(gray);
+                self.buf[i + 3] = 255;
+            }
+            _ => {
+                self.buf[i..i + 2].copy_from_slice(&rgb565(gray, gray, gray).to_ne_bytes());
+            }
+        }
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, gray: u8) {
+        let Some(rect) = rect.intersection(&self.bounds()) else {
+            return;
+        };
+        for y in rect.y..rect.bottom() {
+            for x in rect.x..rect.right() {
+                self.set_pixel(x, y, gray);
+            }
+        }
+    }
+
+    pub fn stroke_rect(&mut self, rect: Rect, gray: u8, thickness: i32) {
+        let t = thickness.min(rect.w / 2).min(rect.h / 2).max(1);
+        self.fill_rect(Rect::new(rect.x, rect.y, rect.w, t), gray);
+        self.fill_rect(Rect::new(rect.x, rect.bottom() - t, rect.w, t), gray);
+        self.fill_rect(Rect::new(rect.x, rect.y, t, rect.h), gray);
+        self.fill_rect(Rect::new(rect.right() - t, rect.y, t, rect.h), gray);
+    }
+
+    /// Draws a single line of text with its top-left corner at `(x, y)`, clipped to `clip`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_text(
+        &mut self,
+        font: &FontArc,
+        size: f32,
+        text: &str,
+        x: i32,
+        y: i32,
+        gray: u8,
+        clip: Rect,
+    ) {
+        let Some(clip) = clip.intersection(&self.bounds()) else {
+            return;
+        };
+        let scaled = font.as_scaled(PxScale::from(size));
+        let mut caret = x as f32;
+        let baseline = y as f32 + scaled.ascent();
+        let mut previous = None;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(size, ab_glyph::point(caret, baseline));
+            caret += scaled.h_advance(id);
+            previous = Some(id);
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let glyph_bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let px = glyph_bounds.min.x as i32 + gx as i32;
+                let py = glyph_bounds.min.y as i32 + gy as i32;
+                if !clip.contains(px, py) {
+                    return;
+                }
+                let Some(bg) = self.get_pixel(px, py) else {
+                    return;
+                };
+                let coverage = coverage.clamp(0.0, 1.0);
+                let blended = f32::from(bg) + (f32::from(gray) - f32::from(bg)) * coverage;
+                self.set_pixel(px, py, blended as u8);
+            });
+        }
+    }
+}
+
+pub fn text_width(font: &FontArc, size: f32, text: &str) -> i32 {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut previous = None;
+    let mut width = 0.0;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width.ceil() as i32
+}
+
+pub fn line_height(font: &FontArc, size: f32) -> i32 {
+    let scaled = font.as_scaled(PxScale::from(size));
+    (scaled.ascent() - scaled.descent()).ceil() as i32
+}
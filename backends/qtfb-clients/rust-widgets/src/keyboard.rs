@@ -0,0 +1,134 @@
+use qtfb_client::gestures::Gesture;
+
+use crate::{text_width, Canvas, Rect, SizeHint, Theme, Widget, WidgetState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Backspace,
+    Enter,
+    Shift,
+}
+
+impl Key {
+    fn label(self, shifted: bool) -> String {
+        match self {
+            Self::Char(' ') => "space".to_string(),
+            Self::Char(c) if shifted => c.to_uppercase().collect(),
+            Self::Char(c) => c.to_string(),
+            Self::Backspace => "⌫".to_string(),
+            Self::Enter => "enter".to_string(),
+            Self::Shift => "shift".to_string(),
+        }
+    }
+
+    /// Width relative to a regular key.
+    const fn weight(self) -> i32 {
+        match self {
+            Self::Char(' ') => 5,
+            Self::Char(_) => 1,
+            Self::Backspace | Self::Enter | Self::Shift => 2,
+        }
+    }
+}
+
+const ROWS: [&str; 4] = ["1234567890", "qwertyuiop", "asdfghjkl-", "zxcvbnm.,/"];
+
+/// An on-screen QWERTY keyboard. Shift is handled internally; every other key press is
+/// reported through `on_key`, already upper-cased while shift is active.
+pub struct Keyboard<M> {
+    state: WidgetState,
+    rows: Vec<Vec<Key>>,
+    shifted: bool,
+    on_key: fn(Key) -> M,
+}
+
+impl<M> Keyboard<M> {
+    pub fn new(on_key: fn(Key) -> M) -> Self {
+        let mut rows = ROWS
+            .iter()
+            .map(|row| row.chars().map(Key::Char).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        rows[3].push(Key::Backspace);
+        rows.push(vec![Key::Shift, Key::Char(' '), Key::Enter]);
+        Self {
+            state: WidgetState::default(),
+            rows,
+            shifted: false,
+            on_key,
+        }
+    }
+
+    fn row_height(&self) -> i32 {
+        self.state.bounds.h / self.rows.len() as i32
+    }
+
+    fn key_rects(&self) -> impl Iterator<Item = (Key, Rect)> + '_ {
+        let bounds = self.state.bounds;
+        let row_height = self.row_height();
+        self.rows.iter().enumerate().flat_map(move |(i, row)| {
+            let total = row.iter().map(|k| k.weight()).sum::<i32>().max(1);
+            let unit = bounds.w / total;
+            let y = bounds.y + i as i32 * row_height;
+            let mut x = bounds.x + (bounds.w - unit * total) / 2;
+            row.iter().map(move |&key| {
+                let rect = Rect::new(x, y, unit * key.weight(), row_height);
+                x += rect.w;
+                (key, rect)
+            })
+        })
+    }
+}
+
+impl<M> Widget<M> for Keyboard<M> {
+    crate::widget_state!();
+
+    fn size_hint(&self, theme: &Theme) -> SizeHint {
+        SizeHint {
+            width: None,
+            height: Some((theme.line_height() + theme.padding * 3) * self.rows.len() as i32),
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        for (key, rect) in self.key_rects() {
+            let rect = rect.inset(theme.border);
+            if key == Key::Shift && self.shifted {
+                canvas.fill_rect(rect, theme.accent);
+            }
+            canvas.stroke_rect(rect, theme.foreground, theme.border);
+            let label = key.label(self.shifted);
+            let width = text_width(&theme.font, theme.font_size, &label);
+            canvas.draw_text(
+                &theme.font,
+                theme.font_size,
+                &label,
+                rect.x + (rect.w - width) / 2,
+                rect.y + (rect.h - theme.line_height()) / 2,
+                theme.foreground,
+                rect,
+            );
+        }
+    }
+
+    fn on_gesture(&mut self, gesture: &Gesture) -> Option<M> {
+        let Gesture::Tap { x, y, .. } = *gesture else {
+            return None;
+        };
+        let (key, _) = self.key_rects().find(|(_, rect)| rect.contains(x, y))?;
+        match key {
+            Key::Shift => {
+                self.shifted = !self.shifted;
+                self.state.dirty = true;
+                None
+            }
+            Key::Char(c) if self.shifted => {
+                self.shifted = false;
+                self.state.dirty = true;
+                let c = c.to_uppercase().next().unwrap_or(c);
+                Some((self.on_key)(Key::Char(c)))
+            }
+            key => Some((self.on_key)(key)),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use qtfb_client::gestures::Gesture;
+
+use crate::{gesture_position, Canvas, Rect, SizeHint, Theme, Widget, WidgetState};
+
+type Children<M> = Vec<Box<dyn Widget<M>>>;
+
+/// Splits `available` between children: those with a preferred size get it, the rest share
+/// what is left equally.
+fn distribute(hints: &[Option<i32>], available: i32, spacing: i32) -> Vec<i32> {
+    let gaps = spacing * (hints.len() as i32 - 1).max(0);
+    let fixed = hints.iter().flatten().sum::<i32>();
+    let flexible = hints.iter().filter(|x| x.is_none()).count() as i32;
+    let share = if flexible > 0 {
+        ((available - fixed - gaps) / flexible).max(0)
+    } else {
+        0
+    };
+    hints.iter().map(|x| x.unwrap_or(share)).collect()
+}
+
+fn draw_children<M>(children: &Children<M>, canvas: &mut Canvas<'_>, theme: &Theme) {
+    for child in children {
+        child.draw(canvas, theme);
+    }
+}
+
+fn redraw_children<M>(
+    children: &mut Children<M>,
+    canvas: &mut Canvas<'_>,
+    theme: &Theme,
+    damage: &mut Vec<Rect>,
+) {
+    for child in children {
+        child.redraw(canvas, theme, damage);
+    }
+}
+
+fn dispatch<M>(children: &mut Children<M>, gesture: &Gesture) -> Option<M> {
+    match gesture_position(gesture) {
+        Some((x, y)) => children
+            .iter_mut()
+            .find(|child| child.bounds().contains(x, y))?
+            .on_gesture(gesture),
+        None => children
+            .iter_mut()
+            .find_map(|child| child.on_gesture(gesture)),
+    }
+}
+
+macro_rules! container {
+    ($name:ident, $doc:literal) => {
+        #[doc = $doc]
+        pub struct $name<M> {
+            state: WidgetState,
+            pub children: Children<M>,
+            pub spacing: i32,
+            pub padding: i32,
+        }
+
+        impl<M> Default for $name<M> {
+            fn default() -> Self {
+                Self {
+                    state: WidgetState::default(),
+                    children: Vec::new(),
+                    spacing: 0,
+                    padding: 0,
+                }
+            }
+        }
+
+        impl<M> $name<M> {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            #[must_use]
+            pub fn push(mut self, child: impl Widget<M> + 'static) -> Self {
+                self.children.push(Box::new(child));
+                self
+            }
+
+            #[must_use]
+            pub const fn spacing(mut self, spacing: i32) -> Self {
+                self.spacing = spacing;
+                self
+            }
+
+            #[must_use]
+            pub const fn padding(mut self, padding: i32) -> Self {
+                self.padding = padding;
+                self
+            }
+        }
+    };
+}
+
+container!(Column, "Stacks children top to bottom.");
+container!(Row, "Places children left to right.");
+
+impl<M> Widget<M> for Column<M> {
+    crate::widget_state!();
+
+    fn size_hint(&self, theme: &Theme) -> SizeHint {
+        let hints = self
+            .children
+            .iter()
+            .map(|x| x.size_hint(theme).height)
+            .collect::<Option<Vec<_>>>();
+        SizeHint {
+            width: None,
+            height: hints.map(|x| {
+                x.iter().sum::<i32>()
+                    + self.spacing * (x.len() as i32 - 1).max(0)
+                    + self.padding * 2
+            }),
+        }
+    }
+
+    fn layout(&mut self, bounds: Rect, theme: &Theme) {
+        self.state.bounds = bounds;
+        let inner = bounds.inset(self.padding);
+        let hints = self
+            .children
+            .iter()
+            .map(|x| x.size_hint(theme).height)
+            .collect::<Vec<_>>();
+        let mut y = inner.y;
+        for (child, h) in self
+            .children
+            .iter_mut()
+            .zip(distribute(&hints, inner.h, self.spacing))
+        {
+            child.layout(Rect::new(inner.x, y, inner.w, h), theme);
+            y += h + self.spacing;
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        draw_children(&self.children, canvas, theme);
+    }
+
+    fn mark_dirty(&mut self) {
+        self.children.iter_mut().for_each(|x| x.mark_dirty());
+    }
+
+    fn redraw(&mut self, canvas: &mut Canvas<'_>, theme: &Theme, damage: &mut Vec<Rect>) {
+        redraw_children(&mut self.children, canvas, theme, damage);
+    }
+
+    fn on_gesture(&mut self, gesture: &Gesture) -> Option<M> {
+        dispatch(&mut self.children, gesture)
+    }
+}
+
+impl<M> Widget<M> for Row<M> {
+    crate::widget_state!();
+
+    fn size_hint(&self, theme: &Theme) -> SizeHint {
+        let height = self
+            .children
+            .iter()
+            .filter_map(|x| x.size_hint(theme).height)
+            .max();
+        SizeHint {
+            width: None,
+            height: height.map(|x| x + self.padding * 2),
+        }
+    }
+
+    fn layout(&mut self, bounds: Rect, theme: &Theme) {
+        self.state.bounds = bounds;
+        let inner = bounds.inset(self.padding);
+        let hints = self
+            .children
+            .iter()
+            .map(|x| x.size_hint(theme).width)
+            .collect::<Vec<_>>();
+        let mut x = inner.x;
+        for (child, w) in self
+            .children
+            .iter_mut()
+            .zip(distribute(&hints, inner.w, self.spacing))
+        {
+            child.layout(Rect::new(x, inner.y, w, inner.h), theme);
+            x += w + self.spacing;
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        draw_children(&self.children, canvas, theme);
+    }
+
+    fn mark_dirty(&mut self) {
+        self.children.iter_mut().for_each(|x| x.mark_dirty());
+    }
+
+    fn redraw(&mut self, canvas: &mut Canvas<'_>, theme: &Theme, damage: &mut Vec<Rect>) {
+        redraw_children(&mut self.children, canvas, theme, damage);
+    }
+
+    fn on_gesture(&mut self, gesture: &Gesture) -> Option<M> {
+        dispatch(&mut self.children, gesture)
+    }
+}
@@ -0,0 +1,386 @@
+//! A small retained-mode widget toolkit for native qtfb applications.
+//!
+//! Widgets remember whether they changed since the last frame, and [`Ui::render`] only
+//! redraws and refreshes those areas, which keeps e-ink ghosting and flashing to a minimum.
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use ab_glyph::FontArc;
+use anyhow::{Context, Result};
+use qtfb_client::{
+    gestures::{Gesture, GestureConfig, GestureRecognizer},
+    ClientConnection, UserInput,
+};
+
+mod canvas;
+mod keyboard;
+mod layout;
+mod widgets;
+
+pub use canvas::{line_height, text_width, Canvas};
+pub use keyboard::{Key, Keyboard};
+pub use layout::{Column, Row};
+pub use widgets::{Button, Label, List, Spacer, TextInput};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub const fn right(&self) -> i32 {
+        self.x + self.w
+    }
+
+    pub const fn bottom(&self) -> i32 {
+        self.y + self.h
+    }
+
+    pub const fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.right() && y < self.bottom()
+    }
+
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        (right > x && bottom > y).then(|| Self::new(x, y, right - x, bottom - y))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self::new(
+            x,
+            y,
+            self.right().max(other.right()) - x,
+            self.bottom().max(other.bottom()) - y,
+        )
+    }
+
+    pub const fn inset(&self, by: i32) -> Self {
+        Self::new(self.x + by, self.y + by, self.w - 2 * by, self.h - 2 * by)
+    }
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub font: FontArc,
+    pub font_size: f32,
+    pub foreground: u8,
+    pub background: u8,
+    /// Used for pressed and selected states.
+    pub accent: u8,
+    pub border: i32,
+    pub padding: i32,
+}
+
+impl Theme {
+    const SYSTEM_FONTS: [&'static str; 3] = [
+        "/usr/share/fonts/ttf/noto/NotoSans-Regular.ttf",
+        "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+        "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    ];
+
+    pub fn new(font: FontArc) -> Self {
+        Self {
+            font,
+            font_size: 40.0,
+            foreground: 0,
+            background: 255,
+            accent: 200,
+            border: 3,
+            padding: 16,
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Ok(Self::new(FontArc::try_from_vec(bytes)?))
+    }
+
+    /// Uses the first font found at the usual reMarkable and desktop locations.
+    pub fn from_system() -> Result<Self> {
+        Self::SYSTEM_FONTS
+            .iter()
+            .find_map(|path| Self::from_file(path).ok())
+            .context("No usable system font found")
+    }
+
+    pub fn line_height(&self) -> i32 {
+        line_height(&self.font, self.font_size)
+    }
+}
+
+/// Preferred size of a widget. `None` means the widget takes whatever it is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SizeHint {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+/// Bookkeeping shared by every widget.
+#[derive(Debug, Clone, Copy)]
+pub struct WidgetState {
+    pub bounds: Rect,
+    pub dirty: bool,
+}
+
+impl Default for WidgetState {
+    fn default() -> Self {
+        Self {
+            bounds: Rect::default(),
+            dirty: true,
+        }
+    }
+}
+
+/// Implements the bookkeeping methods of [`Widget`] using a `state: WidgetState` field.
+#[macro_export]
+macro_rules! widget_state {
+    () => {
+        fn bounds(&self) -> $crate::Rect {
+            self.state.bounds
+        }
+
+        fn set_bounds(&mut self, bounds: $crate::Rect) {
+            if self.state.bounds != bounds {
+                self.state.bounds = bounds;
+                self.state.dirty = true;
+            }
+        }
+
+        fn is_dirty(&self) -> bool {
+            self.state.dirty
+        }
+
+        fn set_dirty(&mut self, dirty: bool) {
+            self.state.dirty = dirty;
+        }
+    };
+}
+
+pub trait Widget<M> {
+    fn bounds(&self) -> Rect;
+
+    /// Moves the widget, marking it dirty if the bounds changed.
+    fn set_bounds(&mut self, bounds: Rect);
+
+    fn is_dirty(&self) -> bool;
+
+    fn set_dirty(&mut self, dirty: bool);
+
+    /// Paints the whole widget inside its bounds.
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme);
+
+    fn size_hint(&self, _theme: &Theme) -> SizeHint {
+        SizeHint::default()
+    }
+
+    fn layout(&mut self, bounds: Rect, _theme: &Theme) {
+        self.set_bounds(bounds);
+    }
+
+    fn on_gesture(&mut self, _gesture: &Gesture) -> Option<M> {
+        None
+    }
+
+    fn mark_dirty(&mut self) {
+        self.set_dirty(true);
+    }
+
+    /// Redraws the widget if it changed, recording the refreshed area in `damage`.
+    fn redraw(&mut self, canvas: &mut Canvas<'_>, theme: &Theme, damage: &mut Vec<Rect>) {
+        if self.is_dirty() {
+            let bounds = self.bounds();
+            canvas.fill_rect(bounds, theme.background);
+            self.draw(canvas, theme);
+            damage.push(bounds);
+            self.set_dirty(false);
+        }
+    }
+}
+
+/// Lets the application keep a handle to a widget inside the tree and update it later,
+/// e.g. `let label = shared(Label::new(".."))` and `label.borrow_mut().set_text(..)`.
+pub type Shared<W> = Rc<RefCell<W>>;
+
+pub fn shared<W>(widget: W) -> Shared<W> {
+    Rc::new(RefCell::new(widget))
+}
+
+impl<M, W: Widget<M>> Widget<M> for Shared<W> {
+    fn bounds(&self) -> Rect {
+        self.borrow().bounds()
+    }
+
+    fn set_bounds(&mut self, bounds: Rect) {
+        self.borrow_mut().set_bounds(bounds);
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.borrow().is_dirty()
+    }
+
+    fn set_dirty(&mut self, dirty: bool) {
+        self.borrow_mut().set_dirty(dirty);
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        self.borrow().draw(canvas, theme);
+    }
+
+    fn size_hint(&self, theme: &Theme) -> SizeHint {
+        self.borrow().size_hint(theme)
+    }
+
+    fn layout(&mut self, bounds: Rect, theme: &Theme) {
+        self.borrow_mut().layout(bounds, theme);
+    }
+
+    fn on_gesture(&mut self, gesture: &Gesture) -> Option<M> {
+        self.borrow_mut().on_gesture(gesture)
+    }
+
+    fn mark_dirty(&mut self) {
+        self.borrow_mut().mark_dirty();
+    }
+
+    fn redraw(&mut self, canvas: &mut Canvas<'_>, theme: &Theme, damage: &mut Vec<Rect>) {
+        self.borrow_mut().redraw(canvas, theme, damage);
+    }
+}
+
+/// Where a gesture happened, if it is tied to a point.
+pub fn gesture_position(gesture: &Gesture) -> Option<(i32, i32)> {
+    match *gesture {
+        Gesture::Tap { x, y, .. }
+        | Gesture::LongPress { x, y, .. }
+        | Gesture::Drag { x, y, .. }
+        | Gesture::DragEnd { x, y, .. }
+        | Gesture::TwoFingerTap { x, y } => Some((x, y)),
+        Gesture::Swipe { from, .. } => Some(from),
+        Gesture::Pinch { center, .. } => Some(center),
+        Gesture::TwoFingerSwipe { .. } | Gesture::PinchEnd { .. } | Gesture::SystemDragDown => None,
+    }
+}
+
+/// Owns the widget tree and connects it to a qtfb window.
+pub struct Ui<M> {
+    pub root: Box<dyn Widget<M>>,
+    pub theme: Theme,
+    pub gestures: GestureRecognizer,
+    bounds: Option<Rect>,
+    /// Drags are delivered to the widget the drag started on.
+    drag_target: Option<(i32, i32)>,
+}
+
+impl<M> Ui<M> {
+    pub fn new(root: impl Widget<M> + 'static, theme: Theme) -> Self {
+        Self {
+            root: Box::new(root),
+            theme,
+            gestures: GestureRecognizer::new(GestureConfig::default()),
+            bounds: None,
+            drag_target: None,
+        }
+    }
+
+    /// Forces the next [`Ui::render`] to repaint and refresh the whole screen.
+    pub fn invalidate(&mut self) {
+        self.bounds = None;
+    }
+
+    /// Feeds raw input through the gesture recognizer and returns the resulting messages.
+    pub fn handle_input(&mut self, input: &UserInput) -> Vec<M> {
+        let mut gestures = self.gestures.handle(input);
+        gestures.extend(self.gestures.tick());
+        gestures
+            .iter()
+            .filter_map(|gesture| self.dispatch(gesture))
+            .collect()
+    }
+
+    /// Delivers long presses that fire while the finger is held still.
+    pub fn tick(&mut self) -> Option<M> {
+        let gesture = self.gestures.tick()?;
+        self.dispatch(&gesture)
+    }
+
+    pub fn dispatch(&mut self, gesture: &Gesture) -> Option<M> {
+        let gesture = match *gesture {
+            Gesture::Drag {
+                pointer,
+                dx,
+                dy,
+                from,
+                ..
+            } => {
+                let (x, y) = *self.drag_target.get_or_insert(from);
+                Gesture::Drag {
+                    pointer,
+                    x,
+                    y,
+                    dx,
+                    dy,
+                    from,
+                }
+            }
+            Gesture::DragEnd { pointer, x, y } => {
+                let (x, y) = self.drag_target.take().unwrap_or((x, y));
+                Gesture::DragEnd { pointer, x, y }
+            }
+            // a drag cut short by another gesture must not capture the next one
+            other => {
+                self.drag_target = None;
+                other
+            }
+        };
+        self.root.on_gesture(&gesture)
+    }
+
+    /// Lays out the tree, redraws changed widgets and refreshes only the damaged areas.
+    pub fn render(&mut self, connection: &mut ClientConnection<'_>) -> Result<()> {
+        let (full, damage) = {
+            let mut canvas = Canvas::from_connection(connection)?;
+            let bounds = canvas.bounds();
+            let full = self.bounds != Some(bounds);
+            self.root.layout(bounds, &self.theme);
+            if full {
+                canvas.fill_rect(bounds, self.theme.background);
+                self.root.mark_dirty();
+            }
+            let mut damage = Vec::new();
+            self.root.redraw(&mut canvas, &self.theme, &mut damage);
+            self.bounds = Some(bounds);
+            (full, damage)
+        };
+        if full {
+            connection.send_complete_update()?;
+            return Ok(());
+        }
+        for rect in merge_damage(damage) {
+            connection.send_partial_update(rect.x, rect.y, rect.w, rect.h)?;
+        }
+        Ok(())
+    }
+}
+
+/// Merges overlapping rectangles so each screen area is refreshed once.
+pub fn merge_damage(mut damage: Vec<Rect>) -> Vec<Rect> {
+    let mut merged: Vec<Rect> = Vec::new();
+    while let Some(mut rect) = damage.pop() {
+        while let Some(i) = merged.iter().position(|x| x.intersection(&rect).is_some()) {
+            rect = rect.union(&merged.swap_remove(i));
+        }
+        merged.push(rect);
+    }
+    merged
+}
@@ -0,0 +1,393 @@
+use qtfb_client::gestures::Gesture;
+
+use crate::{text_width, Canvas, Rect, SizeHint, Theme, Widget, WidgetState};
+
+pub struct Label {
+    state: WidgetState,
+    text: String,
+    font_size: Option<f32>,
+}
+
+impl Label {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            state: WidgetState::default(),
+            text: text.into(),
+            font_size: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_font_size(mut self, size: f32) -> Self {
+        self.font_size = Some(size);
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        if text != self.text {
+            self.text = text;
+            self.state.dirty = true;
+        }
+    }
+}
+
+impl<M> Widget<M> for Label {
+    crate::widget_state!();
+
+    fn size_hint(&self, theme: &Theme) -> SizeHint {
+        let size = self.font_size.unwrap_or(theme.font_size);
+        SizeHint {
+            width: None,
+            height: Some(crate::line_height(&theme.font, size) + theme.padding * 2),
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        let bounds = self.state.bounds;
+        canvas.draw_text(
+            &theme.font,
+            self.font_size.unwrap_or(theme.font_size),
+            &self.text,
+            bounds.x + theme.padding,
+            bounds.y + theme.padding,
+            theme.foreground,
+            bounds,
+        );
+    }
+}
+
+pub struct Button<M> {
+    state: WidgetState,
+    label: String,
+    on_press: M,
+    enabled: bool,
+}
+
+impl<M: Clone> Button<M> {
+    pub fn new(label: impl Into<String>, on_press: M) -> Self {
+        Self {
+            state: WidgetState::default(),
+            label: label.into(),
+            on_press,
+            enabled: true,
+        }
+    }
+
+    pub fn set_label(&mut self, label: impl Into<String>) {
+        self.label = label.into();
+        self.state.dirty = true;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.state.dirty = true;
+        }
+    }
+}
+
+impl<M: Clone> Widget<M> for Button<M> {
+    crate::widget_state!();
+
+    fn size_hint(&self, theme: &Theme) -> SizeHint {
+        SizeHint {
+            width: Some(text_width(&theme.font, theme.font_size, &self.label) + theme.padding * 4),
+            height: Some(theme.line_height() + theme.padding * 2),
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        let bounds = self.state.bounds;
+        let color = if self.enabled {
+            theme.foreground
+        } else {
+            theme.accent
+        };
+        canvas.stroke_rect(bounds, color, theme.border);
+        let width = text_width(&theme.font, theme.font_size, &self.label);
+        canvas.draw_text(
+            &theme.font,
+            theme.font_size,
+            &self.label,
+            bounds.x + (bounds.w - width) / 2,
+            bounds.y + (bounds.h - theme.line_height()) / 2,
+            color,
+            bounds.inset(theme.border),
+        );
+    }
+
+    fn on_gesture(&mut self, gesture: &Gesture) -> Option<M> {
+        match *gesture {
+            Gesture::Tap { x, y, .. } if self.enabled && self.state.bounds.contains(x, y) => {
+                Some(self.on_press.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Fixed-size empty space, or flexible space when the size is `None`.
+#[derive(Default)]
+pub struct Spacer {
+    state: WidgetState,
+    size: Option<i32>,
+}
+
+impl Spacer {
+    pub fn new(size: impl Into<Option<i32>>) -> Self {
+        Self {
+            state: WidgetState::default(),
+            size: size.into(),
+        }
+    }
+}
+
+impl<M> Widget<M> for Spacer {
+    crate::widget_state!();
+
+    fn size_hint(&self, _theme: &Theme) -> SizeHint {
+        SizeHint {
+            width: self.size,
+            height: self.size,
+        }
+    }
+
+    fn draw(&self, _canvas: &mut Canvas<'_>, _theme: &Theme) {}
+}
+
+/// A vertically scrolling list of text rows. Scrolled by dragging, selected by tapping.
+pub struct List<M> {
+    state: WidgetState,
+    items: Vec<String>,
+    selected: Option<usize>,
+    scroll: i32,
+    /// Remembered from the last layout, since gestures are handled without a theme.
+    row_height: i32,
+    on_select: fn(usize) -> M,
+}
+
+impl<M> List<M> {
+    pub fn new(items: Vec<String>, on_select: fn(usize) -> M) -> Self {
+        Self {
+            state: WidgetState::default(),
+            items,
+            selected: None,
+            scroll: 0,
+            row_height: 0,
+            on_select,
+        }
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    pub fn set_items(&mut self, items: Vec<String>) {
+        self.items = items;
+        self.selected = None;
+        self.scroll = 0;
+        self.state.dirty = true;
+    }
+
+    pub const fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn select(&mut self, index: Option<usize>) {
+        if self.selected != index {
+            self.selected = index;
+            self.state.dirty = true;
+        }
+    }
+
+    fn row_height(theme: &Theme) -> i32 {
+        theme.line_height() + theme.padding * 2
+    }
+
+    fn max_scroll(&self, row_height: i32) -> i32 {
+        (self.items.len() as i32 * row_height - self.state.bounds.h).max(0)
+    }
+}
+
+impl<M> Widget<M> for List<M> {
+    crate::widget_state!();
+
+    fn layout(&mut self, bounds: Rect, theme: &Theme) {
+        if self.state.bounds != bounds {
+            self.state.bounds = bounds;
+            self.state.dirty = true;
+        }
+        self.row_height = Self::row_height(theme);
+        self.scroll = self.scroll.min(self.max_scroll(self.row_height));
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        let bounds = self.state.bounds;
+        let row_height = Self::row_height(theme);
+        let first = (self.scroll / row_height) as usize;
+        let visible = (bounds.h / row_height + 2) as usize;
+        for (i, item) in self.items.iter().enumerate().skip(first).take(visible) {
+            let y = bounds.y + i as i32 * row_height - self.scroll;
+            let row = Rect::new(bounds.x, y, bounds.w, row_height);
+            let Some(clip) = row.intersection(&bounds) else {
+                continue;
+            };
+            if self.selected == Some(i) {
+                canvas.fill_rect(clip, theme.accent);
+            }
+            canvas.draw_text(
+                &theme.font,
+                theme.font_size,
+                item,
+                row.x + theme.padding,
+                row.y + theme.padding,
+                theme.foreground,
+                clip,
+            );
+            canvas.fill_rect(
+                Rect::new(row.x, row.bottom() - 1, row.w, 1)
+                    .intersection(&bounds)
+                    .unwrap_or_default(),
+                theme.accent,
+            );
+        }
+    }
+
+    fn on_gesture(&mut self, gesture: &Gesture) -> Option<M> {
+        match *gesture {
+            Gesture::Drag { x, y, dy, .. } if self.state.bounds.contains(x, y) => {
+                let scroll = (self.scroll - dy).clamp(0, self.max_scroll(self.row_height));
+                if scroll != self.scroll {
+                    self.scroll = scroll;
+                    self.state.dirty = true;
+                }
+                None
+            }
+            Gesture::Tap { x, y, .. }
+                if self.row_height > 0 && self.state.bounds.contains(x, y) =>
+            {
+                let index = ((y - self.state.bounds.y + self.scroll) / self.row_height) as usize;
+                if index >= self.items.len() {
+                    return None;
+                }
+                self.select(Some(index));
+                Some((self.on_select)(index))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A single-line text field. Feed it keys from a [`crate::Keyboard`] while it is focused.
+pub struct TextInput<M> {
+    state: WidgetState,
+    text: String,
+    placeholder: String,
+    focused: bool,
+    on_focus: Option<M>,
+}
+
+impl<M: Clone> TextInput<M> {
+    pub fn new(placeholder: impl Into<String>) -> Self {
+        Self {
+            state: WidgetState::default(),
+            text: String::new(),
+            placeholder: placeholder.into(),
+            focused: false,
+            on_focus: None,
+        }
+    }
+
+    /// Message emitted when the field is tapped, e.g. to show the keyboard.
+    #[must_use]
+    pub fn on_focus(mut self, message: M) -> Self {
+        self.on_focus = Some(message);
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.state.dirty = true;
+    }
+
+    pub const fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        if self.focused != focused {
+            self.focused = focused;
+            self.state.dirty = true;
+        }
+    }
+
+    /// Applies a key press. Returns true on [`crate::Key::Enter`].
+    pub fn apply(&mut self, key: crate::Key) -> bool {
+        match key {
+            crate::Key::Char(c) => self.text.push(c),
+            crate::Key::Backspace => {
+                self.text.pop();
+            }
+            crate::Key::Enter => return true,
+            crate::Key::Shift => return false,
+        }
+        self.state.dirty = true;
+        false
+    }
+}
+
+impl<M: Clone> Widget<M> for TextInput<M> {
+    crate::widget_state!();
+
+    fn size_hint(&self, theme: &Theme) -> SizeHint {
+        SizeHint {
+            width: None,
+            height: Some(theme.line_height() + theme.padding * 2),
+        }
+    }
+
+    fn draw(&self, canvas: &mut Canvas<'_>, theme: &Theme) {
+        let bounds = self.state.bounds;
+        let border = if self.focused {
+            theme.border * 2
+        } else {
+            theme.border
+        };
+        canvas.stroke_rect(bounds, theme.foreground, border);
+        let (text, color) = if self.text.is_empty() {
+            (&self.placeholder, theme.accent)
+        } else {
+            (&self.text, theme.foreground)
+        };
+        let x = bounds.x + theme.padding;
+        let y = bounds.y + (bounds.h - theme.line_height()) / 2;
+        let inner = bounds.inset(border);
+        canvas.draw_text(&theme.font, theme.font_size, text, x, y, color, inner);
+        if self.focused {
+            let caret = x + text_width(&theme.font, theme.font_size, &self.text);
+            canvas.fill_rect(
+                Rect::new(caret, y, 3, theme.line_height()),
+                theme.foreground,
+            );
+        }
+    }
+
+    fn on_gesture(&mut self, gesture: &Gesture) -> Option<M> {
+        match *gesture {
+            Gesture::Tap { x, y, .. } if self.state.bounds.contains(x, y) => {
+                self.set_focused(true);
+                self.on_focus.clone()
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Conversion of arbitrary images into the raw framebuffer layouts understood by qtfb.
+//!
+//! Enabled with the `image` feature.
+use anyhow::{bail, Context, Result};
+use image::{imageops::FilterType, DynamicImage, GrayImage, Luma, RgbImage};
+
+pub use crate::pixel::bytes_per_pixel;
+use crate::{constants, pixel::rgb565, ClientConnection};
+
+/// How the source image is mapped onto the framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scaling {
+    /// Keep the aspect ratio and letterbox the remaining area with the background.
+    #[default]
+    Fit,
+    /// Keep the aspect ratio and crop whatever overflows the framebuffer.
+    Fill,
+    /// Ignore the aspect ratio.
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    None,
+    #[default]
+    FloydSteinberg,
+    /// 8x8 Bayer matrix. Cheaper and stable between frames, at the cost of a visible pattern.
+    Ordered,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ConvertOptions {
+    pub scaling: Scaling,
+    pub dither: Dither,
+    /// When false, colour is kept and no dithering takes place.
+    pub grayscale: bool,
+    /// Number of gray levels the output is quantized to. e-ink panels show 16.
+    pub levels: u8,
+    /// Gray value used for letterboxing.
+    pub background: u8,
+    pub filter: FilterType,
+}
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        Self {
+            scaling: Scaling::default(),
+            dither: Dither::default(),
+            grayscale: true,
+            levels: 16,
+            background: 255,
+            filter: FilterType::CatmullRom,
+        }
+    }
+}
+
+/// Decodes PNG or JPEG bytes.
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    image::load_from_memory(bytes).context("Failed to decode image")
+}
+
+/// Scales `image` to `width`x`height` according to `options.scaling`.
+pub fn scale(image: &DynamicImage, width: u32, height: u32, options: &ConvertOptions) -> RgbImage {
+    match options.scaling {
+        Scaling::Stretch => image.resize_exact(width, height, options.filter).to_rgb8(),
+        Scaling::Fill => image
+            .resize_to_fill(width, height, options.filter)
+            .to_rgb8(),
+        Scaling::Fit => {
+            let resized = image.resize(width, height, options.filter).to_rgb8();
+            let bg = options.background;
+            let mut canvas = RgbImage::from_pixel(width, height, image::Rgb([bg, bg, bg]));
+            let x = (width - resized.width()) / 2;
+            let y = (height - resized.height()) / 2;
+            image::imageops::replace(&mut canvas, &resized, x.into(), y.into());
+            canvas
+        }
+    }
+}
+
+/// Quantizes a grayscale image in place to `levels` evenly spaced levels.
+pub fn dither(image: &mut GrayImage, levels: u8, dither: Dither) {
+    let levels = levels.max(2);
+    match dither {
+        Dither::None => image
+            .pixels_mut()
+            .for_each(|Luma([v])| *v = quantize(f32::from(*v), levels)),
+        Dither::Ordered => {
+            let step = 255.0 / f32::from(levels - 1);
+            for (x, y, Luma([v])) in image.enumerate_pixels_mut() {
+                let threshold =
+                    f32::from(BAYER_8X8[(y % 8) as usize][(x % 8) as usize]) / 64.0 - 0.5;
+                *v = quantize(f32::from(*v) + threshold * step, levels);
+            }
+        }
+        Dither::FloydSteinberg => {
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            let mut buffer = image
+                .as_raw()
+                .iter()
+                .map(|&x| f32::from(x))
+                .collect::<Vec<_>>();
+            for y in 0..height {
+                for x in 0..width {
+                    let i = y * width + x;
+                    let old = buffer[i];
+                    let new = f32::from(quantize(old, levels));
+                    buffer[i] = new;
+                    let error = old - new;
+                    if x + 1 < width {
+                        buffer[i + 1] += error * 7.0 / 16.0;
+                    }
+                    if y + 1 < height {
+                        if x > 0 {
+                            buffer[i + width - 1] += error * 3.0 / 16.0;
+                        }
+                        buffer[i + width] += error * 5.0 / 16.0;
+                        if x + 1 < width {
+                            buffer[i + width + 1] += error / 16.0;
+                        }
+                    }
+                }
+            }
+            image
+                .iter_mut()
+                .zip(buffer)
+                .for_each(|(dst, v)| *dst = v.clamp(0.0, 255.0) as u8);
+        }
+    }
+}
+
+fn quantize(value: f32, levels: u8) -> u8 {
+    let step = 255.0 / f32::from(levels - 1);
+    ((value.clamp(0.0, 255.0) / step).round() * step).clamp(0.0, 255.0) as u8
+}
+
+#[rustfmt::skip]
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Scales, optionally dithers and encodes `image` into `out` using the `shm_type` layout.
+pub fn convert_into(
+    image: &DynamicImage,
+    shm_type: u8,
+    (width, height): (u32, u32),
+    options: &ConvertOptions,
+    out: &mut [u8],
+) -> Result<()> {
+    let bpp = bytes_per_pixel(shm_type)?;
+    let needed = width as usize * height as usize * bpp;
+    if out.len() < needed {
+        bail!("Output buffer too small: {} < {needed}", out.len());
+    }
+
+    let scaled = scale(image, width, height, options);
+    let rgb = if options.grayscale {
+        let mut gray = DynamicImage::ImageRgb8(scaled).into_luma8();
+        dither(&mut gray, options.levels, options.dither);
+        DynamicImage::ImageLuma8(gray).into_rgb8()
+    } else {
+        scaled
+    };
+
+    for (dst, src) in out.chunks_exact_mut(bpp).zip(rgb.pixels()) {
+        let [r, g, b] = src.0;
+        match shm_type {
+            constants::FBFMT_RMPP_RGB888 => dst.copy_from_slice(&[r, g, b]),
+            constants::FBFMT_RMPP_RGBA8888 => dst.copy_from_slice(&[r, g, b, 255]),
+            _ => {
+                dst.copy_from_slice(&rgb565(r, g, b).to_ne_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like [`convert_into`], allocating the output buffer.
+pub fn convert(
+    image: &DynamicImage,
+    shm_type: u8,
+    size: (u32, u32),
+    options: &ConvertOptions,
+) -> Result<Vec<u8>> {
+    let mut out = vec![0; size.0 as usize * size.1 as usize * bytes_per_pixel(shm_type)?];
+    convert_into(image, shm_type, size, options, &mut out)?;
+    Ok(out)
+}
+
+impl<'a> ClientConnection<'a> {
+    /// Draws `image` over the whole framebuffer. The caller still has to send an update.
+    pub fn draw_image(&mut self, image: &DynamicImage, options: &ConvertOptions) -> Result<()> {
+        let size = (u32::from(self.width()), u32::from(self.height()));
+        let shm_type = self.shm_type();
+        convert_into(image, shm_type, size, options, self.shm)
+    }
+
+    /// Decodes PNG or JPEG bytes and draws them over the whole framebuffer.
+    pub fn draw_encoded_image(&mut self, bytes: &[u8], options: &ConvertOptions) -> Result<()> {
+        self.draw_image(&decode(bytes)?, options)
+    }
+}
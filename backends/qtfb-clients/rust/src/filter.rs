@@ -0,0 +1,171 @@
+//! Palm rejection: drops touch input while the pen is in use, so drawing apps only see the pen.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{constants, UserInput};
+
+/// An area of the framebuffer, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl Region {
+    pub const fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Self { x, y, w, h }
+    }
+
+    pub const fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.w && y < self.y + self.h
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FilterConfig {
+    /// Suppress touches while the pen is pressed or was seen within `pen_timeout`.
+    pub pen_priority: bool,
+    /// How long after the last pen event (pressed or hovering) the pen counts as in proximity.
+    pub pen_timeout: Duration,
+    /// Touches starting in these regions are always dropped.
+    pub reject_regions: Vec<Region>,
+    /// Touches starting in these regions are never dropped, e.g. a toolbar.
+    pub allow_regions: Vec<Region>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            pen_priority: true,
+            pen_timeout: Duration::from_millis(500),
+            reject_regions: Vec::new(),
+            allow_regions: Vec::new(),
+        }
+    }
+}
+
+/// Sits between [`crate::ClientConnection::poll_input`] and the application.
+///
+/// Touches are accepted or rejected as a whole: a touch that started while rejected stays
+/// rejected until it is released, and a touch that was in progress when the pen arrived is
+/// closed with a synthetic `INPUT_TOUCH_RELEASE`, so the output never has dangling contacts.
+#[derive(Debug, Default)]
+pub struct InputFilter {
+    pub config: FilterConfig,
+    pen_down: bool,
+    last_pen_event: Option<Instant>,
+    /// Accepted touches by id.
+    touches: HashMap<i32, Touch>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Touch {
+    position: (i32, i32),
+    /// Started in an allowed region, so the pen does not cancel it.
+    pinned: bool,
+}
+
+impl InputFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn filter(&mut self, input: &UserInput) -> Vec<UserInput> {
+        self.filter_at(input, Instant::now())
+    }
+
+    pub fn pen_active_at(&self, now: Instant) -> bool {
+        self.pen_down
+            || self
+                .last_pen_event
+                .is_some_and(|x| now.duration_since(x) < self.config.pen_timeout)
+    }
+
+    pub fn filter_at(&mut self, input: &UserInput, now: Instant) -> Vec<UserInput> {
+        match input.input_type {
+            constants::INPUT_PEN_PRESS | constants::INPUT_PEN_UPDATE => {
+                if input.input_type == constants::INPUT_PEN_PRESS {
+                    self.pen_down = true;
+                }
+                self.last_pen_event = Some(now);
+                let mut out = if self.config.pen_priority {
+                    self.release_all_touches()
+                } else {
+                    Vec::new()
+                };
+                out.push(*input);
+                out
+            }
+            constants::INPUT_PEN_RELEASE => {
+                self.pen_down = false;
+                self.last_pen_event = Some(now);
+                vec![*input]
+            }
+            constants::INPUT_TOUCH_PRESS => {
+                let pinned = self.in_allowed_region(input);
+                if !pinned && self.should_reject(input, now) {
+                    return Vec::new();
+                }
+                let touch = Touch {
+                    position: (input.x, input.y),
+                    pinned,
+                };
+                self.touches.insert(input.dev_id, touch);
+                vec![*input]
+            }
+            constants::INPUT_TOUCH_UPDATE => {
+                let Some(touch) = self.touches.get_mut(&input.dev_id) else {
+                    return Vec::new();
+                };
+                touch.position = (input.x, input.y);
+                vec![*input]
+            }
+            constants::INPUT_TOUCH_RELEASE => {
+                if self.touches.remove(&input.dev_id).is_some() {
+                    vec![*input]
+                } else {
+                    Vec::new()
+                }
+            }
+            _ => vec![*input],
+        }
+    }
+
+    fn in_allowed_region(&self, input: &UserInput) -> bool {
+        self.config
+            .allow_regions
+            .iter()
+            .any(|r| r.contains(input.x, input.y))
+    }
+
+    fn should_reject(&self, input: &UserInput, now: Instant) -> bool {
+        let in_rejected_region = self
+            .config
+            .reject_regions
+            .iter()
+            .any(|r| r.contains(input.x, input.y));
+        in_rejected_region || (self.config.pen_priority && self.pen_active_at(now))
+    }
+
+    fn release_all_touches(&mut self) -> Vec<UserInput> {
+        let released = self
+            .touches
+            .iter()
+            .filter(|(_, touch)| !touch.pinned)
+            .map(|(&dev_id, touch)| UserInput {
+                input_type: constants::INPUT_TOUCH_RELEASE,
+                dev_id,
+                x: touch.position.0,
+                y: touch.position.1,
+                d: 0,
+            })
+            .collect::<Vec<_>>();
+        self.touches.retain(|_, touch| touch.pinned);
+        released
+    }
+}
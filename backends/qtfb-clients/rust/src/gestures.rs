@@ -0,0 +1,442 @@
+//! Turns the raw touch and pen stream of [`UserInput`] into high-level gestures.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::{constants, UserInput};
+
+/// AppLoad closes the window when a touch starts above this line...
+pub const SYSTEM_DRAG_START_MAX_Y: i32 = 100;
+/// ...and is released strictly between the two lines below.
+pub const SYSTEM_DRAG_END_MIN_Y: i32 = 100;
+pub const SYSTEM_DRAG_END_MAX_Y: i32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pointer {
+    Touch,
+    Pen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    fn from_delta(dx: i32, dy: i32) -> Self {
+        if dx.abs() >= dy.abs() {
+            if dx < 0 {
+                Self::Left
+            } else {
+                Self::Right
+            }
+        } else if dy < 0 {
+            Self::Up
+        } else {
+            Self::Down
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Tap {
+        pointer: Pointer,
+        x: i32,
+        y: i32,
+    },
+    LongPress {
+        pointer: Pointer,
+        x: i32,
+        y: i32,
+    },
+    /// Emitted on every update once a single contact moved past the tap threshold.
+    Drag {
+        pointer: Pointer,
+        x: i32,
+        y: i32,
+        /// Movement since the previous update.
+        dx: i32,
+        dy: i32,
+        /// Where the contact touched down.
+        from: (i32, i32),
+    },
+    DragEnd {
+        pointer: Pointer,
+        x: i32,
+        y: i32,
+    },
+    Swipe {
+        pointer: Pointer,
+        direction: Direction,
+        from: (i32, i32),
+        to: (i32, i32),
+    },
+    TwoFingerTap {
+        x: i32,
+        y: i32,
+    },
+    TwoFingerSwipe {
+        direction: Direction,
+    },
+    /// `scale` is relative to the finger distance when the pinch started.
+    Pinch {
+        center: (i32, i32),
+        scale: f32,
+    },
+    PinchEnd {
+        scale: f32,
+    },
+    /// A touch matched AppLoad's drag-down-to-close gesture. The window is about to close.
+    SystemDragDown,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GestureConfig {
+    /// Maximum movement, in pixels, for a contact to still count as a tap or long press.
+    pub tap_max_distance: i32,
+    pub tap_max_duration: Duration,
+    pub long_press_duration: Duration,
+    pub swipe_min_distance: i32,
+    pub swipe_max_duration: Duration,
+    /// Change in finger distance, in pixels, before two contacts are considered a pinch.
+    pub pinch_min_distance: i32,
+    /// Ignore touches starting in AppLoad's drag-down-to-close area so they don't
+    /// trigger application gestures.
+    pub reserve_system_area: bool,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            tap_max_distance: 30,
+            tap_max_duration: Duration::from_millis(300),
+            long_press_duration: Duration::from_millis(600),
+            swipe_min_distance: 150,
+            swipe_max_duration: Duration::from_millis(800),
+            pinch_min_distance: 40,
+            reserve_system_area: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Contact {
+    pointer: Pointer,
+    start: (i32, i32),
+    position: (i32, i32),
+    started_at: Instant,
+    moved: bool,
+    long_pressed: bool,
+    system: bool,
+}
+
+impl Contact {
+    fn new(pointer: Pointer, x: i32, y: i32, now: Instant) -> Self {
+        Self {
+            pointer,
+            start: (x, y),
+            position: (x, y),
+            started_at: now,
+            moved: false,
+            long_pressed: false,
+            system: false,
+        }
+    }
+
+    fn delta(&self) -> (i32, i32) {
+        (
+            self.position.0 - self.start.0,
+            self.position.1 - self.start.1,
+        )
+    }
+
+    fn distance(&self) -> i32 {
+        let (dx, dy) = self.delta();
+        f64::from(dx).hypot(f64::from(dy)) as i32
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MultiTouch {
+    start_distance: f32,
+    started_at: Instant,
+    pinching: bool,
+    scale: f32,
+}
+
+/// Feed every [`UserInput`] to [`GestureRecognizer::handle`], and call
+/// [`GestureRecognizer::tick`] periodically so long presses fire while the finger is still down.
+#[derive(Debug, Default)]
+pub struct GestureRecognizer {
+    pub config: GestureConfig,
+    touches: HashMap<i32, Contact>,
+    pen: Option<Contact>,
+    multi: Option<MultiTouch>,
+    /// Set once a multi-touch gesture ended until every finger is lifted.
+    consumed: bool,
+}
+
+impl GestureRecognizer {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn handle(&mut self, input: &UserInput) -> Vec<Gesture> {
+        self.handle_at(input, Instant::now())
+    }
+
+    pub fn tick(&mut self) -> Option<Gesture> {
+        self.tick_at(Instant::now())
+    }
+
+    pub fn handle_at(&mut self, input: &UserInput, now: Instant) -> Vec<Gesture> {
+        let UserInput {
+            input_type,
+            dev_id,
+            x,
+            y,
+            ..
+        } = *input;
+        let mut gestures = Vec::new();
+        match input_type {
+            constants::INPUT_PEN_PRESS => {
+                self.pen = Some(Contact::new(Pointer::Pen, x, y, now));
+            }
+            constants::INPUT_PEN_UPDATE => {
+                if let Some(mut pen) = self.pen {
+                    self.update_single(&mut pen, x, y, &mut gestures);
+                    self.pen = Some(pen);
+                }
+            }
+            constants::INPUT_PEN_RELEASE => {
+                if let Some(mut pen) = self.pen.take() {
+                    pen.position = (x, y);
+                    self.release_single(&pen, now, &mut gestures);
+                }
+            }
+            constants::INPUT_TOUCH_PRESS => {
+                let mut contact = Contact::new(Pointer::Touch, x, y, now);
+                contact.system = self.config.reserve_system_area && y < SYSTEM_DRAG_START_MAX_Y;
+                self.touches.insert(dev_id, contact);
+                if self.active_touches().count() == 2 && self.multi.is_none() && !self.consumed {
+                    // the first finger may have been dragging, which ends here
+                    if let Some(dragging) = self.touches.iter().find_map(|(id, x)| {
+                        (*id != dev_id && !x.system && x.moved && !x.long_pressed).then_some(x)
+                    }) {
+                        gestures.push(Gesture::DragEnd {
+                            pointer: dragging.pointer,
+                            x: dragging.position.0,
+                            y: dragging.position.1,
+                        });
+                    }
+                    self.multi = Some(MultiTouch {
+                        start_distance: self.finger_distance().max(1.0),
+                        started_at: now,
+                        pinching: false,
+                        scale: 1.0,
+                    });
+                }
+            }
+            constants::INPUT_TOUCH_UPDATE => {
+                let Some(mut contact) = self.touches.get(&dev_id).copied() else {
+                    return gestures;
+                };
+                if contact.system {
+                    contact.position = (x, y);
+                } else if self.multi.is_some() || self.consumed {
+                    contact.position = (x, y);
+                    contact.moved |= contact.distance() > self.config.tap_max_distance;
+                } else {
+                    self.update_single(&mut contact, x, y, &mut gestures);
+                }
+                self.touches.insert(dev_id, contact);
+                self.update_multi(&mut gestures);
+            }
+            constants::INPUT_TOUCH_RELEASE => {
+                let Some(mut contact) = self.touches.remove(&dev_id) else {
+                    return gestures;
+                };
+                contact.position = (x, y);
+                if contact.system {
+                    if y > SYSTEM_DRAG_END_MIN_Y && y < SYSTEM_DRAG_END_MAX_Y {
+                        gestures.push(Gesture::SystemDragDown);
+                    }
+                } else if let Some(multi) = self.multi.take() {
+                    self.release_multi(&contact, multi, now, &mut gestures);
+                    self.consumed = true;
+                } else if !self.consumed {
+                    self.release_single(&contact, now, &mut gestures);
+                }
+                if self.touches.is_empty() {
+                    self.consumed = false;
+                }
+            }
+            _ => {}
+        }
+        gestures
+    }
+
+    pub fn tick_at(&mut self, now: Instant) -> Option<Gesture> {
+        if self.multi.is_some() || self.consumed {
+            return None;
+        }
+        let long_press_duration = self.config.long_press_duration;
+        let mut contacts = self.touches.values_mut().filter(|x| !x.system);
+        let contact = match (self.pen.as_mut(), contacts.next(), contacts.next()) {
+            (Some(pen), _, _) => pen,
+            (None, Some(touch), None) => touch,
+            _ => return None,
+        };
+        if contact.moved
+            || contact.long_pressed
+            || now.duration_since(contact.started_at) < long_press_duration
+        {
+            return None;
+        }
+        contact.long_pressed = true;
+        Some(Gesture::LongPress {
+            pointer: contact.pointer,
+            x: contact.position.0,
+            y: contact.position.1,
+        })
+    }
+
+    fn active_touches(&self) -> impl Iterator<Item = &Contact> {
+        self.touches.values().filter(|x| !x.system)
+    }
+
+    fn finger_distance(&self) -> f32 {
+        let mut touches = self.active_touches();
+        match (touches.next(), touches.next()) {
+            (Some(a), Some(b)) => {
+                let dx = (a.position.0 - b.position.0) as f32;
+                let dy = (a.position.1 - b.position.1) as f32;
+                dx.hypot(dy)
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn finger_center(&self) -> (i32, i32) {
+        let mut touches = self.active_touches();
+        match (touches.next(), touches.next()) {
+            (Some(a), Some(b)) => (
+                (a.position.0 + b.position.0) / 2,
+                (a.position.1 + b.position.1) / 2,
+            ),
+            _ => (0, 0),
+        }
+    }
+
+    fn update_single(&self, contact: &mut Contact, x: i32, y: i32, gestures: &mut Vec<Gesture>) {
+        let (dx, dy) = (x - contact.position.0, y - contact.position.1);
+        contact.position = (x, y);
+        if !contact.moved && contact.distance() > self.config.tap_max_distance {
+            contact.moved = true;
+        }
+        if contact.moved && !contact.long_pressed {
+            gestures.push(Gesture::Drag {
+                pointer: contact.pointer,
+                x,
+                y,
+                dx,
+                dy,
+                from: contact.start,
+            });
+        }
+    }
+
+    fn release_single(&self, contact: &Contact, now: Instant, gestures: &mut Vec<Gesture>) {
+        if contact.long_pressed {
+            return;
+        }
+        let (x, y) = contact.position;
+        let held = now.duration_since(contact.started_at);
+        if !contact.moved && contact.distance() <= self.config.tap_max_distance {
+            if held <= self.config.tap_max_duration {
+                gestures.push(Gesture::Tap {
+                    pointer: contact.pointer,
+                    x,
+                    y,
+                });
+            }
+            return;
+        }
+        gestures.push(Gesture::DragEnd {
+            pointer: contact.pointer,
+            x,
+            y,
+        });
+        if contact.distance() >= self.config.swipe_min_distance
+            && held <= self.config.swipe_max_duration
+        {
+            let (dx, dy) = contact.delta();
+            gestures.push(Gesture::Swipe {
+                pointer: contact.pointer,
+                direction: Direction::from_delta(dx, dy),
+                from: contact.start,
+                to: contact.position,
+            });
+        }
+    }
+
+    fn update_multi(&mut self, gestures: &mut Vec<Gesture>) {
+        let distance = self.finger_distance();
+        let center = self.finger_center();
+        let pinch_min_distance = self.config.pinch_min_distance as f32;
+        let Some(multi) = self.multi.as_mut() else {
+            return;
+        };
+        if !multi.pinching && (distance - multi.start_distance).abs() > pinch_min_distance {
+            multi.pinching = true;
+        }
+        if multi.pinching {
+            multi.scale = distance / multi.start_distance;
+            gestures.push(Gesture::Pinch {
+                center,
+                scale: multi.scale,
+            });
+        }
+    }
+
+    fn release_multi(
+        &self,
+        released: &Contact,
+        multi: MultiTouch,
+        now: Instant,
+        gestures: &mut Vec<Gesture>,
+    ) {
+        if multi.pinching {
+            gestures.push(Gesture::PinchEnd { scale: multi.scale });
+            return;
+        }
+        let Some(other) = self.active_touches().next() else {
+            return;
+        };
+        if !released.moved && !other.moved {
+            if now.duration_since(multi.started_at) <= self.config.tap_max_duration {
+                gestures.push(Gesture::TwoFingerTap {
+                    x: (other.position.0 + released.position.0) / 2,
+                    y: (other.position.1 + released.position.1) / 2,
+                });
+            }
+            return;
+        }
+        let (dx, dy) = released.delta();
+        let (odx, ody) = other.delta();
+        let direction = Direction::from_delta(dx, dy);
+        if released.distance() >= self.config.swipe_min_distance
+            && other.distance() >= self.config.swipe_min_distance
+            && direction == Direction::from_delta(odx, ody)
+            && now.duration_since(multi.started_at) <= self.config.swipe_max_duration
+        {
+            gestures.push(Gesture::TwoFingerSwipe { direction });
+        }
+    }
+}
@@ -12,17 +12,41 @@ use std::os::unix::io::{AsRawFd, RawFd};
 use std::ptr;
 use std::slice;
 
+#[cfg(feature = "image")]
+pub mod convert;
+pub mod filter;
+pub mod gestures;
+pub mod pixel;
+
 pub mod constants {
     pub const DEFAULT_SCENE: u32 = 245209899;
     pub const SOCKET_PATH: &str = "/tmp/qtfb.sock";
     pub const MESSAGE_INITIALIZE: u8 = 0;
     pub const MESSAGE_UPDATE: u8 = 1;
     pub const MESSAGE_CUSTOM_INITIALIZE: u8 = 2;
+    pub const MESSAGE_TERMINATE: u8 = 3;
+    pub const MESSAGE_USERINPUT: u8 = 4;
     pub const UPDATE_ALL: i32 = 0;
     pub const UPDATE_PARTIAL: i32 = 1;
     pub const FBFMT_RM2FB: u8 = 0;
     pub const FBFMT_RMPP_RGB888: u8 = 1;
     pub const FBFMT_RMPP_RGBA8888: u8 = 2;
+    pub const FBFMT_RMPP_RGB565: u8 = 3;
+    pub const RM2_WIDTH: u16 = 1404;
+    pub const RM2_HEIGHT: u16 = 1872;
+    pub const RMPP_WIDTH: u16 = 1620;
+    pub const RMPP_HEIGHT: u16 = 2160;
+    pub const INPUT_TOUCH_PRESS: i32 = 0x10;
+    pub const INPUT_TOUCH_RELEASE: i32 = 0x11;
+    pub const INPUT_TOUCH_UPDATE: i32 = 0x12;
+    pub const INPUT_PEN_PRESS: i32 = 0x20;
+    pub const INPUT_PEN_RELEASE: i32 = 0x21;
+    pub const INPUT_PEN_UPDATE: i32 = 0x22;
+    pub const INPUT_BTN_PRESS: i32 = 0x30;
+    pub const INPUT_BTN_RELEASE: i32 = 0x31;
+    pub const INPUT_BTN_X_LEFT: i32 = 0;
+    pub const INPUT_BTN_X_HOME: i32 = 1;
+    pub const INPUT_BTN_X_RIGHT: i32 = 2;
 
     pub type FBKey = u32;
 }
@@ -52,6 +76,18 @@ struct InitMessageResponseContents {
     shm_size: usize,
 }
 
+/// Input forwarded by AppLoad. `x` and `y` are in framebuffer pixels; for buttons `x` holds
+/// the `INPUT_BTN_X_*` key. `d` is the pen pressure in percent, `dev_id` the touch point id.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UserInput {
+    pub input_type: i32,
+    pub dev_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub d: i32,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct UpdateRegionMessageContents {
@@ -75,15 +111,24 @@ struct ClientMessage {
     contents: ClientMessageContents,
 }
 
+#[repr(C)]
+union ServerMessageContents {
+    init: InitMessageResponseContents,
+    user_input: UserInput,
+}
+
 #[repr(C)]
 struct ServerMessage {
     msg_type: u8,
-    init: InitMessageResponseContents,
+    contents: ServerMessageContents,
 }
 
 pub struct ClientConnection<'a> {
     fd: RawFd,
     pub shm: &'a mut [u8],
+    shm_type: u8,
+    width: u16,
+    height: u16,
 }
 
 impl<'a> ClientConnection<'a> {
@@ -104,7 +149,10 @@ impl<'a> ClientConnection<'a> {
         let socket_path = CString::new(constants::SOCKET_PATH).unwrap();
         let path_bytes = socket_path.as_bytes_with_nul();
         addr.sun_path[..path_bytes.len()].copy_from_slice(unsafe {
-            std::slice::from_raw_parts(transmute(path_bytes.as_ptr()), path_bytes.len())
+            std::slice::from_raw_parts(
+                transmute::<*const u8, *const libc::c_char>(path_bytes.as_ptr()),
+                path_bytes.len(),
+            )
         });
 
         let connect_res = unsafe {
@@ -158,9 +206,11 @@ impl<'a> ClientConnection<'a> {
 
         let mut server_message = ServerMessage {
             msg_type: 0,
-            init: InitMessageResponseContents {
-                shm_key_defined: 0,
-                shm_size: 0,
+            contents: ServerMessageContents {
+                init: InitMessageResponseContents {
+                    shm_key_defined: 0,
+                    shm_size: 0,
+                },
             },
         };
 
@@ -177,13 +227,15 @@ impl<'a> ClientConnection<'a> {
             return Err(Error::new(io::Error::last_os_error()));
         }
 
-        let shm_name = format!("/dev/shm/qtfb_{}", server_message.init.shm_key_defined);
+        let init = unsafe { server_message.contents.init };
+
+        let shm_name = format!("/dev/shm/qtfb_{}", init.shm_key_defined);
         let shm_fd = OpenOptions::new().read(true).write(true).open(&shm_name)?;
 
         let shm_ptr = unsafe {
             mmap(
                 ptr::null_mut(),
-                server_message.init.shm_size,
+                init.shm_size,
                 PROT_READ | PROT_WRITE,
                 MAP_SHARED,
                 shm_fd.as_raw_fd(),
@@ -195,10 +247,32 @@ impl<'a> ClientConnection<'a> {
             return Err(Error::new(io::Error::last_os_error()));
         }
 
-        let shm =
-            unsafe { slice::from_raw_parts_mut(shm_ptr as *mut u8, server_message.init.shm_size) };
+        let shm = unsafe { slice::from_raw_parts_mut(shm_ptr as *mut u8, init.shm_size) };
+
+        let (width, height) = custom_resolution.unwrap_or(match shm_type {
+            constants::FBFMT_RM2FB => (constants::RM2_WIDTH, constants::RM2_HEIGHT),
+            _ => (constants::RMPP_WIDTH, constants::RMPP_HEIGHT),
+        });
+
+        Ok(Self {
+            fd,
+            shm,
+            shm_type,
+            width,
+            height,
+        })
+    }
+
+    pub fn shm_type(&self) -> u8 {
+        self.shm_type
+    }
 
-        Ok(Self { fd, shm })
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
     }
 
     pub fn send_complete_update(&self) -> io::Result<()> {
@@ -235,6 +309,55 @@ impl<'a> ClientConnection<'a> {
         self.send_message(&update_message)
     }
 
+    /// Returns the next input event without blocking, or `None` if nothing is queued.
+    pub fn poll_input(&self) -> io::Result<Option<UserInput>> {
+        self.recv_input(libc::MSG_DONTWAIT)
+    }
+
+    /// Blocks until the next input event arrives.
+    pub fn wait_input(&self) -> io::Result<UserInput> {
+        loop {
+            if let Some(input) = self.recv_input(0)? {
+                return Ok(input);
+            }
+        }
+    }
+
+    fn recv_input(&self, flags: i32) -> io::Result<Option<UserInput>> {
+        loop {
+            let mut server_message = ServerMessage {
+                msg_type: 0,
+                contents: ServerMessageContents {
+                    user_input: UserInput::default(),
+                },
+            };
+
+            let res = unsafe {
+                libc::recv(
+                    self.fd,
+                    &mut server_message as *mut _ as *mut c_void,
+                    mem::size_of::<ServerMessage>(),
+                    flags,
+                )
+            };
+
+            if res == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(None);
+                }
+                return Err(err);
+            }
+            if res == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            if server_message.msg_type == constants::MESSAGE_USERINPUT {
+                return Ok(Some(unsafe { server_message.contents.user_input }));
+            }
+        }
+    }
+
     fn send_message(&self, msg: &ClientMessage) -> io::Result<()> {
         let res = unsafe {
             libc::send(
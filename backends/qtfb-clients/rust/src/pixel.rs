@@ -0,0 +1,18 @@
+//! Pixel layouts of the `FBFMT_*` framebuffer formats.
+use anyhow::{bail, Result};
+
+use crate::constants;
+
+pub fn bytes_per_pixel(shm_type: u8) -> Result<usize> {
+    Ok(match shm_type {
+        constants::FBFMT_RM2FB | constants::FBFMT_RMPP_RGB565 => 2,
+        constants::FBFMT_RMPP_RGB888 => 3,
+        constants::FBFMT_RMPP_RGBA8888 => 4,
+        x => bail!("Unknown framebuffer format {x}"),
+    })
+}
+
+/// Packs a colour into the 16-bit layout of `FBFMT_RM2FB` and `FBFMT_RMPP_RGB565`.
+pub const fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+}
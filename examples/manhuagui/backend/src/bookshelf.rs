@@ -1,13 +1,18 @@
 use std::{collections::HashMap, env, path::PathBuf};
 
-use backend::{Backend, MangaBackend, SManga, epub::Epub};
+use backend::Source;
 use serde::{Deserialize, Serialize};
 use smol::fs;
 
 use crate::manga_reader::MangaReader;
 
-#[derive(Clone)]
-pub struct BookShelf(HashMap<BookShelfKey, MangaReader>);
+#[derive(Clone, Default)]
+pub struct BookShelf {
+    manga: HashMap<BookShelfKey, MangaReader>,
+    /// Saved entries whose source can't be built, e.g. a removed declarative source. They are
+    /// written back unchanged so the manga return with their source.
+    unloaded: Vec<serde_json::Value>,
+}
 
 #[derive(Clone, Eq, Hash, PartialEq, Deserialize, Serialize, Debug, Default)]
 pub struct BookShelfKey {
@@ -16,27 +21,21 @@ pub struct BookShelfKey {
 }
 
 impl BookShelfKey {
-    fn from_manga(manga: &MangaReader) -> Self {
-        let id = match manga.details.url.clone() {
-            backend::ImageUrl::Web(x) => x,
-            backend::ImageUrl::LocalEpub {
-                epub_path: path, ..
-            } => {
-                let id_path = path.parent().unwrap().parent().unwrap();
-                id_path.to_string_lossy().to_string()
-            }
-        };
+    pub fn from_manga(manga: &MangaReader) -> Self {
         Self {
             backend_id: manga.api.id(),
-            manga_distinguisher: id,
+            manga_distinguisher: manga.details.url.get_distinguisher(),
         }
     }
-    pub fn new(id: &Backend, manga_url: String) -> Self {
+    pub fn new(id: &Source, manga_url: String) -> Self {
         Self {
             backend_id: id.id(),
             manga_distinguisher: manga_url,
         }
     }
+    pub fn backend_id(&self) -> &str {
+        &self.backend_id
+    }
 }
 
 impl BookShelf {
@@ -45,15 +44,39 @@ impl BookShelf {
 
         if let Some(path) = path.filter(|x| x.exists()) {
             let s = std::fs::read_to_string(&path)?;
-            let x: Vec<(BookShelfKey, MangaReader)> = serde_json::from_str(&s)?;
-            let mut x: HashMap<BookShelfKey, MangaReader> = x.into_iter().collect();
+            let entries: Vec<serde_json::Value> = serde_json::from_str(&s)?;
+            let mut x = HashMap::new();
+            let mut unloaded = Vec::new();
+            for entry in entries {
+                match serde_json::from_value::<(BookShelfKey, MangaReader)>(entry.clone()) {
+                    // keys are rebuilt since older bookshelves used type names as backend ids
+                    Ok((_, manga)) => {
+                        x.insert(BookShelfKey::from_manga(&manga), manga);
+                    }
+                    Err(err) => {
+                        println!("skipping a bookshelf entry: {err}");
+                        unloaded.push(entry);
+                    }
+                }
+            }
             // destroys pages because we need to "re-move" the files from within the epub to image
             // x.iter_mut()
             //     .filter(|(key, _)| key.backend_id == Epub::default().id())
             //     .for_each(|x| x.1.pages = HashMap::new());
-            Ok(Self(x))
+            Ok(Self { manga: x, unloaded })
         } else {
-            Ok(Self(HashMap::new()))
+            Ok(Self::default())
+        }
+    }
+
+    /// Renames a bookshelf that can't be read, so starting over doesn't overwrite it.
+    pub fn set_aside() {
+        if let Some(path) = Self::path().filter(|x| x.exists()) {
+            let backup = path.with_extension("json.broken");
+            match std::fs::rename(&path, &backup) {
+                Ok(()) => println!("moved the bookshelf to {}", backup.display()),
+                Err(err) => println!("can't move the bookshelf aside: {err}"),
+            }
         }
     }
 
@@ -69,13 +92,18 @@ impl BookShelf {
 
     pub async fn insert(&mut self, manga: MangaReader) -> anyhow::Result<()> {
         let key = BookShelfKey::from_manga(&manga);
-        self.0.insert(key, manga);
+        self.manga.insert(key, manga);
         self.save().await?;
         Ok(())
     }
     pub async fn save(&self) -> anyhow::Result<()> {
         if let Some(path) = Self::path() {
-            let result = self.0.iter().collect::<Vec<_>>();
+            let mut result = self
+                .manga
+                .iter()
+                .map(serde_json::to_value)
+                .collect::<Result<Vec<_>, _>>()?;
+            result.extend(self.unloaded.iter().cloned());
             let contents = serde_json::to_string(&result)?;
             fs::write(path, contents).await?;
         }
@@ -88,11 +116,32 @@ impl BookShelf {
         manga: &MangaReader,
     ) -> anyhow::Result<()> {
         let key = BookShelfKey::from_manga(manga);
-        f(self.0.get_mut(&key));
+        f(self.manga.get_mut(&key));
         self.save().await
     }
 
+    /// Changes the manga of `key`, if it is still on the bookshelf.
+    pub async fn update(
+        &mut self,
+        key: &BookShelfKey,
+        f: impl FnOnce(&mut MangaReader),
+    ) -> anyhow::Result<()> {
+        let Some(manga) = self.manga.get_mut(key) else {
+            return Ok(());
+        };
+        f(manga);
+        self.save().await
+    }
+
+    /// Points every manga of the same source at a rebuilt instance of it.
+    pub fn replace_source(&mut self, source: &Source) {
+        self.manga
+            .values_mut()
+            .filter(|manga| manga.api.id() == source.id())
+            .for_each(|manga| manga.api = source.clone());
+    }
+
     pub const fn bookshelf(&self) -> &HashMap<BookShelfKey, MangaReader> {
-        &self.0
+        &self.manga
     }
 }
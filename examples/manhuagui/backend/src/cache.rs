@@ -0,0 +1,164 @@
+//! An on-disk cache of the pages sources read manga details, chapter lists and page lists from.
+//!
+//! A response younger than the TTL of its [`CacheKind`] is answered from disk. An older one is
+//! revalidated with `If-None-Match` and `If-Modified-Since` when the server sent an `ETag` or
+//! `Last-Modified`, so an unchanged page only costs a `304 Not Modified`. When the network is
+//! down or the server fails, the stale response is served instead, which keeps the bookshelf
+//! readable offline.
+//!
+//! Entries are stored per source in the format of the [`http`] recordings, their age is the
+//! modification time of the file. The cache is off until [`set_dir`] is called.
+use std::{
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::{
+    Method, RequestBuilder, Response, StatusCode,
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+
+use crate::{
+    SourceError,
+    http::{self, Policy, SavedResponse},
+};
+
+/// Gives up early when there is a stale response to fall back on, so reading offline does not
+/// wait for every retry.
+const REVALIDATE_POLICY: Policy = Policy {
+    retries: 1,
+    timeout: Duration::from_secs(10),
+    ..Policy::DEFAULT
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheKind {
+    /// The page [`crate::MangaBackend::search_by_id`] reads.
+    Details,
+    Chapters,
+    Pages,
+}
+
+impl CacheKind {
+    /// How long a response is used without asking the server.
+    pub const fn ttl(self) -> Duration {
+        match self {
+            Self::Details => Duration::from_secs(24 * 60 * 60),
+            // new chapters should show up the same hour
+            Self::Chapters => Duration::from_secs(60 * 60),
+            // the images of a chapter rarely change, but some sites sign their urls for a while
+            Self::Pages => Duration::from_secs(6 * 60 * 60),
+        }
+    }
+}
+
+static DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// `~/.cache/mangarr/http`.
+pub fn default_dir() -> Option<PathBuf> {
+    #[allow(deprecated)]
+    Some(std::env::home_dir()?.join(".cache/mangarr/http"))
+}
+
+/// Caches responses in `dir`, or stops caching with `None`.
+pub fn set_dir(dir: Option<PathBuf>) {
+    *DIR.write().unwrap_or_else(|x| x.into_inner()) = dir;
+}
+
+fn dir() -> Option<PathBuf> {
+    DIR.read().unwrap_or_else(|x| x.into_inner()).clone()
+}
+
+fn source_dir(dir: &Path, source: &str) -> PathBuf {
+    dir.join(source.replace(|x: char| !x.is_ascii_alphanumeric() && x != '-', "_"))
+}
+
+fn entry_path(dir: &Path, source: &str, url: &str) -> PathBuf {
+    source_dir(dir, source).join(format!("{:016x}.http", http::stable_hash(url)))
+}
+
+/// A file `source` keeps next to its responses, for state that is cheap to lose.
+pub fn source_file(source: &str, name: &str) -> Option<PathBuf> {
+    Some(source_dir(&dir()?, source).join(name))
+}
+
+fn age(path: &Path) -> Option<Duration> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.elapsed().unwrap_or_default())
+}
+
+/// Restarts the TTL of an entry the server confirmed is unchanged.
+fn touch(path: &Path) {
+    let touched = std::fs::File::options()
+        .append(true)
+        .open(path)
+        .and_then(|x| x.set_modified(SystemTime::now()));
+    if let Err(err) = touched {
+        println!("can't refresh the cached {}: {err}", path.display());
+    }
+}
+
+/// Saves a successful response, a failure to save only costs the next request.
+async fn store(path: &Path, url: &str, response: Response) -> anyhow::Result<Response> {
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+    let saved = SavedResponse::read(response).await?;
+    if let Err(err) = saved.save(path, &Method::GET, url).await {
+        println!("can't cache {url}: {err:#}");
+    }
+    saved.response()
+}
+
+/// Sends `request` of `source` through the cache as the module describes. Requests other than
+/// `GET` and every request while the cache is off go straight to [`http::send`].
+pub async fn send(
+    source: &str,
+    kind: CacheKind,
+    request: RequestBuilder,
+) -> anyhow::Result<Response> {
+    let built = request.try_clone().and_then(|x| x.build().ok());
+    let (Some(dir), Some(built)) = (dir(), built) else {
+        return http::send(request).await;
+    };
+    if built.method() != Method::GET {
+        return http::send(request).await;
+    }
+    let url = built.url().to_string();
+    let path = entry_path(&dir, source, &url);
+    let Some((cached, age)) = SavedResponse::load(&path).ok().zip(age(&path)) else {
+        let response = http::send(request).await?;
+        return store(&path, &url, response).await;
+    };
+    if age < kind.ttl() {
+        return cached.response();
+    }
+
+    let mut request = request;
+    if let Some(etag) = cached.headers.get(ETAG) {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(modified) = cached.headers.get(LAST_MODIFIED) {
+        request = request.header(IF_MODIFIED_SINCE, modified);
+    }
+    match http::send_with(request, &REVALIDATE_POLICY).await {
+        Ok(response) if response.status() == StatusCode::NOT_MODIFIED => {
+            touch(&path);
+            cached.response()
+        }
+        Ok(response) if response.status().is_server_error() => {
+            println!(
+                "serving the cached {url}, the server answered {}",
+                response.status()
+            );
+            cached.response()
+        }
+        Ok(response) => store(&path, &url, response).await,
+        Err(err) if SourceError::from(&err).is_transient() => {
+            println!("serving the cached {url}: {err:#}");
+            cached.response()
+        }
+        Err(err) => Err(err),
+    }
+}
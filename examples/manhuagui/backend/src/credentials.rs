@@ -0,0 +1,88 @@
+//! Cookies and user agents that sources need to get past bot protection. They are entered by
+//! the user and persisted in `~/.config/mangarr/credentials.json`.
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Credentials {
+    #[serde(default)]
+    pub cookies: BTreeMap<String, String>,
+    /// Must match the browser the cookies were taken from, Cloudflare checks both.
+    pub user_agent: Option<String>,
+}
+
+impl Credentials {
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty() && self.user_agent.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CredentialStore(BTreeMap<String, Credentials>);
+
+impl CredentialStore {
+    fn path() -> Option<PathBuf> {
+        #[allow(deprecated)]
+        Some(std::env::home_dir()?.join(".config/mangarr/credentials.json"))
+    }
+
+    pub fn load() -> Self {
+        let Some(path) = Self::path().filter(|x| x.exists()) else {
+            return Self::default();
+        };
+        match std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|x| Ok(serde_json::from_str(&x)?))
+        {
+            Ok(store) => store,
+            Err(err) => {
+                println!("ignoring invalid {}: {err:#}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path().context("no home dir")?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, source_id: &str) -> Credentials {
+        self.0.get(source_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, source_id: &str, credentials: Credentials) {
+        self.0.insert(source_id.to_owned(), credentials);
+    }
+}
+
+/// Returned when a site rejects the request for lack of valid credentials, e.g. once a
+/// Cloudflare clearance cookie expired. The frontend prompts the user for new ones.
+#[derive(Debug, Clone)]
+pub struct NeedsCredentials {
+    pub source_id: String,
+    /// Where the user can solve the challenge to obtain the credentials.
+    pub url: String,
+    /// Cookie names the source needs, the user agent is always asked for.
+    pub cookies: Vec<String>,
+}
+
+impl Display for NeedsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} needs new credentials, open {} and copy {} along with the user agent",
+            self.source_id,
+            self.url,
+            self.cookies.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for NeedsCredentials {}
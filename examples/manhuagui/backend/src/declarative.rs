@@ -0,0 +1,641 @@
+//! Sources described by a TOML or JSON file instead of code, so new sites can be added on the
+//! device without rebuilding the backend.
+//!
+//! Every `*.toml` and `*.json` file in `~/.config/mangarr/sources` is registered at startup.
+//! A definition uses URL templates and [`Extractor`]s:
+//!
+//! ```toml
+//! id = "example"
+//! name = "Example"
+//! base_url = "https://example.com"
+//!
+//! [manga]
+//! url = "{base_url}/manga/{id}/"
+//! title = "h1.title"
+//! thumbnail = { selector = "#cover img", attr = "src" }
+//!
+//! [chapters]
+//! item = "ul.chapters > li > a"
+//! name = { attr = "title" }
+//! link = { attr = "href" }
+//! reverse = true
+//!
+//! [pages]
+//! images = { regex = 'var images = (\[.*?\]);', script = "JSON.parse(value)" }
+//!
+//! [popular]
+//! url = "{base_url}/popular?page={page}"
+//! item = "div.book"
+//! title = "h3"
+//! link = { selector = "a", attr = "href" }
+//! next_page = "a.next"
+//! ```
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use quick_js::JsValue;
+use regex::Regex;
+use reqwest::{
+    Client, Url,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Deserializer, de};
+
+use crate::{
+    Capabilities, ImageUrl, MangaBackend, MangaStatus, MangasPage, Page, SChapter, SManga,
+    SearchFilter, Source, SourceInfo,
+    cache::{self, CacheKind},
+    http,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Definition {
+    /// Lowercase letters, digits, `_` and `-`, since it also names the settings file.
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_lang")]
+    pub lang: String,
+    #[serde(default)]
+    pub description: String,
+    pub base_url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// JavaScript evaluated before every script, e.g. shared decoding functions.
+    #[serde(default)]
+    pub prelude: String,
+    pub manga: MangaDefinition,
+    pub chapters: ChaptersDefinition,
+    pub pages: PagesDefinition,
+    /// `{query}` and `{page}` are filled in, percent-encoded.
+    pub search: Option<ListingDefinition>,
+    pub popular: Option<ListingDefinition>,
+    pub latest: Option<ListingDefinition>,
+}
+
+fn default_lang() -> String {
+    "all".to_owned()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MangaDefinition {
+    /// `{id}` is what the user searched for, percent-encoded.
+    pub url: String,
+    pub title: Extractor,
+    pub author: Option<Extractor>,
+    pub description: Option<Extractor>,
+    pub genre: Option<Extractor>,
+    pub thumbnail: Option<Extractor>,
+    pub status: Option<Extractor>,
+    /// Regex the extracted status must match for the manga to count as completed,
+    /// anything else is ongoing.
+    pub completed: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChaptersDefinition {
+    /// `{manga_url}` and `{id}` are filled in, defaults to the manga page.
+    pub url: Option<String>,
+    /// Selects one element per chapter, the extractors below run inside it.
+    pub item: String,
+    pub name: Extractor,
+    pub link: Extractor,
+    /// Set when the site lists the newest chapter first.
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PagesDefinition {
+    /// `{chapter_url}` is filled in, defaults to the chapter page.
+    pub url: Option<String>,
+    /// Every value extracted is one page, in order.
+    pub images: Extractor,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListingDefinition {
+    pub url: String,
+    /// Selects one element per manga, the extractors below run inside it.
+    pub item: String,
+    pub title: Extractor,
+    pub link: Extractor,
+    pub thumbnail: Option<Extractor>,
+    /// Selector that only matches when there is another page.
+    pub next_page: Option<String>,
+}
+
+/// Pulls strings out of a page, applying each configured step in order:
+///
+/// 1. `selector` picks elements, without one the extractor sees the raw HTML,
+/// 2. `attr` reads an attribute instead of the element's text,
+/// 3. `regex` keeps every match, or its first capture group if it has one,
+/// 4. `script` is evaluated with the value in `value` and may return a string or an array.
+///
+/// A plain string is shorthand for a selector.
+#[derive(Debug, Clone, Default)]
+pub struct Extractor {
+    pub selector: Option<String>,
+    pub attr: Option<String>,
+    pub regex: Option<String>,
+    pub script: Option<String>,
+}
+
+/// The table form of an [`Extractor`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExtractorSteps {
+    selector: Option<String>,
+    attr: Option<String>,
+    regex: Option<String>,
+    script: Option<String>,
+}
+
+// not an untagged enum, which would hide a misspelled step behind "did not match any variant"
+impl<'de> Deserialize<'de> for Extractor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Extractor;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a selector or a table of extractor steps")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Extractor, E> {
+                Ok(Extractor {
+                    selector: Some(v.to_owned()),
+                    ..Default::default()
+                })
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Extractor, A::Error> {
+                let steps =
+                    ExtractorSteps::deserialize(de::value::MapAccessDeserializer::new(map))?;
+                Ok(Extractor {
+                    selector: steps.selector,
+                    attr: steps.attr,
+                    regex: steps.regex,
+                    script: steps.script,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+fn parse_selector(s: &str) -> anyhow::Result<Selector> {
+    Selector::parse(s).map_err(|x| anyhow::anyhow!("invalid selector `{s}`: {x}"))
+}
+
+impl Extractor {
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(selector) = &self.selector {
+            parse_selector(selector)?;
+        }
+        if let Some(regex) = &self.regex {
+            Regex::new(regex)?;
+        }
+        Ok(())
+    }
+
+    fn read(&self, element: ElementRef<'_>, raw: bool) -> Option<String> {
+        match &self.attr {
+            Some(attr) => element.value().attr(attr).map(|x| x.trim().to_owned()),
+            None if raw => Some(element.html()),
+            None => Some(element.text().collect::<String>().trim().to_owned()),
+        }
+    }
+
+    pub fn extract_all(&self, root: ElementRef<'_>, prelude: &str) -> anyhow::Result<Vec<String>> {
+        let mut values = match &self.selector {
+            Some(selector) => root
+                .select(&parse_selector(selector)?)
+                .filter_map(|x| self.read(x, false))
+                .collect(),
+            None => self.read(root, true).into_iter().collect::<Vec<_>>(),
+        };
+        if let Some(regex) = &self.regex {
+            let regex = Regex::new(regex)?;
+            values = values
+                .iter()
+                .flat_map(|value| {
+                    regex
+                        .captures_iter(value)
+                        .filter_map(|x| x.get(1).or_else(|| x.get(0)))
+                        .map(|x| x.as_str().to_owned())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+        }
+        if let Some(script) = &self.script {
+            values = values
+                .into_iter()
+                .map(|value| run_script(prelude, script, value))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .concat();
+        }
+        Ok(values)
+    }
+
+    pub fn extract(&self, root: ElementRef<'_>, prelude: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .extract_all(root, prelude)?
+            .into_iter()
+            .find(|x| !x.is_empty()))
+    }
+}
+
+fn run_script(prelude: &str, script: &str, value: String) -> anyhow::Result<Vec<String>> {
+    let context = quick_js::Context::new()?;
+    context.set_global("value", value)?;
+    let values = match context.eval(&format!("{prelude}\n{script}"))? {
+        JsValue::String(x) => vec![x],
+        JsValue::Array(x) => x
+            .into_iter()
+            .map(|x| {
+                x.as_str()
+                    .map(str::to_owned)
+                    .context("script returned a non string")
+            })
+            .collect::<anyhow::Result<_>>()?,
+        JsValue::Undefined | JsValue::Null => Vec::new(),
+        x => bail!("script returned {x:?} instead of a string or array"),
+    };
+    Ok(values)
+}
+
+/// Percent-encodes user input so it stays one value wherever the template puts it.
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, NON_ALPHANUMERIC).to_string()
+}
+
+fn fill(template: &str, vars: &[(&str, &str)]) -> String {
+    vars.iter().fold(template.to_owned(), |acc, (key, value)| {
+        acc.replace(&format!("{{{key}}}"), value)
+    })
+}
+
+impl Definition {
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let definition: Self = match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => toml::from_str(&contents)?,
+            Some("json") => serde_json::from_str(&contents)?,
+            _ => bail!("unsupported source definition {}", path.display()),
+        };
+        definition.validate()?;
+        Ok(definition)
+    }
+
+    /// Checks every selector and regex up front so mistakes show up when loading.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.id.is_empty()
+            || !self
+                .id
+                .chars()
+                .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '_' || x == '-')
+        {
+            bail!("source id `{}` may only use a-z, 0-9, `_` and `-`", self.id);
+        }
+        Url::parse(&self.base_url)?;
+        let manga = &self.manga;
+        let listings = [&self.search, &self.popular, &self.latest];
+        let extractors = [Some(&manga.title), Some(&self.chapters.name)]
+            .into_iter()
+            .chain([&manga.author, &manga.description, &manga.genre].map(Option::as_ref))
+            .chain([&manga.thumbnail, &manga.status].map(Option::as_ref))
+            .chain([Some(&self.chapters.link), Some(&self.pages.images)])
+            .chain(listings.iter().flat_map(|x| {
+                x.iter()
+                    .flat_map(|x| [Some(&x.title), Some(&x.link), x.thumbnail.as_ref()])
+            }))
+            .flatten();
+        for extractor in extractors {
+            extractor.validate()?;
+        }
+        parse_selector(&self.chapters.item)?;
+        for listing in listings.into_iter().flatten() {
+            parse_selector(&listing.item)?;
+            if let Some(next_page) = &listing.next_page {
+                parse_selector(next_page)?;
+            }
+        }
+        if let Some(completed) = &manga.completed {
+            Regex::new(completed)?;
+        }
+        Ok(())
+    }
+
+    pub fn into_source_info(self) -> SourceInfo {
+        let definition = Arc::new(self);
+        SourceInfo::new(
+            definition.id.clone(),
+            definition.name.clone(),
+            definition.lang.clone(),
+            {
+                let definition = definition.clone();
+                move |_| Ok(Source::new(DeclarativeSource::new(definition.clone())?))
+            },
+        )
+        .with_description(definition.description.clone())
+    }
+}
+
+/// Where definitions are loaded from.
+pub fn sources_dir() -> Option<PathBuf> {
+    #[allow(deprecated)]
+    Some(std::env::home_dir()?.join(".config/mangarr/sources"))
+}
+
+/// Loads every definition in `dir`, skipping other files.
+pub fn load_dir(dir: &Path) -> Vec<anyhow::Result<Definition>> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths = entries
+        .filter_map(|x| Some(x.ok()?.path()))
+        .filter(|x| x.extension().is_some_and(|x| x == "toml" || x == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            Definition::from_file(&path)
+                .with_context(|| format!("failed to load {}", path.display()))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct DeclarativeSource {
+    definition: Arc<Definition>,
+    base_url: Url,
+    client: Client,
+}
+
+impl Display for DeclarativeSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.definition.name)
+    }
+}
+
+impl DeclarativeSource {
+    pub fn new(definition: Arc<Definition>) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        for (key, value) in &definition.headers {
+            headers.insert(HeaderName::try_from(key)?, HeaderValue::from_str(value)?);
+        }
+        let client = Client::builder().default_headers(headers).build()?;
+        Ok(Self {
+            base_url: Url::parse(&definition.base_url)?,
+            definition,
+            client,
+        })
+    }
+
+    fn absolute(&self, url: &str) -> anyhow::Result<String> {
+        Ok(self.base_url.join(url)?.to_string())
+    }
+
+    /// Fills in `{base_url}` first, so the `vars` can't expand into it.
+    fn template(&self, template: &str, vars: &[(&str, &str)]) -> String {
+        let base_url = self.definition.base_url.trim_end_matches('/');
+        fill(&fill(template, &[("base_url", base_url)]), vars)
+    }
+
+    async fn fetch(&self, url: &str) -> anyhow::Result<String> {
+        let response = http::send(self.client.get(url)).await?.error_for_status()?;
+        Ok(response.text().await?)
+    }
+
+    /// [`Self::fetch`] through the [`cache`].
+    async fn fetch_cached(&self, kind: CacheKind, url: &str) -> anyhow::Result<String> {
+        let request = self.client.get(url);
+        let response = cache::send(&self.definition.id, kind, request).await?;
+        Ok(response.error_for_status()?.text().await?)
+    }
+
+    fn parse_manga(&self, body: &str, url: String) -> anyhow::Result<SManga> {
+        let document = Html::parse_document(body);
+        let root = document.root_element();
+        let manga = &self.definition.manga;
+        let prelude = &self.definition.prelude;
+        let optional = |x: &Option<Extractor>| -> anyhow::Result<Option<String>> {
+            match x {
+                Some(x) => x.extract(root, prelude),
+                None => Ok(None),
+            }
+        };
+
+        let status = match (optional(&manga.status)?, &manga.completed) {
+            (Some(status), Some(completed)) if Regex::new(completed)?.is_match(&status) => {
+                MangaStatus::Completed
+            }
+            (Some(_), Some(_)) => MangaStatus::Ongoing,
+            _ => MangaStatus::Unknown,
+        };
+
+        Ok(SManga {
+            url: ImageUrl::Web(url),
+            title: manga
+                .title
+                .extract(root, prelude)?
+                .context("no title found")?,
+            thumbnail_url: optional(&manga.thumbnail)?
+                .map(|x| self.absolute(&x))
+                .transpose()?,
+            author: optional(&manga.author)?,
+            description: optional(&manga.description)?,
+            genre: optional(&manga.genre)?,
+            status,
+            last_updated_time: String::new(),
+        })
+    }
+
+    fn parse_chapters(&self, body: &str) -> anyhow::Result<Vec<SChapter>> {
+        let document = Html::parse_document(body);
+        let chapters = &self.definition.chapters;
+        let prelude = &self.definition.prelude;
+        let mut items = document
+            .select(&parse_selector(&chapters.item)?)
+            .map(|item| {
+                let url = chapters
+                    .link
+                    .extract(item, prelude)?
+                    .context("no chapter link")?;
+                let name = chapters.name.extract(item, prelude)?.unwrap_or_default();
+                anyhow::Ok((name, self.absolute(&url)?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if chapters.reverse {
+            items.reverse();
+        }
+        Ok(items
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, url))| SChapter {
+                url: ImageUrl::Web(url),
+                name,
+                chapter_number: (i + 1) as f32,
+                date_upload: None,
+            })
+            .collect())
+    }
+
+    fn parse_listing(&self, listing: &ListingDefinition, body: &str) -> anyhow::Result<MangasPage> {
+        let document = Html::parse_document(body);
+        let prelude = &self.definition.prelude;
+        let mangas = document
+            .select(&parse_selector(&listing.item)?)
+            .map(|item| {
+                let Some(link) = listing.link.extract(item, prelude)? else {
+                    return Ok(None);
+                };
+                let thumbnail_url = match &listing.thumbnail {
+                    Some(x) => x.extract(item, prelude)?,
+                    None => None,
+                };
+                anyhow::Ok(Some(SManga {
+                    url: ImageUrl::Web(self.absolute(&link)?),
+                    title: listing.title.extract(item, prelude)?.unwrap_or_default(),
+                    thumbnail_url: thumbnail_url.map(|x| self.absolute(&x)).transpose()?,
+                    ..Default::default()
+                }))
+            })
+            .filter_map(Result::transpose)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let has_next_page = match &listing.next_page {
+            Some(next_page) => document
+                .select(&parse_selector(next_page)?)
+                .next()
+                .is_some(),
+            None => false,
+        };
+        Ok(MangasPage {
+            mangas,
+            has_next_page,
+        })
+    }
+
+    async fn fetch_listing(
+        &self,
+        listing: Option<&ListingDefinition>,
+        vars: &[(&str, &str)],
+    ) -> anyhow::Result<MangasPage> {
+        let listing = listing.context("listing not defined")?;
+        let body = self.fetch(&self.template(&listing.url, vars)).await?;
+        self.parse_listing(listing, &body)
+    }
+}
+
+#[async_trait]
+impl MangaBackend for DeclarativeSource {
+    fn id(&self) -> String {
+        self.definition.id.clone()
+    }
+    fn client(&self) -> Option<Client> {
+        self.client.clone().into()
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            search: self.definition.search.is_some(),
+            popular: self.definition.popular.is_some(),
+            latest: self.definition.latest.is_some(),
+            ..Default::default()
+        }
+    }
+    async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga> {
+        let url = if id.starts_with("http") {
+            id.to_owned()
+        } else {
+            self.template(&self.definition.manga.url, &[("id", &encode(id))])
+        };
+        let body = self.fetch_cached(CacheKind::Details, &url).await?;
+        self.parse_manga(&body, url)
+    }
+    async fn search(
+        &self,
+        query: &str,
+        page: u32,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<MangasPage> {
+        let page = page.to_string();
+        self.fetch_listing(
+            self.definition.search.as_ref(),
+            &[("query", &encode(query.trim())), ("page", &page)],
+        )
+        .await
+    }
+    async fn popular(&self, page: u32) -> anyhow::Result<MangasPage> {
+        let page = page.to_string();
+        self.fetch_listing(self.definition.popular.as_ref(), &[("page", &page)])
+            .await
+    }
+    async fn latest(&self, page: u32) -> anyhow::Result<MangasPage> {
+        let page = page.to_string();
+        self.fetch_listing(self.definition.latest.as_ref(), &[("page", &page)])
+            .await
+    }
+    async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
+        let ImageUrl::Web(manga_url) = &manga.url else {
+            bail!("not a web manga");
+        };
+        let body = match &self.definition.chapters.url {
+            Some(template) => {
+                let id = manga_url
+                    .trim_end_matches('/')
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default();
+                let url = self.template(template, &[("manga_url", manga_url), ("id", id)]);
+                self.fetch_cached(CacheKind::Chapters, &url).await?
+            }
+            None => self.fetch_cached(CacheKind::Chapters, manga_url).await?,
+        };
+        self.parse_chapters(&body)
+    }
+    async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
+        let ImageUrl::Web(chapter_url) = &chapter.url else {
+            bail!("not a web chapter");
+        };
+        let url = match &self.definition.pages.url {
+            Some(template) => self.template(template, &[("chapter_url", chapter_url)]),
+            None => chapter_url.clone(),
+        };
+        let body = self.fetch_cached(CacheKind::Pages, &url).await?;
+        let images = {
+            let document = Html::parse_document(&body);
+            self.definition
+                .pages
+                .images
+                .extract_all(document.root_element(), &self.definition.prelude)?
+        };
+        images
+            .into_iter()
+            .enumerate()
+            .map(|(index, image)| {
+                Ok(Page {
+                    index,
+                    url: url.clone(),
+                    image_url: ImageUrl::Web(self.absolute(&image)?),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,243 @@
+//! Saves whole chapters into the [`library`], one after another, and reports their progress.
+//!
+//! Pausing aborts the worker. Finished pages stay on disk, so resuming picks the chapter up at
+//! its first missing page. Cancelling a chapter deletes what was saved of it.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use backend::{SChapter, SManga, Source, library};
+
+use crate::{
+    AbortableTask, BackendReplier,
+    manga_reader::MangaReader,
+    message::{ReplierExt, SendMessage},
+    spawn,
+};
+
+#[derive(Debug, Clone)]
+struct Job {
+    id: u64,
+    api: Source,
+    manga: Arc<SManga>,
+    chapter: SChapter,
+}
+
+impl Job {
+    fn dir(&self) -> anyhow::Result<std::path::PathBuf> {
+        library::chapter_dir(&self.api.id(), &self.manga, &self.chapter)
+            .context("downloads are off since there is no library directory")
+    }
+
+    fn progress(&self, state: DownloadState) -> DownloadProgress {
+        DownloadProgress {
+            id: self.id,
+            title: self.manga.title.clone(),
+            chapter: self.chapter.name.clone(),
+            state,
+            pages: None,
+            message: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadState {
+    Queued,
+    Downloading,
+    Paused,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl DownloadState {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Downloading => "downloading",
+            Self::Paused => "paused",
+            Self::Done => "done",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// One change of a download, as reported to the frontend.
+#[derive(Debug, Clone)]
+pub struct DownloadProgress {
+    pub id: u64,
+    pub title: String,
+    pub chapter: String,
+    pub state: DownloadState,
+    /// Saved and total pages, once the page list is known.
+    pub pages: Option<(usize, usize)>,
+    /// Why a download failed.
+    pub message: Option<String>,
+}
+
+async fn report(functionality: &BackendReplier, progress: DownloadProgress) {
+    let message = SendMessage::DownloadProgress(progress);
+    if let Err(err) = functionality.send_typed_message(message).await {
+        println!("can't report download progress: {err:#}");
+    }
+}
+
+#[derive(Default)]
+pub struct Downloads {
+    /// The chapter being downloaded first.
+    queue: Arc<Mutex<VecDeque<Job>>>,
+    next_id: u64,
+    paused: bool,
+    worker: Option<AbortableTask<()>>,
+}
+
+impl Downloads {
+    /// Queues the `chapters` of `manga` that are neither saved nor queued yet.
+    pub async fn enqueue(
+        &mut self,
+        manga: &MangaReader,
+        chapters: impl IntoIterator<Item = usize>,
+        functionality: &BackendReplier,
+    ) -> anyhow::Result<()> {
+        let api = manga.api.clone();
+        for index in chapters {
+            let chapter = manga
+                .chapters
+                .get(index)
+                .with_context(|| format!("there is no chapter {}", index + 1))?;
+            let job = Job {
+                id: self.next_id,
+                api: api.clone(),
+                manga: manga.details.clone(),
+                chapter: chapter.clone(),
+            };
+            if library::is_downloaded(&job.dir()?) {
+                continue;
+            }
+            let queued = self.queue.lock().unwrap().iter().any(|x| {
+                x.api.id() == job.api.id()
+                    && x.manga.url == job.manga.url
+                    && x.chapter.url == job.chapter.url
+            });
+            if queued {
+                continue;
+            }
+            self.next_id += 1;
+            report(functionality, job.progress(DownloadState::Queued)).await;
+            self.queue.lock().unwrap().push_back(job);
+        }
+        self.wake(functionality);
+        Ok(())
+    }
+
+    pub async fn pause(&mut self, functionality: &BackendReplier) {
+        self.paused = true;
+        if let Some(worker) = self.worker.take() {
+            worker.abort();
+        }
+        let current = self.queue.lock().unwrap().front().cloned();
+        if let Some(job) = current {
+            report(functionality, job.progress(DownloadState::Paused)).await;
+        }
+    }
+
+    pub fn resume(&mut self, functionality: &BackendReplier) {
+        self.paused = false;
+        self.wake(functionality);
+    }
+
+    /// Cancels the download `id`, or every download with `None`.
+    pub async fn cancel(
+        &mut self,
+        id: Option<u64>,
+        functionality: &BackendReplier,
+    ) -> anyhow::Result<()> {
+        let (cancelled, current) = {
+            let mut queue = self.queue.lock().unwrap();
+            let current = queue.front().map(|x| x.id);
+            let (cancelled, kept) = std::mem::take(&mut *queue)
+                .into_iter()
+                .partition::<Vec<_>, _>(|x| id.is_none_or(|id| x.id == id));
+            *queue = kept.into();
+            (cancelled, current)
+        };
+        // the worker must stop writing into the chapter before it is deleted
+        if cancelled.iter().any(|x| Some(x.id) == current)
+            && let Some(worker) = self.worker.take()
+        {
+            worker.abort();
+            let _ = worker.await;
+        }
+        for job in cancelled {
+            library::remove_chapter(&job.dir()?).await?;
+            report(functionality, job.progress(DownloadState::Cancelled)).await;
+        }
+        self.wake(functionality);
+        Ok(())
+    }
+
+    /// Starts the worker unless it runs already, is paused or has nothing to do.
+    fn wake(&mut self, functionality: &BackendReplier) {
+        let running = self.worker.as_ref().is_some_and(|x| !x.is_finished());
+        if self.paused || running || self.queue.lock().unwrap().is_empty() {
+            return;
+        }
+        let queue = self.queue.clone();
+        let functionality = functionality.clone();
+        self.worker = Some(spawn(async move { work(&queue, &functionality).await }));
+    }
+}
+
+async fn work(queue: &Mutex<VecDeque<Job>>, functionality: &BackendReplier) {
+    loop {
+        let Some(job) = queue.lock().unwrap().front().cloned() else {
+            return;
+        };
+        let mut progress = job.progress(DownloadState::Done);
+        if let Err(err) = download(&job, functionality).await {
+            println!("downloading {} failed: {err:#}", job.chapter.name);
+            progress.state = DownloadState::Failed;
+            progress.message = Some(format!("{err:#}"));
+        }
+        {
+            let mut queue = queue.lock().unwrap();
+            if queue.front().is_some_and(|x| x.id == job.id) {
+                queue.pop_front();
+            }
+        }
+        report(functionality, progress).await;
+    }
+}
+
+async fn download(job: &Job, functionality: &BackendReplier) -> anyhow::Result<()> {
+    let dir = job.dir()?;
+    let pages = if let Some(pages) = library::load_pages(&dir) {
+        pages
+    } else {
+        let pages = job
+            .api
+            .fetch_pages(&job.chapter)
+            .await?
+            .into_iter()
+            .map(|x| x.image_url)
+            .collect::<Vec<_>>();
+        library::save_pages(&dir, &pages).await?;
+        pages
+    };
+
+    for (index, url) in pages.iter().enumerate() {
+        if library::page(&dir, index).is_none() {
+            library::ensure_space(&dir)?;
+            let image = job.api.fetch_image(url).await?;
+            library::save_page(&dir, index, &image).await?;
+        }
+        let mut progress = job.progress(DownloadState::Downloading);
+        progress.pages = Some((index + 1, pages.len()));
+        report(functionality, progress).await;
+    }
+    Ok(())
+}
@@ -1,173 +1,648 @@
 use std::fmt::Display;
 use std::fs::File;
-use std::hash::DefaultHasher;
-use std::hash::Hash;
-use std::hash::Hasher;
 use std::io::BufReader;
-use std::mem::MaybeUninit;
+use std::path::Component;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::LazyLock;
 
+use ab_glyph::Font;
+use ab_glyph::FontArc;
+use ab_glyph::GlyphId;
+use ab_glyph::PxScale;
+use ab_glyph::ScaleFont;
 use anyhow::Context;
+use anyhow::bail;
+use async_trait::async_trait;
 use epub::doc::EpubDoc;
-use futures::FutureExt;
+use epub::doc::NavPoint;
+use photon_rs::PhotonImage;
 use reqwest::Client;
+use scraper::ElementRef;
 use scraper::Html;
 use scraper::Selector;
-// use futures::StreamExt;
-use serde::Deserialize;
-use serde::Serialize;
-use smol::fs::create_dir;
-use smol::fs::create_dir_all;
-use smol::fs::read_dir;
-use smol::stream::StreamExt;
 
 use crate::ImageUrl;
 use crate::MangaBackend;
 use crate::Page;
 use crate::SChapter;
 use crate::SManga;
+use crate::local_archive::natural_cmp;
+use crate::settings::SettingField;
+use crate::settings::Settings;
+use crate::settings::SettingsSchema;
 
-#[derive(Debug, Deserialize, Serialize)]
+type Doc = EpubDoc<BufReader<File>>;
+
+/// Text-only pages are rendered at the aspect ratio of the reader.
+const TEXT_PAGE_WIDTH: u32 = 1080;
+const TEXT_PAGE_HEIGHT: u32 = 1760;
+const TEXT_MARGIN: u32 = 60;
+const TEXT_FONT_SIZE: f32 = 40.0;
+const TEXT_LINE_HEIGHT: u32 = 56;
+const TEXT_LINE_WIDTH: f32 = (TEXT_PAGE_WIDTH - 2 * TEXT_MARGIN) as f32;
+const TEXT_PAGE_LINES: usize = ((TEXT_PAGE_HEIGHT - 2 * TEXT_MARGIN) / TEXT_LINE_HEIGHT) as usize;
+
+/// Fonts with CJK glyphs at the usual reMarkable and desktop locations, tried before the bundled
+/// Roboto.
+const SYSTEM_FONTS: [&str; 8] = [
+    "/usr/share/fonts/ttf/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/ttf/noto/NotoSansCJKsc-Regular.otf",
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/wenquanyi/wqy-microhei/wqy-microhei.ttc",
+    "/usr/share/fonts/truetype/droid/DroidSansFallbackFull.ttf",
+];
+
+/// Every glyph is drawn with the first font that has it. Fonts the user puts into
+/// `~/.local/share/mangarr/fonts` come first, then [`SYSTEM_FONTS`], then Roboto, which only
+/// covers latin text.
+static TEXT_FONTS: LazyLock<Vec<FontArc>> = LazyLock::new(|| {
+    #[allow(deprecated)]
+    let user_fonts = std::env::home_dir()
+        .and_then(|x| std::fs::read_dir(x.join(".local/share/mangarr/fonts")).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .collect::<Vec<_>>();
+    let mut fonts = user_fonts
+        .iter()
+        .map(PathBuf::as_path)
+        .chain(SYSTEM_FONTS.iter().map(Path::new))
+        .filter_map(|path| {
+            let bytes = std::fs::read(path).ok()?;
+            FontArc::try_from_vec(bytes)
+                .inspect_err(|err| println!("can't load the font {}: {err}", path.display()))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    fonts.push(
+        FontArc::try_from_slice(include_bytes!("../fonts/Roboto-Regular.ttf"))
+            .expect("the bundled font is valid"),
+    );
+    fonts
+});
+
+/// The font that has `c`, or the last one to draw a missing glyph.
+fn text_glyph(c: char) -> (&'static FontArc, GlyphId) {
+    let fonts = &*TEXT_FONTS;
+    fonts
+        .iter()
+        .map(|font| (font, font.glyph_id(c)))
+        .find(|(_, id)| id.0 != 0)
+        .unwrap_or_else(|| {
+            let font = fonts.last().expect("the bundled font is always there");
+            (font, font.glyph_id(c))
+        })
+}
+
+/// Width of `text` in pixels, kerned where neighbours share a font.
+fn text_width(text: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous: Option<(&FontArc, GlyphId)> = None;
+    for c in text.chars() {
+        let (font, id) = text_glyph(c);
+        let scaled = font.as_scaled(PxScale::from(TEXT_FONT_SIZE));
+        if let Some((previous_font, previous_id)) = previous
+            && std::ptr::eq(previous_font, font)
+        {
+            width += scaled.kern(previous_id, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some((font, id));
+    }
+    width
+}
+
+/// Ideographs, kana, hangul and fullwidth forms, which lines may break around.
+const fn is_cjk(c: char) -> bool {
+    matches!(
+        c,
+        '\u{2e80}'..='\u{9fff}'
+            | '\u{ac00}'..='\u{d7af}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{ff00}'..='\u{ffef}'
+            | '\u{20000}'..='\u{3ffff}'
+    )
+}
+
+#[derive(Debug)]
 pub struct Epub {
     base: PathBuf,
 }
 
+/// What a spine item contributes to a chapter.
+enum SpineContent {
+    Images(Vec<String>),
+    /// Number of rendered pages.
+    Text(usize),
+}
+
+fn open(path: &Path) -> anyhow::Result<Doc> {
+    EpubDoc::new(path).with_context(|| format!("can't open {}", path.display()))
+}
+
+/// Resolves `href` relative to the archive entry `base`, dropping any fragment.
+fn resolve(base: &Path, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut path = Vec::new();
+    for component in base
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(href)
+        .components()
+    {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(x) => path.push(x.to_string_lossy().to_string()),
+            _ => {}
+        }
+    }
+    path.join("/")
+}
+
+/// The archive path and mime type of every spine item in reading order, skipping auxiliary
+/// content such as footnotes.
+fn spine(doc: &Doc) -> Vec<(String, String)> {
+    doc.spine
+        .iter()
+        .filter(|item| item.linear)
+        .filter_map(|item| doc.resources.get(&item.idref))
+        .map(|(path, mime)| (path.to_string_lossy().replace('\\', "/"), mime.clone()))
+        .collect()
+}
+
+/// Images in document order, including ones wrapped in SVG `<image xlink:href>`.
+fn markup_images(doc_path: &Path, html: &Html) -> Vec<String> {
+    let selector = Selector::parse("img, image").unwrap();
+    html.select(&selector)
+        .filter_map(|x| {
+            let value = x.value();
+            // `attr` only matches attributes without a namespace, unlike xlink:href
+            value
+                .attr("src")
+                .or_else(|| value.attrs().find(|(name, _)| *name == "href").map(|x| x.1))
+        })
+        .filter(|x| !x.starts_with("data:"))
+        .map(|x| resolve(doc_path, x))
+        .collect()
+}
+
+/// Paragraphs of a text document, headings included.
+fn markup_text(html: &Html) -> Vec<String> {
+    let block = Selector::parse("h1, h2, h3, h4, h5, h6, p, li, pre, blockquote").unwrap();
+    let normalize = |x: ElementRef| {
+        x.text()
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut paragraphs = html
+        .select(&block)
+        .map(normalize)
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    if paragraphs.is_empty() {
+        let body = Selector::parse("body").unwrap();
+        paragraphs = html
+            .select(&body)
+            .map(normalize)
+            .filter(|x| !x.is_empty())
+            .collect();
+    }
+    paragraphs
+}
+
+/// Splits a paragraph where lines may break: at spaces, which stay with the word before, and
+/// around every CJK character.
+fn text_words(paragraph: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in paragraph.char_indices() {
+        let end = i + c.len_utf8();
+        if is_cjk(c) {
+            if start < i {
+                words.push(&paragraph[start..i]);
+            }
+            words.push(&paragraph[i..end]);
+            start = end;
+        } else if c == ' ' {
+            words.push(&paragraph[start..end]);
+            start = end;
+        }
+    }
+    if start < paragraph.len() {
+        words.push(&paragraph[start..]);
+    }
+    words
+}
+
+/// Wraps paragraphs into lines as wide as the page measured with the glyphs that draw them, and
+/// lines into pages. Words wider than a line are broken anywhere.
+fn text_pages(paragraphs: &[String]) -> Vec<Vec<String>> {
+    let mut lines = Vec::new();
+    for paragraph in paragraphs {
+        let mut line = String::new();
+        for word in text_words(paragraph) {
+            if text_width(&line) + text_width(word.trim_end()) <= TEXT_LINE_WIDTH {
+                line.push_str(word);
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(line.trim_end().to_owned());
+                line.clear();
+            }
+            for c in word.chars() {
+                if !line.is_empty()
+                    && text_width(&line) + text_width(&c.to_string()) > TEXT_LINE_WIDTH
+                {
+                    lines.push(std::mem::take(&mut line));
+                }
+                line.push(c);
+            }
+        }
+        lines.push(line.trim_end().to_owned());
+        lines.push(String::new());
+    }
+    lines.pop();
+    lines
+        .chunks(TEXT_PAGE_LINES)
+        .map(<[String]>::to_vec)
+        .collect()
+}
+
+/// Draws black text on a white page and encodes it as a png.
+fn render_text_page(lines: &[String]) -> Vec<u8> {
+    let mut pixels = vec![255; (TEXT_PAGE_WIDTH * TEXT_PAGE_HEIGHT * 4) as usize];
+    let scale = PxScale::from(TEXT_FONT_SIZE);
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = (TEXT_MARGIN + i as u32 * TEXT_LINE_HEIGHT) as f32
+            + TEXT_FONTS
+                .last()
+                .map_or(TEXT_FONT_SIZE, |x| x.as_scaled(scale).ascent());
+        let mut caret = TEXT_MARGIN as f32;
+        let mut previous: Option<(&FontArc, GlyphId)> = None;
+        for c in line.chars() {
+            let (font, id) = text_glyph(c);
+            let scaled = font.as_scaled(scale);
+            if let Some((previous_font, previous_id)) = previous
+                && std::ptr::eq(previous_font, font)
+            {
+                caret += scaled.kern(previous_id, id);
+            }
+            let glyph = id.with_scale_and_position(scale, ab_glyph::point(caret, baseline));
+            caret += scaled.h_advance(id);
+            previous = Some((font, id));
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                let x = bounds.min.x as i64 + i64::from(gx);
+                let y = bounds.min.y as i64 + i64::from(gy);
+                if !(0..i64::from(TEXT_PAGE_WIDTH)).contains(&x)
+                    || !(0..i64::from(TEXT_PAGE_HEIGHT)).contains(&y)
+                {
+                    return;
+                }
+                let at = ((y * i64::from(TEXT_PAGE_WIDTH) + x) * 4) as usize;
+                let gray = (f32::from(pixels[at]) * (1.0 - coverage.clamp(0.0, 1.0))) as u8;
+                pixels[at..at + 3].fill(gray);
+            });
+        }
+    }
+    PhotonImage::new(pixels, TEXT_PAGE_WIDTH, TEXT_PAGE_HEIGHT).get_bytes()
+}
+
+fn spine_content(doc: &mut Doc, path: &str, mime: &str) -> anyhow::Result<SpineContent> {
+    if mime.starts_with("image/") && !mime.contains("svg") {
+        return Ok(SpineContent::Images(vec![path.to_owned()]));
+    }
+    let bytes = doc
+        .get_resource_by_path(path)
+        .with_context(|| format!("{path} is missing"))?;
+    let html = Html::parse_document(&String::from_utf8_lossy(&bytes));
+    let images = markup_images(Path::new(path), &html);
+    if images.is_empty() {
+        Ok(SpineContent::Text(text_pages(&markup_text(&html)).len()))
+    } else {
+        Ok(SpineContent::Images(images))
+    }
+}
+
+fn child_elements<'a>(x: ElementRef<'a>, name: &str) -> Vec<ElementRef<'a>> {
+    x.children()
+        .filter_map(ElementRef::wrap)
+        .filter(|x| x.value().name() == name)
+        .collect()
+}
+
+/// Top level entries of the EPUB 3 navigation document, for books without a toc.ncx.
+fn nav_document_toc(doc: &mut Doc) -> Option<Vec<(String, String)>> {
+    let root_file = doc.root_file.clone();
+    let opf = doc.get_resource_str_by_path(&root_file)?;
+    let opf = roxmltree::Document::parse(&opf).ok()?;
+    let href = opf
+        .descendants()
+        .filter(|x| x.has_tag_name("item"))
+        .find(|x| {
+            x.attribute("properties")
+                .is_some_and(|x| x.split_whitespace().any(|x| x == "nav"))
+        })?
+        .attribute("href")?;
+    let nav_path = resolve(&root_file, href);
+    let html = Html::parse_document(&doc.get_resource_str_by_path(&nav_path)?);
+
+    let nav_selector = Selector::parse("nav").unwrap();
+    let ol_selector = Selector::parse("ol").unwrap();
+    let nav = html
+        .select(&nav_selector)
+        .find(|x| x.value().attr("epub:type") == Some("toc"))
+        .or_else(|| html.select(&nav_selector).next())?;
+    let mut items = child_elements(nav.select(&ol_selector).next()?, "li");
+    // a single entry for the whole book, its children are the chapters
+    if let [item] = items.as_slice()
+        && let Some(ol) = child_elements(*item, "ol").first()
+    {
+        items = child_elements(*ol, "li");
+    }
+    Some(
+        items
+            .into_iter()
+            .filter_map(|li| {
+                let a = *child_elements(li, "a").first()?;
+                let label = a.text().collect::<String>().trim().to_owned();
+                Some((
+                    label,
+                    resolve(Path::new(&nav_path), a.value().attr("href")?),
+                ))
+            })
+            .collect(),
+    )
+}
+
+/// Where each chapter of the table of contents starts in the spine, as `(spine index, label)`.
+/// Front matter before the first entry belongs to the first chapter.
+fn toc_chapters(doc: &mut Doc) -> Vec<(usize, String)> {
+    let mut points: &[NavPoint] = &doc.toc;
+    if let [point] = points
+        && !point.children.is_empty()
+    {
+        points = &point.children;
+    }
+    let entries = if points.is_empty() {
+        nav_document_toc(doc).unwrap_or_default()
+    } else {
+        points
+            .iter()
+            .map(|x| {
+                let content = x.content.to_string_lossy().replace('\\', "/");
+                (
+                    x.label.trim().to_owned(),
+                    content.split('#').next().unwrap_or_default().to_owned(),
+                )
+            })
+            .collect()
+    };
+
+    let spine = spine(doc);
+    let mut chapters = Vec::<(usize, String)>::new();
+    for (label, path) in entries {
+        let Some(index) = spine.iter().position(|(x, _)| *x == path) else {
+            continue;
+        };
+        if chapters.last().is_none_or(|(last, _)| *last < index) {
+            chapters.push((index, label));
+        }
+    }
+    if let Some(first) = chapters.first_mut() {
+        first.0 = 0;
+    }
+    chapters
+}
+
 impl Epub {
+    pub const ID: &'static str = "epub";
+
+    /// The first book of every chapter folder, naturally sorted by folder name.
+    fn books(id_path: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let mut books = std::fs::read_dir(id_path)
+            .with_context(|| format!("can't read {}", id_path.display()))?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.is_dir())
+            .filter_map(|chapter| {
+                let mut epubs = std::fs::read_dir(&chapter)
+                    .ok()?
+                    .filter_map(|x| x.ok())
+                    .map(|x| x.path())
+                    .filter(|x| {
+                        x.extension()
+                            .is_some_and(|x| x.eq_ignore_ascii_case("epub"))
+                    })
+                    .collect::<Vec<_>>();
+                epubs.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+                let name = chapter.file_name()?.to_string_lossy().to_string();
+                Some((name, epubs.into_iter().next()?))
+            })
+            .collect::<Vec<_>>();
+        books.sort_by(|a, b| natural_cmp(&a.0, &b.0));
+        Ok(books)
+    }
+
     // base/[id]/[chapter-name]/[book-name](to id)
     pub async fn create_s_manga(&self, id: &str) -> anyhow::Result<SManga> {
-        let iter = read_dir(&self.base).await?;
-        let id_path = iter
-            .map(|x| anyhow::Ok(x?.path()))
-            .find(|x| {
-                x.as_ref().is_ok_and(|x| {
-                    let x = x
-                        .file_name()
-                        .context("no filename?")
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_string();
-                    x == id
-                })
-            })
-            .await
-            .context("can't find it")??;
-        let first_chapter = read_dir(id_path)
-            .await?
-            .next()
-            .await
-            .context("no first chapter")??;
-        let path = read_dir(first_chapter.path())
-            .await?
+        if id.contains(['/', '\\']) {
+            bail!("invalid id {id}");
+        }
+        let id_path = self.base.join(id);
+        let (_, path) = Self::books(&id_path)?
+            .into_iter()
             .next()
-            .await
-            .context("no first book")??
-            .path();
-
-        let epub = EpubDoc::new(&path).unwrap();
-        let title = epub
-            .metadata
-            .get("title")
-            .and_then(|x| x.first().cloned())
-            .unwrap_or_else(|| path.file_name().unwrap().to_string_lossy().to_string());
-        let author = epub
-            .metadata
-            .get("creator")
-            .and_then(|x| x.first().cloned());
-        let date = epub.metadata.get("date").and_then(|x| x.first().cloned());
+            .with_context(|| format!("no epub in {}", id_path.display()))?;
+
+        let epub = open(&path)?;
+        let title = epub.mdata("title").unwrap_or_else(|| id.to_owned());
+        let author = epub.mdata("creator");
+        let description = epub.mdata("description");
+        let subjects = epub.metadata.get("subject").map(|x| x.join(", "));
+        let date = epub.mdata("date");
         Ok(SManga {
             url: ImageUrl::new_epub(path),
             title,
             thumbnail_url: None,
             author,
-            description: None,
-            genre: None,
+            description,
+            genre: subjects.filter(|x| !x.is_empty()),
             status: crate::MangaStatus::Unknown,
             last_updated_time: date.unwrap_or_default(),
         })
     }
+
     // base/[id]/[chapter-name]/[book-name]
-    pub async fn create_s_chapters(manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
+    /// Every book is a chapter, unless there is a single book, then its table of contents
+    /// splits it into chapters.
+    pub fn create_s_chapters(manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
         let ImageUrl::LocalEpub {
             epub_path: path, ..
         } = &manga.url
         else {
-            unreachable!()
+            bail!("not an epub: {:?}", manga.url);
         };
+        let id_path = path
+            .parent()
+            .and_then(Path::parent)
+            .context("epub is not inside [id]/[chapter-name]")?;
 
-        let id_path = path.parent().unwrap().parent().unwrap();
-        let chapter_number = std::fs::read_dir(id_path)?.count();
-        let iter = read_dir(id_path).await?;
-        let p = iter
-            .then(|x| async move {
-                let chapter_name = x?.path();
-                let path = read_dir(chapter_name.clone())
-                    .await?
-                    .next()
-                    .await
-                    .context("no first book")??
-                    .path();
-                anyhow::Ok(SChapter {
-                    name: chapter_name
-                        .file_name()
-                        .context("plz don't be empty")?
-                        .to_string_lossy()
-                        .to_string(),
-                    url: ImageUrl::new_epub(path),
-                    chapter_number: chapter_number as f32,
-                    date_upload: None,
-                })
+        let books = Self::books(id_path)?;
+        if let [(_, path)] = books.as_slice() {
+            let chapters = toc_chapters(&mut open(path)?);
+            if chapters.len() > 1 {
+                let spine = spine(&open(path)?);
+                return Ok(chapters
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (index, label))| SChapter {
+                        name: label,
+                        url: ImageUrl::LocalEpub {
+                            epub_path: path.clone(),
+                            img_path: Some(spine[index].0.clone()),
+                        },
+                        chapter_number: (i + 1) as f32,
+                        date_upload: None,
+                    })
+                    .collect());
+            }
+        }
+        Ok(books
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, path))| SChapter {
+                name,
+                url: ImageUrl::new_epub(path),
+                chapter_number: (i + 1) as f32,
+                date_upload: None,
             })
-            .collect::<Vec<_>>()
-            .await;
-        let p = p.into_iter().collect::<anyhow::Result<Vec<_>>>()?;
-        Ok(p)
+            .collect())
     }
-    pub async fn fetch_pages(base: PathBuf, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
+
+    /// A chapter is either a whole book, or the spine items from its start up to the start of
+    /// the next chapter of the table of contents.
+    pub fn fetch_pages(chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
         let ImageUrl::LocalEpub {
-            epub_path: path, ..
+            epub_path: path,
+            img_path: start,
         } = &chapter.url
         else {
-            unreachable!()
+            bail!("not an epub: {:?}", chapter.url);
         };
 
-        let mut epub = EpubDoc::new(path).unwrap();
-        let mut v = vec![];
-        for i in 0..epub.get_num_pages() {
-            let id = epub.spine.get(i).cloned().map(|i| i.idref).unwrap();
-            let (bytes, _) = epub.get_resource(&id).unwrap();
-
-            let html = String::from_utf8(bytes).unwrap();
-            let img_path = {
-                let html = Html::parse_document(&html);
-                html.select(&Selector::parse("img").unwrap())
-                    .next()
-                    .unwrap()
-                    .attr("src")
-                    .unwrap()
-                    .to_owned()
-            };
-            let img_path = img_path.strip_prefix("../").unwrap_or(&img_path);
-            v.push(Page {
-                index: i,
+        let mut epub = open(path)?;
+        let spine = spine(&epub);
+        let range = match start {
+            None => 0..spine.len(),
+            Some(start) => {
+                let starts = toc_chapters(&mut epub);
+                let position = starts
+                    .iter()
+                    .position(|(index, _)| spine[*index].0 == *start)
+                    .with_context(|| format!("{start} no longer starts a chapter"))?;
+                let end = starts.get(position + 1).map_or(spine.len(), |x| x.0);
+                starts[position].0..end
+            }
+        };
+
+        let mut img_paths = Vec::new();
+        for (doc_path, mime) in &spine[range.clone()] {
+            match spine_content(&mut epub, doc_path, mime) {
+                Ok(SpineContent::Images(images)) => {
+                    for image in images {
+                        if img_paths.last() != Some(&image) {
+                            img_paths.push(image);
+                        }
+                    }
+                }
+                Ok(SpineContent::Text(pages)) => {
+                    img_paths.extend((0..pages).map(|page| format!("{doc_path}#page={page}")));
+                }
+                Err(err) => println!("skipping {doc_path}: {err:#}"),
+            }
+        }
+
+        // covers are often only listed in the manifest
+        if range.start == 0
+            && let Some((cover, _)) = epub
+                .get_cover_id()
+                .and_then(|x| epub.resources.get(&x).cloned())
+        {
+            let cover = cover.to_string_lossy().replace('\\', "/");
+            if !img_paths.contains(&cover) {
+                img_paths.insert(0, cover);
+            }
+        }
+
+        Ok(img_paths
+            .into_iter()
+            .enumerate()
+            .map(|(index, img_path)| Page {
+                index,
                 url: String::new(),
                 image_url: ImageUrl::LocalEpub {
-                    epub_path: path.to_path_buf(),
-                    img_path: Some(img_path.to_string()),
+                    epub_path: path.clone(),
+                    img_path: Some(img_path),
                 },
-            });
-        }
-        dbg!("finished page fetching for epub");
-        Ok(v)
+            })
+            .collect())
     }
+
+    /// `epub_img_path` is an image, or `[document]#page=[n]` for a page of rendered text.
     pub fn fetch_img(&self, path: PathBuf, epub_img_path: &str) -> anyhow::Result<Vec<u8>> {
-        let mut epub = EpubDoc::new(path).unwrap();
+        let mut epub = open(&path)?;
+        if let Some((doc_path, page)) = epub_img_path.split_once("#page=") {
+            let page = page.parse::<usize>()?;
+            let bytes = epub
+                .get_resource_by_path(doc_path)
+                .with_context(|| format!("{doc_path} is missing"))?;
+            let html = Html::parse_document(&String::from_utf8_lossy(&bytes));
+            let pages = text_pages(&markup_text(&html));
+            let lines = pages.get(page).map_or(&[][..], Vec::as_slice);
+            return Ok(render_text_page(lines));
+        }
+        // older bookshelves stored paths relative to the package directory
+        let legacy = epub.root_base.join(epub_img_path);
         epub.get_resource_by_path(epub_img_path)
+            .or_else(|| epub.get_resource_by_path(legacy))
             .context("resource does not exist")
     }
+
     #[must_use]
     pub const fn new(path: PathBuf) -> Self {
         Self { base: path }
     }
+
+    pub fn schema() -> SettingsSchema {
+        SettingsSchema(vec![
+            SettingField::string(
+                "directory",
+                "Books directory",
+                Self::default().base.to_string_lossy(),
+            )
+            .with_description("Laid out as [id]/[chapter-name]/[book].epub"),
+        ])
+    }
+
+    /// Expects settings already validated against [`Self::schema`].
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings
+            .string("directory")
+            .map_or_else(Self::default, |x| Self::new(PathBuf::from(x)))
+    }
 }
 
 impl Display for Epub {
@@ -187,22 +662,40 @@ impl Default for Epub {
     }
 }
 
+#[async_trait]
 impl MangaBackend for Epub {
+    fn id(&self) -> String {
+        Self::ID.to_owned()
+    }
+    fn settings_schema(&self) -> SettingsSchema {
+        Self::schema()
+    }
     async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga> {
         self.create_s_manga(id).await
     }
 
     async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
-        let mut p = Self::create_s_chapters(manga).await?;
-        p.sort_by_key(|x| x.name.clone());
-        Ok(p)
+        let manga = manga.clone();
+        smol::unblock(move || Self::create_s_chapters(&manga)).await
     }
 
     async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
-        Self::fetch_pages(self.base.clone(), chapter).await
+        let chapter = chapter.clone();
+        smol::unblock(move || Self::fetch_pages(&chapter)).await
     }
 
     fn client(&self) -> std::option::Option<reqwest::Client> {
         Client::default().into()
     }
+
+    fn read_local_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        let ImageUrl::LocalEpub {
+            epub_path,
+            img_path: Some(img_path),
+        } = url
+        else {
+            anyhow::bail!("not an image inside an epub: {url:?}");
+        };
+        self.fetch_img(epub_path.clone(), img_path)
+    }
 }
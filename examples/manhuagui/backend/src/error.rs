@@ -0,0 +1,141 @@
+//! What went wrong in a source, in terms the frontend can act on.
+//!
+//! Sources keep returning [`anyhow::Result`] and may return a [`SourceError`] directly where they
+//! know better. Anything else is classified by [`SourceError::from`], which looks through the
+//! error chain for the underlying HTTP, IO or parser error.
+use std::fmt::Display;
+
+use reqwest::StatusCode;
+
+use crate::credentials::NeedsCredentials;
+
+#[derive(Debug, Clone)]
+pub enum SourceError {
+    /// No response was received, e.g. the device is offline or the request timed out.
+    Network(String),
+    /// The server answered with an error status.
+    HttpStatus {
+        status: u16,
+        url: Option<String>,
+    },
+    /// A response or file did not have the expected shape.
+    Parse(String),
+    /// The manga, chapter, page or file does not exist (anymore).
+    NotFound(String),
+    NeedsCredentials(NeedsCredentials),
+    /// The server asks to slow down, for `retry_after` seconds when it says so.
+    RateLimited {
+        retry_after: Option<u64>,
+    },
+    /// The source does not offer this, e.g. searching.
+    Unsupported(String),
+    /// Anything not covered above.
+    Other(String),
+}
+
+impl SourceError {
+    /// A stable name of the variant for the frontend.
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Network(_) => "network",
+            Self::HttpStatus { .. } => "http_status",
+            Self::Parse(_) => "parse",
+            Self::NotFound(_) => "not_found",
+            Self::NeedsCredentials(_) => "needs_credentials",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::Unsupported(_) => "unsupported",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// Whether trying again later may succeed.
+    pub const fn is_transient(&self) -> bool {
+        match self {
+            Self::Network(_) | Self::RateLimited { .. } => true,
+            Self::HttpStatus { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    fn from_reqwest(err: &reqwest::Error, message: String) -> Self {
+        let url = err.url().map(ToString::to_string);
+        match err.status() {
+            Some(StatusCode::NOT_FOUND) => Self::NotFound(message),
+            Some(StatusCode::TOO_MANY_REQUESTS) => Self::RateLimited { retry_after: None },
+            Some(status) => Self::HttpStatus {
+                status: status.as_u16(),
+                url,
+            },
+            None if err.is_decode() => Self::Parse(message),
+            None => Self::Network(message),
+        }
+    }
+}
+
+impl Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Network(x) => write!(f, "network error: {x}"),
+            Self::HttpStatus { status, url } => {
+                write!(f, "server answered with status {status}")?;
+                match url {
+                    Some(url) => write!(f, " for {url}"),
+                    None => Ok(()),
+                }
+            }
+            Self::Parse(x) => write!(f, "unexpected content: {x}"),
+            Self::NotFound(x) => write!(f, "not found: {x}"),
+            Self::NeedsCredentials(x) => x.fmt(f),
+            Self::RateLimited {
+                retry_after: Some(seconds),
+            } => write!(f, "rate limited, try again in {seconds}s"),
+            Self::RateLimited { retry_after: None } => write!(f, "rate limited, try again later"),
+            Self::Unsupported(x) | Self::Other(x) => f.write_str(x),
+        }
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl From<NeedsCredentials> for SourceError {
+    fn from(value: NeedsCredentials) -> Self {
+        Self::NeedsCredentials(value)
+    }
+}
+
+impl From<anyhow::Error> for SourceError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::from(&err)
+    }
+}
+
+impl From<&anyhow::Error> for SourceError {
+    fn from(err: &anyhow::Error) -> Self {
+        let message = format!("{err:#}");
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<Self>() {
+                return err.clone();
+            }
+            if let Some(needs) = cause.downcast_ref::<NeedsCredentials>() {
+                return Self::NeedsCredentials(needs.clone());
+            }
+            if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+                return Self::from_reqwest(err, message);
+            }
+            if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+                return match err.kind() {
+                    std::io::ErrorKind::NotFound => Self::NotFound(message),
+                    _ => Self::Other(message),
+                };
+            }
+            if cause.is::<serde_json::Error>()
+                || cause.is::<roxmltree::Error>()
+                || cause.is::<toml::de::Error>()
+                || cause.is::<zip::result::ZipError>()
+            {
+                return Self::Parse(message);
+            }
+        }
+        Self::Other(message)
+    }
+}
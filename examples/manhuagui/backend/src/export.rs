@@ -0,0 +1,390 @@
+//! Packages chapters into files other readers open, one file per chapter.
+//!
+//! A CBZ is laid out the way [`crate::local_archive::LocalArchive`] reads a series, with a
+//! `ComicInfo.xml` in every archive:
+//!
+//! ```text
+//! [series]/[position] [chapter].cbz
+//! ```
+//!
+//! An EPUB is a fixed-layout EPUB 3 book with one image per page, laid out the way
+//! [`crate::epub::Epub`] reads a series. Its title is the series, so all chapters import as one:
+//!
+//! ```text
+//! [series]/[position] [chapter]/[position] [chapter].epub
+//! ```
+//!
+//! The position prefix keeps the chapters in order, since both sources sort by file name.
+use std::{
+    fmt::{Display, Write as _},
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{Context, bail};
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::{ImageUrl, SChapter, SManga, http, library};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Cbz,
+    Epub,
+}
+
+impl ExportFormat {
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Cbz => "cbz",
+            Self::Epub => "epub",
+        }
+    }
+
+    /// Where chapter number `position` of `manga`, counted from 1, is exported to inside `dir`.
+    pub fn path(self, dir: &Path, manga: &SManga, position: usize, chapter: &SChapter) -> PathBuf {
+        let series = dir.join(library::file_name(&manga.title));
+        let name = library::file_name(&format!("{position:04} {}", chapter.name));
+        match self {
+            Self::Cbz => series.join(format!("{name}.cbz")),
+            Self::Epub => series.join(&name).join(format!("{name}.epub")),
+        }
+    }
+
+    /// Writes the `pages` of `chapter` to `path`, replacing an earlier export. Pages are encoded
+    /// images, PNG, JPEG, GIF or WebP.
+    pub fn write(
+        self,
+        path: &Path,
+        manga: &SManga,
+        chapter: &SChapter,
+        pages: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        if pages.is_empty() {
+            bail!("{} has no pages to export", chapter.name);
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let part = path.with_extension("part");
+        let mut zip = ZipWriter::new(File::create(&part)?);
+        match self {
+            Self::Cbz => write_cbz(&mut zip, manga, chapter, pages)?,
+            Self::Epub => write_epub(&mut zip, manga, chapter, pages)?,
+        }
+        zip.finish()?.sync_all()?;
+        std::fs::rename(&part, path)?;
+        Ok(())
+    }
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "cbz" => Ok(Self::Cbz),
+            "epub" => Ok(Self::Epub),
+            x => bail!("unknown export format {x}"),
+        }
+    }
+}
+
+/// `~/mangarr-exports`, with a folder per format so either can be picked as a source's
+/// directory.
+pub fn default_dir(format: ExportFormat) -> Option<PathBuf> {
+    #[allow(deprecated)]
+    Some(
+        std::env::home_dir()?
+            .join("mangarr-exports")
+            .join(format.extension()),
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The extension and media type of an encoded image, from its magic bytes.
+fn image_type(image: &[u8]) -> anyhow::Result<(&'static str, &'static str)> {
+    Ok(if image.starts_with(b"\x89PNG") {
+        ("png", "image/png")
+    } else if image.starts_with(b"\xff\xd8\xff") {
+        ("jpg", "image/jpeg")
+    } else if image.starts_with(b"GIF8") {
+        ("gif", "image/gif")
+    } else if image.starts_with(b"RIFF") && image.get(8..12) == Some(b"WEBP") {
+        ("webp", "image/webp")
+    } else {
+        bail!("not a png, jpeg, gif or webp image")
+    })
+}
+
+/// Width and height, read from the header of a PNG and decoded otherwise.
+fn image_size(image: &[u8]) -> anyhow::Result<(u32, u32)> {
+    if image.starts_with(b"\x89PNG")
+        && image.get(12..16) == Some(b"IHDR")
+        && let Some(size) = image.get(16..24)
+    {
+        let (width, height) = size.split_at(4);
+        return Ok((
+            u32::from_be_bytes(width.try_into()?),
+            u32::from_be_bytes(height.try_into()?),
+        ));
+    }
+    let image = photon_rs::native::open_image_from_bytes(image)?;
+    Ok((image.get_width(), image.get_height()))
+}
+
+/// Images are compressed already.
+fn stored() -> SimpleFileOptions {
+    SimpleFileOptions::default().compression_method(CompressionMethod::Stored)
+}
+
+fn deflated() -> SimpleFileOptions {
+    SimpleFileOptions::default().compression_method(CompressionMethod::Deflated)
+}
+
+/// See <https://anansi-project.github.io/docs/comicinfo/documentation>.
+fn comic_info(manga: &SManga, chapter: &SChapter, pages: usize) -> String {
+    let mut fields = vec![
+        ("Title", chapter.name.clone()),
+        ("Series", manga.title.clone()),
+        ("Number", chapter.chapter_number.to_string()),
+    ];
+    fields.extend(manga.description.clone().map(|x| ("Summary", x)));
+    fields.extend(manga.author.clone().map(|x| ("Writer", x)));
+    fields.extend(manga.genre.clone().map(|x| ("Genre", x)));
+    let date = chapter
+        .date_upload
+        .and_then(chrono::DateTime::from_timestamp_millis);
+    if let Some(date) = date {
+        fields.push(("Year", date.format("%Y").to_string()));
+        fields.push(("Month", date.format("%-m").to_string()));
+        fields.push(("Day", date.format("%-d").to_string()));
+    }
+    if let ImageUrl::Web(url) = &manga.url {
+        fields.push(("Web", url.clone()));
+    }
+    fields.push(("PageCount", pages.to_string()));
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<ComicInfo \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n",
+    );
+    for (name, value) in fields {
+        let _ = writeln!(xml, "  <{name}>{}</{name}>", escape(&value));
+    }
+    xml.push_str("</ComicInfo>\n");
+    xml
+}
+
+fn write_cbz(
+    zip: &mut ZipWriter<File>,
+    manga: &SManga,
+    chapter: &SChapter,
+    pages: &[Vec<u8>],
+) -> anyhow::Result<()> {
+    for (index, page) in pages.iter().enumerate() {
+        let (extension, _) = image_type(page)?;
+        zip.start_file(format!("{:04}.{extension}", index + 1), stored())?;
+        zip.write_all(page)?;
+    }
+    zip.start_file("ComicInfo.xml", deflated())?;
+    zip.write_all(comic_info(manga, chapter, pages.len()).as_bytes())?;
+    Ok(())
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn page_xhtml(title: &str, image: &str, (width, height): (u32, u32)) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+  <title>{title}</title>
+  <meta name="viewport" content="width={width}, height={height}"/>
+  <style>html, body {{ margin: 0; padding: 0; }} img {{ width: 100%; height: 100%; }}</style>
+</head>
+<body>
+  <img src="../images/{image}" alt=""/>
+</body>
+</html>
+"#
+    )
+}
+
+/// A table of contents with the chapter as its only entry, so the whole book is one chapter.
+fn nav_xhtml(title: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{title}</title></head>
+<body>
+  <nav epub:type="toc">
+    <ol><li><a href="pages/0001.xhtml">{title}</a></li></ol>
+  </nav>
+</body>
+</html>
+"#
+    )
+}
+
+/// EPUB 2 readers only know this table of contents.
+fn toc_ncx(id: &str, title: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <head><meta name="dtb:uid" content="{id}"/></head>
+  <docTitle><text>{title}</text></docTitle>
+  <navMap>
+    <navPoint id="chapter" playOrder="1">
+      <navLabel><text>{title}</text></navLabel>
+      <content src="pages/0001.xhtml"/>
+    </navPoint>
+  </navMap>
+</ncx>
+"#
+    )
+}
+
+fn content_opf(
+    id: &str,
+    manga: &SManga,
+    chapter: &SChapter,
+    images: &[(String, &'static str)],
+) -> String {
+    let mut metadata = format!(
+        "    <dc:identifier id=\"id\">{id}</dc:identifier>\n    <dc:title>{}</dc:title>\n    \
+         <dc:language>und</dc:language>\n",
+        escape(&manga.title)
+    );
+    if let Some(author) = &manga.author {
+        let _ = writeln!(metadata, "    <dc:creator>{}</dc:creator>", escape(author));
+    }
+    if let Some(description) = &manga.description {
+        let _ = writeln!(
+            metadata,
+            "    <dc:description>{}</dc:description>",
+            escape(description)
+        );
+    }
+    for genre in manga.genre.iter().flat_map(|x| x.split(',')).map(str::trim) {
+        if !genre.is_empty() {
+            let _ = writeln!(metadata, "    <dc:subject>{}</dc:subject>", escape(genre));
+        }
+    }
+    if let Some(date) = chapter
+        .date_upload
+        .and_then(chrono::DateTime::from_timestamp_millis)
+    {
+        let _ = writeln!(
+            metadata,
+            "    <dc:date>{}</dc:date>",
+            date.format("%Y-%m-%d")
+        );
+    }
+    let modified = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ");
+    let _ = write!(
+        metadata,
+        "    <meta property=\"dcterms:modified\">{modified}</meta>\n    \
+         <meta property=\"rendition:layout\">pre-paginated</meta>\n    \
+         <meta property=\"rendition:spread\">none</meta>\n    \
+         <meta name=\"cover\" content=\"image0001\"/>\n"
+    );
+
+    let mut manifest = String::from(
+        "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" \
+         properties=\"nav\"/>\n    <item id=\"ncx\" href=\"toc.ncx\" \
+         media-type=\"application/x-dtbncx+xml\"/>\n",
+    );
+    let mut spine = String::new();
+    for (index, (image, media_type)) in images.iter().enumerate() {
+        let number = index + 1;
+        let cover = if index == 0 {
+            " properties=\"cover-image\""
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            manifest,
+            "    <item id=\"image{number:04}\" href=\"images/{image}\" \
+             media-type=\"{media_type}\"{cover}/>\n    <item id=\"page{number:04}\" \
+             href=\"pages/{number:04}.xhtml\" media-type=\"application/xhtml+xml\"/>"
+        );
+        let _ = writeln!(spine, "    <itemref idref=\"page{number:04}\"/>");
+    }
+
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id" prefix="rendition: http://www.idpf.org/vocab/rendition/#">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+{metadata}  </metadata>
+  <manifest>
+{manifest}  </manifest>
+  <spine toc="ncx">
+{spine}  </spine>
+</package>
+"#
+    )
+}
+
+fn write_epub(
+    zip: &mut ZipWriter<File>,
+    manga: &SManga,
+    chapter: &SChapter,
+    pages: &[Vec<u8>],
+) -> anyhow::Result<()> {
+    let id = format!(
+        "urn:mangarr:{:016x}",
+        http::stable_hash(&chapter.url.get_distinguisher())
+    );
+    let title = escape(&chapter.name);
+
+    // readers expect the mimetype first and uncompressed
+    zip.start_file("mimetype", stored())?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated())?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+
+    let mut images = Vec::with_capacity(pages.len());
+    for (index, page) in pages.iter().enumerate() {
+        let number = index + 1;
+        let (extension, media_type) = image_type(page)?;
+        let size = image_size(page).with_context(|| format!("can't read page {number}"))?;
+        let image = format!("{number:04}.{extension}");
+        zip.start_file(format!("OEBPS/images/{image}"), stored())?;
+        zip.write_all(page)?;
+        zip.start_file(format!("OEBPS/pages/{number:04}.xhtml"), deflated())?;
+        zip.write_all(page_xhtml(&title, &image, size).as_bytes())?;
+        images.push((image, media_type));
+    }
+
+    zip.start_file("OEBPS/nav.xhtml", deflated())?;
+    zip.write_all(nav_xhtml(&title).as_bytes())?;
+    zip.start_file("OEBPS/toc.ncx", deflated())?;
+    zip.write_all(toc_ncx(&id, &title).as_bytes())?;
+    zip.start_file("OEBPS/content.opf", deflated())?;
+    zip.write_all(content_opf(&id, manga, chapter, &images).as_bytes())?;
+    Ok(())
+}
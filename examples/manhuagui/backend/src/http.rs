@@ -0,0 +1,337 @@
+//! The request layer every source sends through.
+//!
+//! [`send`] spaces out requests to the same host according to [`limit_host`], times out
+//! requests that hang, and retries connection failures, `429 Too Many Requests` and server
+//! errors with exponential backoff, waiting at least as long as a `Retry-After` header asks.
+//! A `Retry-After` also holds back every other request to that host.
+//!
+//! With [`Recording::Record`] every response is also saved to a directory, which
+//! [`Recording::Replay`] later serves instead of the network. This makes sources testable
+//! offline and lets a broken scraper be debugged against the exact pages it failed on. The mode
+//! is picked with [`set_recording`] or the `MANGARR_HTTP_RECORD` and `MANGARR_HTTP_REPLAY`
+//! environment variables, which name the directory.
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::Context;
+use reqwest::{
+    Method, RequestBuilder, Response, StatusCode,
+    header::{CONTENT_TYPE, HeaderMap, HeaderName, RETRY_AFTER},
+};
+
+use crate::SourceError;
+
+/// How a request is sent, [`Policy::DEFAULT`] suits most sites.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every following one.
+    pub backoff: Duration,
+    /// Longest wait between two attempts, a longer `Retry-After` fails instead.
+    pub max_wait: Duration,
+    pub timeout: Duration,
+}
+
+impl Policy {
+    pub const DEFAULT: Self = Self {
+        retries: 3,
+        backoff: Duration::from_millis(500),
+        max_wait: Duration::from_secs(60),
+        timeout: Duration::from_secs(30),
+    };
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Debug, Default)]
+struct Host {
+    /// Minimum time between the start of two requests.
+    interval: Duration,
+    /// When the next request may start.
+    next: Option<Instant>,
+}
+
+static HOSTS: LazyLock<Mutex<HashMap<String, Host>>> = LazyLock::new(Default::default);
+
+/// Lets at most one request start every `interval` to `host`, e.g. `www.manhuagui.com`.
+pub fn limit_host(host: impl Into<String>, interval: Duration) {
+    let mut hosts = HOSTS.lock().unwrap_or_else(|x| x.into_inner());
+    hosts.entry(host.into()).or_default().interval = interval;
+}
+
+/// Reserves the next free slot of `host`, returning how long to wait for it.
+fn reserve(host: &str) -> Duration {
+    let mut hosts = HOSTS.lock().unwrap_or_else(|x| x.into_inner());
+    let Some(state) = hosts.get_mut(host) else {
+        return Duration::ZERO;
+    };
+    let now = Instant::now();
+    let slot = state.next.map_or(now, |x| x.max(now));
+    state.next = Some(slot + state.interval);
+    slot - now
+}
+
+/// Holds back every request to `host` for `duration`.
+fn pause(host: &str, duration: Duration) {
+    let mut hosts = HOSTS.lock().unwrap_or_else(|x| x.into_inner());
+    let state = hosts.entry(host.to_owned()).or_default();
+    let until = Instant::now() + duration;
+    state.next = Some(state.next.map_or(until, |x| x.max(until)));
+}
+
+/// `Retry-After` as either seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let date = SystemTime::from(date);
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Makes retries of concurrent requests spread out instead of hitting the host together.
+fn jitter(duration: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    duration + duration.mul_f64(f64::from(nanos % 250) / 1000.0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recording {
+    Off,
+    /// Saves every response into the directory.
+    Record(PathBuf),
+    /// Answers from the responses saved in the directory, failing for any other request.
+    Replay(PathBuf),
+}
+
+static RECORDING: LazyLock<RwLock<Recording>> = LazyLock::new(|| {
+    let recording = match (
+        std::env::var_os("MANGARR_HTTP_REPLAY"),
+        std::env::var_os("MANGARR_HTTP_RECORD"),
+    ) {
+        (Some(dir), _) => Recording::Replay(dir.into()),
+        (None, Some(dir)) => Recording::Record(dir.into()),
+        (None, None) => Recording::Off,
+    };
+    RwLock::new(recording)
+});
+
+/// Switches between the network, recording and replaying for every source.
+pub fn set_recording(recording: Recording) {
+    *RECORDING.write().unwrap_or_else(|x| x.into_inner()) = recording;
+}
+
+fn recording() -> Recording {
+    RECORDING.read().unwrap_or_else(|x| x.into_inner()).clone()
+}
+
+/// FNV-1a, unlike the std hasher it stays the same across releases, for naming files.
+pub(crate) fn stable_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, x| {
+        (hash ^ u64::from(x)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Where the response to `method url` is saved, readable enough to find a page by hand.
+pub fn recording_path(dir: &Path, method: &Method, url: &str) -> PathBuf {
+    let hash = stable_hash(&format!("{method} {url}"));
+    let readable = url
+        .split_once("://")
+        .map_or(url, |(_, x)| x)
+        .chars()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || x == '.' {
+                x
+            } else {
+                '_'
+            }
+        })
+        .take(80)
+        .collect::<String>();
+    dir.join(format!("{method}_{readable}-{hash:016x}.http"))
+}
+
+/// A response saved to a file as the request line, the status, the headers, a blank line and
+/// the body. Recordings and the [`crate::cache`] share the format.
+#[derive(Debug, Clone)]
+pub(crate) struct SavedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl SavedResponse {
+    pub async fn read(response: Response) -> anyhow::Result<Self> {
+        Ok(Self {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+
+    pub async fn save(&self, path: &Path, method: &Method, url: &str) -> anyhow::Result<()> {
+        let mut head = format!("{method} {url}\n{}\n", self.status.as_u16());
+        for (name, value) in &self.headers {
+            if let Ok(value) = value.to_str() {
+                let _ = writeln!(head, "{name}: {value}");
+            }
+        }
+        head.push('\n');
+        if let Some(dir) = path.parent() {
+            smol::fs::create_dir_all(dir).await?;
+        }
+        smol::fs::write(path, [head.as_bytes(), &self.body].concat()).await?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::read(path)?;
+        let (head, body) = file
+            .windows(2)
+            .position(|x| x == b"\n\n")
+            .map(|i| (&file[..i], &file[i + 2..]))
+            .with_context(|| format!("{} has no blank line after the headers", path.display()))?;
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines().skip(1);
+        let status = lines
+            .next()
+            .and_then(|x| StatusCode::from_bytes(x.trim().as_bytes()).ok())
+            .with_context(|| format!("{} has no status", path.display()))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in lines.filter_map(|x| x.split_once(':')) {
+            if let (Ok(name), Ok(value)) = (name.trim().parse(), value.trim().parse()) {
+                headers.append::<HeaderName>(name, value);
+            }
+        }
+        if !headers.contains_key(CONTENT_TYPE) {
+            headers.insert(CONTENT_TYPE, "text/html; charset=utf-8".parse()?);
+        }
+        Ok(Self {
+            status,
+            headers,
+            body: body.to_vec(),
+        })
+    }
+
+    pub fn response(&self) -> anyhow::Result<Response> {
+        let mut response = ::http::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            response = response.header(name, value);
+        }
+        Ok(Response::from(response.body(self.body.clone())?))
+    }
+}
+
+/// Sends `request` with the [`Policy::DEFAULT`].
+pub async fn send(request: RequestBuilder) -> anyhow::Result<Response> {
+    send_with(request, &Policy::DEFAULT).await
+}
+
+/// Whether Cloudflare answered with a challenge, which only a browser can pass and retrying
+/// never will.
+pub fn is_challenge(headers: &HeaderMap) -> bool {
+    headers
+        .get("cf-mitigated")
+        .is_some_and(|x| x.as_bytes().eq_ignore_ascii_case(b"challenge"))
+}
+
+/// Sends `request`, retrying as `policy` allows. Error statuses other than 429 are returned
+/// like reqwest does, for the caller to check, once the retries ran out.
+pub async fn send_with(request: RequestBuilder, policy: &Policy) -> anyhow::Result<Response> {
+    let Some(built) = request.try_clone().and_then(|x| x.build().ok()) else {
+        // a streamed body can only be sent once and is never recorded
+        return send_live(request, policy, "").await;
+    };
+    let (method, url) = (built.method().clone(), built.url().to_string());
+    match recording() {
+        Recording::Off => {
+            send_live(request, policy, built.url().host_str().unwrap_or_default()).await
+        }
+        Recording::Record(dir) => {
+            let response =
+                send_live(request, policy, built.url().host_str().unwrap_or_default()).await?;
+            let saved = SavedResponse::read(response).await?;
+            saved
+                .save(&recording_path(&dir, &method, &url), &method, &url)
+                .await?;
+            saved.response()
+        }
+        Recording::Replay(dir) => {
+            let path = recording_path(&dir, &method, &url);
+            SavedResponse::load(&path)
+                .with_context(|| {
+                    format!(
+                        "no recorded response for {method} {url} at {}",
+                        path.display()
+                    )
+                })?
+                .response()
+        }
+    }
+}
+
+async fn send_live(
+    request: RequestBuilder,
+    policy: &Policy,
+    host: &str,
+) -> anyhow::Result<Response> {
+    let host = host.to_owned();
+    let mut attempt = 0;
+    loop {
+        let Some(current) = request.try_clone().filter(|_| attempt < policy.retries) else {
+            smol::Timer::after(reserve(&host)).await;
+            let response = request.timeout(policy.timeout).send().await?;
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                return Err(SourceError::RateLimited {
+                    retry_after: retry_after(response.headers()).map(|x| x.as_secs()),
+                }
+                .into());
+            }
+            return Ok(response);
+        };
+        smol::Timer::after(reserve(&host)).await;
+        let backoff = jitter(policy.backoff * 2u32.pow(attempt));
+        attempt += 1;
+        let wait = match current.timeout(policy.timeout).send().await {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                let wait = retry_after(response.headers()).unwrap_or(backoff);
+                if wait > policy.max_wait {
+                    return Err(SourceError::RateLimited {
+                        retry_after: Some(wait.as_secs()),
+                    }
+                    .into());
+                }
+                pause(&host, wait);
+                wait
+            }
+            Ok(response)
+                if response.status().is_server_error() && !is_challenge(response.headers()) =>
+            {
+                let wait = retry_after(response.headers()).unwrap_or(backoff);
+                if wait > policy.max_wait {
+                    return Ok(response);
+                }
+                wait
+            }
+            Ok(response) => return Ok(response),
+            Err(err) if err.is_connect() || err.is_timeout() => backoff,
+            Err(err) => return Err(err.into()),
+        };
+        println!("retrying request to {host} in {wait:?}");
+        smol::Timer::after(wait).await;
+    }
+}
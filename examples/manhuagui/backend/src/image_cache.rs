@@ -0,0 +1,372 @@
+//! Pages prepared for the screen, kept across restarts up to a size limit.
+//!
+//! An image is stored as `{key}.png` next to its thumbnail `{key}.preview.png`, where the key is
+//! the SHA-256 of the source and the image url, see [`key`]. Unlike the std hasher it stays the
+//! same across releases. `index.json` records the size and last use of every image, and once
+//! the cache outgrows its limit the least recently used images are evicted.
+//!
+//! Files are written to a `.part` file and renamed when complete. Opening the cache deletes the
+//! `.part` files a crash left behind, drops images whose PNG is truncated and adopts complete
+//! images the index lost track of.
+//!
+//! The limit of the [`global`] cache is a setting the frontend reads and changes like the
+//! settings of a source, under the id [`SETTINGS_ID`].
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use smol::lock::Mutex;
+
+use crate::{
+    ImageUrl,
+    settings::{SettingField, Settings, SettingsSchema},
+};
+
+pub const DEFAULT_MAX_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Answers `GetSettings` and `UpdateSettings` in place of a source id.
+pub const SETTINGS_ID: &str = "image-cache";
+
+/// Every PNG ends with this chunk, so a file without it was cut short.
+const PNG_END: [u8; 12] = [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82];
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+struct Entry {
+    /// Bytes of the image and its preview together.
+    size: u64,
+    /// Unix time in milliseconds.
+    last_used: i64,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Index {
+    entries: HashMap<String, Entry>,
+}
+
+impl Index {
+    fn size(&self) -> u64 {
+        self.entries.values().map(|x| x.size).sum()
+    }
+}
+
+#[derive(Debug)]
+pub struct ImageCache {
+    dir: PathBuf,
+    max_size: AtomicU64,
+    index: Mutex<Index>,
+}
+
+/// The key of the image at `url` of `source`, as 64 hex digits.
+pub fn key(source: &str, url: &ImageUrl) -> String {
+    let url = serde_json::to_string(url).unwrap_or_default();
+    let hash = Sha256::new()
+        .chain_update(source)
+        .chain_update([0])
+        .chain_update(url)
+        .finalize();
+    hash.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// `~/.cache/mangarr/images`, or the temporary directory without a home.
+pub fn default_dir() -> PathBuf {
+    #[allow(deprecated)]
+    std::env::home_dir().map_or_else(
+        || std::env::temp_dir().join("mangarr/images"),
+        |x| x.join(".cache/mangarr/images"),
+    )
+}
+
+pub fn settings_schema() -> SettingsSchema {
+    SettingsSchema(vec![
+        SettingField::choice(
+            "max_size_mb",
+            "Image cache size",
+            &[
+                ("128", "128 MiB"),
+                ("256", "256 MiB"),
+                ("512", "512 MiB"),
+                ("1024", "1 GiB"),
+                ("2048", "2 GiB"),
+            ],
+            &(DEFAULT_MAX_SIZE / 1024 / 1024).to_string(),
+        )
+        .with_description("Least recently read pages are deleted beyond this size"),
+    ])
+}
+
+/// The saved settings of the cache, the defaults if they are missing or invalid.
+pub fn load_settings() -> Settings {
+    let schema = settings_schema();
+    match Settings::load(SETTINGS_ID).and_then(|x| schema.validate(&x)) {
+        Ok(settings) => settings,
+        Err(err) => {
+            println!("ignoring saved image cache settings: {err:#}");
+            schema.defaults()
+        }
+    }
+}
+
+/// The size limit chosen in validated `settings`.
+pub fn max_size(settings: &Settings) -> u64 {
+    settings
+        .string("max_size_mb")
+        .and_then(|x| x.parse::<u64>().ok())
+        .map_or(DEFAULT_MAX_SIZE, |x| x * 1024 * 1024)
+}
+
+static GLOBAL: RwLock<Option<Arc<ImageCache>>> = RwLock::new(None);
+
+/// The cache the reader uses, opened in the [`default_dir`] on first use unless [`set_global`]
+/// picked another one.
+pub fn global() -> Arc<ImageCache> {
+    if let Some(cache) = &*GLOBAL.read().unwrap_or_else(|x| x.into_inner()) {
+        return cache.clone();
+    }
+    GLOBAL
+        .write()
+        .unwrap_or_else(|x| x.into_inner())
+        .get_or_insert_with(|| {
+            Arc::new(ImageCache::open(default_dir(), max_size(&load_settings())))
+        })
+        .clone()
+}
+
+pub fn set_global(cache: ImageCache) {
+    *GLOBAL.write().unwrap_or_else(|x| x.into_inner()) = Some(Arc::new(cache));
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|x| x.len())
+}
+
+/// Whether the file ends like a complete PNG.
+fn is_complete(path: &Path) -> bool {
+    let mut end = [0; PNG_END.len()];
+    File::open(path)
+        .and_then(|mut x| {
+            x.seek(SeekFrom::End(-(PNG_END.len() as i64)))?;
+            x.read_exact(&mut end)
+        })
+        .is_ok_and(|()| end == PNG_END)
+}
+
+fn remove(path: &Path) {
+    if let Err(err) = std::fs::remove_file(path)
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        println!("can't remove {}: {err}", path.display());
+    }
+}
+
+async fn write(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let part = path.with_extension("part");
+    smol::fs::write(&part, bytes).await?;
+    smol::fs::rename(&part, path).await?;
+    Ok(())
+}
+
+impl ImageCache {
+    /// Opens the cache in `dir`, creating it and repairing it as the module describes. Problems
+    /// are only logged, since at worst they cost downloading pages again.
+    pub fn open(dir: PathBuf, max_size: u64) -> Self {
+        if let Err(err) = std::fs::create_dir_all(&dir) {
+            println!("can't create the image cache {}: {err}", dir.display());
+        }
+        let mut index = std::fs::read(dir.join("index.json"))
+            .ok()
+            .and_then(|x| serde_json::from_slice::<Index>(&x).ok())
+            .unwrap_or_default();
+        let cache = Self {
+            dir,
+            max_size: AtomicU64::new(max_size),
+            index: Mutex::new(Index::default()),
+        };
+        cache.repair(&mut index);
+        for key in cache.overflow(&index, None) {
+            index.entries.remove(&key);
+            cache.remove_files(&key);
+        }
+        if let Err(err) = cache.save_index(&index) {
+            println!("can't save the image cache index: {err:#}");
+        }
+        *cache.index.try_lock().expect("the cache is not shared yet") = index;
+        cache
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.png"))
+    }
+
+    pub fn preview_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.preview.png"))
+    }
+
+    pub fn max_size(&self) -> u64 {
+        self.max_size.load(Ordering::Relaxed)
+    }
+
+    /// Changes the size limit, evicting the least recently used images at once when it shrinks.
+    pub async fn set_max_size(&self, max_size: u64) -> anyhow::Result<()> {
+        let mut index = self.index.lock().await;
+        self.max_size.store(max_size, Ordering::Relaxed);
+        for key in self.overflow(&index, None) {
+            index.entries.remove(&key);
+            self.remove_files(&key);
+        }
+        self.save_index(&index)
+    }
+
+    /// Bytes used by every image and preview.
+    pub async fn size(&self) -> u64 {
+        self.index.lock().await.size()
+    }
+
+    /// The image of `key`, marking it as used. The use is saved with the next change.
+    pub async fn get(&self, key: &str) -> Option<PathBuf> {
+        let mut index = self.index.lock().await;
+        let entry = index.entries.get_mut(key)?;
+        let path = self.path(key);
+        if !path.exists() {
+            index.entries.remove(key);
+            return None;
+        }
+        entry.last_used = now();
+        Some(path)
+    }
+
+    /// Stores the PNG `image` of `key` with its `preview`, evicting the least recently used
+    /// images when the cache grows too large.
+    pub async fn insert(&self, key: &str, image: &[u8], preview: &[u8]) -> anyhow::Result<PathBuf> {
+        let path = self.path(key);
+        write(&self.preview_path(key), preview).await?;
+        write(&path, image).await?;
+
+        let mut index = self.index.lock().await;
+        index.entries.insert(
+            key.to_owned(),
+            Entry {
+                size: (image.len() + preview.len()) as u64,
+                last_used: now(),
+            },
+        );
+        for key in self.overflow(&index, Some(key)) {
+            index.entries.remove(&key);
+            self.remove_files(&key);
+        }
+        self.save_index(&index)?;
+        Ok(path)
+    }
+
+    /// Saves when images were last used, e.g. before quitting.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        self.save_index(&*self.index.lock().await)
+    }
+
+    fn save_index(&self, index: &Index) -> anyhow::Result<()> {
+        let path = self.dir.join("index.json");
+        let part = path.with_extension("part");
+        std::fs::write(&part, serde_json::to_vec(index)?)?;
+        std::fs::rename(&part, &path)?;
+        Ok(())
+    }
+
+    fn remove_files(&self, key: &str) {
+        remove(&self.path(key));
+        remove(&self.preview_path(key));
+    }
+
+    /// The least recently used keys to evict to fit into the size limit, never `keep`.
+    fn overflow(&self, index: &Index, keep: Option<&str>) -> Vec<String> {
+        let max_size = self.max_size();
+        let mut size = index.size();
+        if size <= max_size {
+            return Vec::new();
+        }
+        let mut entries = index
+            .entries
+            .iter()
+            .filter(|(key, _)| Some(key.as_str()) != keep)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(_, x)| x.last_used);
+        let mut evicted = Vec::new();
+        for (key, entry) in entries {
+            if size <= max_size {
+                break;
+            }
+            size -= entry.size;
+            evicted.push(key.clone());
+        }
+        evicted
+    }
+
+    /// Makes the files and `index` agree, trusting the files.
+    fn repair(&self, index: &mut Index) {
+        let Ok(files) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut found = HashMap::<String, SystemTime>::new();
+        for path in files.filter_map(|x| x.ok()).map(|x| x.path()) {
+            let name = path
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if name.ends_with(".part") {
+                println!("removing {}, left by an interrupted write", path.display());
+                remove(&path);
+            } else if let Some(key) = name.strip_suffix(".png")
+                && !key.ends_with(".preview")
+            {
+                let modified = std::fs::metadata(&path).and_then(|x| x.modified());
+                found.insert(key.to_owned(), modified.unwrap_or(SystemTime::UNIX_EPOCH));
+            }
+        }
+
+        index.entries.retain(|key, _| found.contains_key(key));
+        for (key, modified) in found {
+            let (path, preview) = (self.path(&key), self.preview_path(&key));
+            if !is_complete(&path) || !is_complete(&preview) {
+                println!("removing the truncated image {}", path.display());
+                index.entries.remove(&key);
+                self.remove_files(&key);
+                continue;
+            }
+            let size =
+                file_size(&path).unwrap_or_default() + file_size(&preview).unwrap_or_default();
+            let last_used = chrono::DateTime::<chrono::Utc>::from(modified).timestamp_millis();
+            index
+                .entries
+                .entry(key)
+                .or_insert(Entry { size, last_used })
+                .size = size;
+        }
+        // previews whose image is gone
+        let orphans = std::fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| {
+                x.file_name()
+                    .and_then(|x| x.to_str()?.strip_suffix(".preview.png"))
+                    .is_some_and(|key| !index.entries.contains_key(key))
+            })
+            .collect::<Vec<_>>();
+        for path in orphans {
+            remove(&path);
+        }
+    }
+}
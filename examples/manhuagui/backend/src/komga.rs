@@ -0,0 +1,377 @@
+//! Reads from a self-hosted [Komga](https://komga.org) server through its REST API.
+//!
+//! Series are mangas and books are chapters. Pages are streamed one by one and the page being
+//! read is sent back as the read progress of the book, so other devices pick up where the tablet
+//! left off.
+use std::fmt::Display;
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use reqwest::{Client, Method, RequestBuilder};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::json;
+
+use crate::{
+    Capabilities, ImageUrl, MangaBackend, MangaStatus, MangasPage, Page, SChapter, SManga,
+    SearchFilter, http,
+    settings::{SettingField, Settings, SettingsSchema},
+};
+
+/// Series per listing page.
+const PAGE_SIZE: u32 = 20;
+
+#[derive(Debug, Deserialize)]
+struct Paged<T> {
+    content: Vec<T>,
+    last: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Series {
+    id: String,
+    name: String,
+    metadata: SeriesMetadata,
+    books_metadata: BooksMetadata,
+    last_modified: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SeriesMetadata {
+    status: String,
+    title: String,
+    summary: String,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BooksMetadata {
+    #[serde(default)]
+    authors: Vec<Author>,
+    summary: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    name: String,
+    role: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Book {
+    id: String,
+    name: String,
+    metadata: BookMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BookMetadata {
+    title: String,
+    number_sort: f32,
+    release_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BookPage {
+    number: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Login {
+    Password(String, String),
+    ApiKey(String),
+}
+
+#[derive(Debug)]
+pub struct Komga {
+    base_url: String,
+    login: Option<Login>,
+    client: Client,
+}
+
+impl Komga {
+    pub const ID: &'static str = "komga";
+
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim().trim_end_matches('/').to_owned(),
+            login: None,
+            client: Client::new(),
+        }
+    }
+
+    /// Sends basic auth with every request.
+    #[must_use]
+    pub fn with_password(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.login = Some(Login::Password(username.into(), password.into()));
+        self
+    }
+
+    /// Sends the key as `X-API-Key` with every request, taking precedence over a password.
+    #[must_use]
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.login = Some(Login::ApiKey(key.into()));
+        self
+    }
+
+    pub fn schema() -> SettingsSchema {
+        SettingsSchema(vec![
+            SettingField::string("url", "Server url", "")
+                .with_description("e.g. http://server:25600"),
+            SettingField::string("username", "Username", ""),
+            SettingField::string("password", "Password", ""),
+            SettingField::string("api_key", "API key", "")
+                .with_description("Used instead of the username and password when set"),
+        ])
+    }
+
+    /// Expects settings already validated against [`Self::schema`].
+    pub fn from_settings(settings: &Settings) -> Self {
+        let komga = Self::new(settings.string("url").unwrap_or_default());
+        let non_empty = |key| settings.string(key).filter(|x| !x.is_empty());
+        match (non_empty("api_key"), non_empty("username")) {
+            (Some(key), _) => komga.with_api_key(key),
+            (None, Some(username)) => {
+                komga.with_password(username, settings.string("password").unwrap_or_default())
+            }
+            (None, None) => komga,
+        }
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.login {
+            Some(Login::Password(username, password)) => {
+                request.basic_auth(username, Some(password))
+            }
+            Some(Login::ApiKey(key)) => request.header("X-API-Key", key),
+            None => request,
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> anyhow::Result<T> {
+        if self.base_url.is_empty() {
+            bail!("set the server url in the settings of {self}");
+        }
+        let url = format!("{}{path}", self.base_url);
+        let response = http::send(self.request(Method::GET, &url).query(query)).await?;
+        Ok(response.error_for_status()?.json().await?)
+    }
+
+    fn series_url(&self, id: &str) -> String {
+        format!("{}/api/v1/series/{id}", self.base_url)
+    }
+
+    fn book_url(&self, id: &str) -> String {
+        format!("{}/api/v1/books/{id}", self.base_url)
+    }
+
+    /// The series or book id at the end of a url made by [`Self::series_url`] or
+    /// [`Self::book_url`].
+    fn id_of(url: &ImageUrl) -> anyhow::Result<&str> {
+        let ImageUrl::Web(url) = url else {
+            bail!("not a komga url: {url:?}");
+        };
+        url.rsplit('/').next().context("empty url")
+    }
+
+    fn manga(&self, series: Series) -> SManga {
+        let authors = series
+            .books_metadata
+            .authors
+            .iter()
+            .filter(|x| matches!(&*x.role, "writer" | "penciller"))
+            .map(|x| x.name.as_str())
+            .fold(Vec::new(), |mut names, x| {
+                if !names.contains(&x) {
+                    names.push(x);
+                }
+                names
+            });
+        let metadata = series.metadata;
+        let summary = if metadata.summary.is_empty() {
+            series.books_metadata.summary
+        } else {
+            metadata.summary
+        };
+        let genres = metadata
+            .genres
+            .into_iter()
+            .chain(metadata.tags)
+            .collect::<Vec<_>>();
+        SManga {
+            url: ImageUrl::Web(self.series_url(&series.id)),
+            title: if metadata.title.is_empty() {
+                series.name
+            } else {
+                metadata.title
+            },
+            thumbnail_url: Some(format!("{}/thumbnail", self.series_url(&series.id))),
+            author: Some(authors.join(", ")).filter(|x| !x.is_empty()),
+            description: Some(summary).filter(|x| !x.is_empty()),
+            genre: Some(genres.join(", ")).filter(|x| !x.is_empty()),
+            status: match &*metadata.status {
+                "ONGOING" | "HIATUS" => MangaStatus::Ongoing,
+                "ENDED" | "ABANDONED" => MangaStatus::Completed,
+                _ => MangaStatus::Unknown,
+            },
+            last_updated_time: series.last_modified.unwrap_or_default(),
+        }
+    }
+
+    /// `page` starts at 1, Komga's pages at 0.
+    async fn listing(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        page: u32,
+    ) -> anyhow::Result<MangasPage> {
+        let page = page.saturating_sub(1).to_string();
+        let size = PAGE_SIZE.to_string();
+        let query = [query, &[("page", &page), ("size", &size)]].concat();
+        let series: Paged<Series> = self.get(path, &query).await?;
+        Ok(MangasPage {
+            mangas: series.content.into_iter().map(|x| self.manga(x)).collect(),
+            has_next_page: !series.last,
+        })
+    }
+}
+
+impl Display for Komga {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Komga")
+    }
+}
+
+#[async_trait]
+impl MangaBackend for Komga {
+    fn id(&self) -> String {
+        Self::ID.to_owned()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            search: true,
+            popular: true,
+            latest: true,
+            ..Default::default()
+        }
+    }
+
+    fn settings_schema(&self) -> SettingsSchema {
+        Self::schema()
+    }
+
+    async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga> {
+        let series = self.get(&format!("/api/v1/series/{id}"), &[]).await?;
+        Ok(self.manga(series))
+    }
+
+    async fn search(
+        &self,
+        query: &str,
+        page: u32,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<MangasPage> {
+        let query = [("search", query.trim()), ("sort", "metadata.titleSort,asc")];
+        self.listing("/api/v1/series", &query, page).await
+    }
+
+    /// Every series, by title.
+    async fn popular(&self, page: u32) -> anyhow::Result<MangasPage> {
+        let query = [("sort", "metadata.titleSort,asc")];
+        self.listing("/api/v1/series", &query, page).await
+    }
+
+    /// Recently added or updated series.
+    async fn latest(&self, page: u32) -> anyhow::Result<MangasPage> {
+        self.listing("/api/v1/series/latest", &[], page).await
+    }
+
+    async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
+        let id = Self::id_of(&manga.url)?;
+        let books: Paged<Book> = self
+            .get(
+                &format!("/api/v1/series/{id}/books"),
+                &[("unpaged", "true"), ("sort", "metadata.numberSort,asc")],
+            )
+            .await?;
+        Ok(books
+            .content
+            .into_iter()
+            .map(|book| SChapter {
+                url: ImageUrl::Web(self.book_url(&book.id)),
+                name: if book.metadata.title.is_empty() {
+                    book.name
+                } else {
+                    book.metadata.title
+                },
+                chapter_number: book.metadata.number_sort,
+                date_upload: book
+                    .metadata
+                    .release_date
+                    .and_then(|x| NaiveDate::parse_from_str(&x, "%Y-%m-%d").ok())
+                    .and_then(|x| x.and_hms_opt(0, 0, 0))
+                    .map(|x| x.and_utc().timestamp_millis()),
+            })
+            .collect())
+    }
+
+    async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
+        let id = Self::id_of(&chapter.url)?;
+        let pages: Vec<BookPage> = self.get(&format!("/api/v1/books/{id}/pages"), &[]).await?;
+        Ok(pages
+            .into_iter()
+            .enumerate()
+            .map(|(index, page)| Page {
+                index,
+                url: String::new(),
+                image_url: ImageUrl::Web(format!("{}/pages/{}", self.book_url(id), page.number)),
+            })
+            .collect())
+    }
+
+    fn client(&self) -> Option<Client> {
+        Some(self.client.clone())
+    }
+
+    async fn fetch_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        let ImageUrl::Web(url) = url else {
+            bail!("{self} can not read local image {url:?}");
+        };
+        let response = http::send(self.request(Method::GET, url)).await?;
+        Ok(response.error_for_status()?.bytes().await?.to_vec())
+    }
+
+    /// Komga counts pages from 1 and marks the book read on its last page.
+    async fn sync_progress(
+        &self,
+        chapter: &SChapter,
+        page: usize,
+        pages: usize,
+    ) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/read-progress",
+            self.book_url(Self::id_of(&chapter.url)?)
+        );
+        let request = self
+            .request(Method::PATCH, &url)
+            .json(&json!({ "page": page + 1, "completed": page + 1 >= pages }));
+        http::send(request).await?.error_for_status()?;
+        Ok(())
+    }
+}
@@ -1,14 +1,32 @@
-use std::{fmt::Display, ops::Deref, path::PathBuf, str::FromStr, sync::Arc};
+use std::{fmt::Display, path::PathBuf};
 
-use manhuagui::{Manhuagui, Preferences};
+use anyhow::Context;
+use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
-use crate::{epub::Epub, nhentai::NHentai};
+pub use crate::{
+    error::SourceError,
+    registry::{Source, SourceInfo, SourceRegistry},
+};
 
+pub mod cache;
+pub mod credentials;
+pub mod declarative;
 pub mod epub;
+pub mod error;
+pub mod export;
+pub mod http;
+pub mod image_cache;
+pub mod komga;
+pub mod library;
+pub mod local_archive;
 pub mod manhuagui;
 pub mod nhentai;
+pub mod opds;
+pub mod pdf;
+pub mod registry;
+pub mod settings;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct SManga {
@@ -30,6 +48,47 @@ pub struct SChapter {
     pub date_upload: Option<i64>,
 }
 
+/// One page of a listing (search results, popular or latest).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MangasPage {
+    pub mangas: Vec<SManga>,
+    pub has_next_page: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchFilter {
+    /// One of the ids from [`Capabilities::genres`].
+    pub genre: Option<String>,
+    pub status: Option<MangaStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Genre {
+    pub id: String,
+    pub name: String,
+}
+
+impl Genre {
+    pub fn new(id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+        }
+    }
+}
+
+/// What a backend supports, so the frontend can hide the rest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Capabilities {
+    pub search_by_id: bool,
+    pub search: bool,
+    pub popular: bool,
+    pub latest: bool,
+    pub status_filter: bool,
+    /// Empty when filtering by genre is unsupported.
+    pub genres: Vec<Genre>,
+}
+
 #[derive(Debug)]
 pub struct Page {
     pub index: usize,
@@ -44,6 +103,12 @@ pub enum ImageUrl {
         epub_path: PathBuf,
         img_path: Option<String>,
     },
+    /// A file on disk, or with `entry` a file inside the archive or a page of the document at
+    /// `path`.
+    LocalFile {
+        path: PathBuf,
+        entry: Option<String>,
+    },
 }
 impl ImageUrl {
     pub fn new_epub(path: PathBuf) -> Self {
@@ -64,6 +129,7 @@ impl ImageUrl {
                 let id_path = path.parent().unwrap().parent().unwrap();
                 id_path.to_string_lossy().to_string()
             }
+            Self::LocalFile { path, .. } => path.to_string_lossy().to_string(),
         }
     }
 }
@@ -74,94 +140,81 @@ impl From<String> for ImageUrl {
     }
 }
 
+#[async_trait]
 pub trait MangaBackend: std::fmt::Debug + Send + Sync + Display {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            ..Default::default()
+        }
+    }
+
     async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga>;
 
+    /// `page` starts at 1.
+    async fn search(
+        &self,
+        _query: &str,
+        _page: u32,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<MangasPage> {
+        Err(SourceError::Unsupported(format!("{self} does not support searching")).into())
+    }
+
+    async fn popular(&self, _page: u32) -> anyhow::Result<MangasPage> {
+        Err(SourceError::Unsupported(format!("{self} does not support popular listings")).into())
+    }
+
+    async fn latest(&self, _page: u32) -> anyhow::Result<MangasPage> {
+        Err(SourceError::Unsupported(format!("{self} does not support latest listings")).into())
+    }
+
     async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>>;
 
     async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>>;
 
     fn client(&self) -> Option<Client>;
 
-    /// # Safety
-    /// Users must ensure the implementations do NOT have the same type name.(do not override)
-    fn id(&self) -> String {
-        core::any::type_name::<Self>().to_string()
+    /// Downloads a page image. Sources with several image hosts override this to fail over.
+    async fn fetch_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        match url {
+            ImageUrl::Web(url) => {
+                let client = self.client().context("no http client")?;
+                let response = http::send(client.get(url)).await?.error_for_status()?;
+                Ok(response.bytes().await?.to_vec())
+            }
+            ImageUrl::LocalEpub { .. } | ImageUrl::LocalFile { .. } => self.read_local_image(url),
+        }
     }
-}
 
-#[derive(Debug, Deserialize, Serialize)]
-pub enum Backend {
-    Epub(Epub),
-    NHentai(NHentai),
-    Manhuagui(Manhuagui),
-}
-
-impl Display for Backend {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let p = match self {
-            Self::Epub(x) => x.to_string(),
-            Self::NHentai(x) => x.to_string(),
-            Self::Manhuagui(x) => x.to_string(),
-        };
-        write!(f, "{p}")
+    /// Reads an image that is not fetched over HTTP, e.g. one stored inside an epub.
+    fn read_local_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        Err(SourceError::Unsupported(format!("{self} can not read local image {url:?}")).into())
     }
-}
 
-impl From<Manhuagui> for Backend {
-    fn from(v: Manhuagui) -> Self {
-        Self::Manhuagui(v)
+    /// Replaces the credentials used for requests without rebuilding the source.
+    fn set_credentials(&self, _credentials: &credentials::Credentials) -> anyhow::Result<()> {
+        Err(SourceError::Unsupported(format!("{self} does not use credentials")).into())
     }
-}
 
-impl From<NHentai> for Backend {
-    fn from(v: NHentai) -> Self {
-        Self::NHentai(v)
+    /// Tells the source that page `page` of `pages` of `chapter` is being read, both counted
+    /// from 0, for servers that keep track of read progress across devices.
+    async fn sync_progress(
+        &self,
+        _chapter: &SChapter,
+        _page: usize,
+        _pages: usize,
+    ) -> anyhow::Result<()> {
+        Ok(())
     }
-}
 
-impl From<Epub> for Backend {
-    fn from(v: Epub) -> Self {
-        Self::Epub(v)
+    /// Options the user can change, see [`settings`].
+    fn settings_schema(&self) -> settings::SettingsSchema {
+        settings::SettingsSchema::default()
     }
-}
 
-impl Backend {
-    pub fn id(&self) -> String {
-        match self {
-            Self::Epub(x) => x.id(),
-            Self::NHentai(x) => x.id(),
-            Self::Manhuagui(x) => x.id(),
-        }
-    }
-    pub fn client(&self) -> Option<Client> {
-        match self {
-            Self::Epub(x) => x.client(),
-            Self::NHentai(x) => x.client(),
-            Self::Manhuagui(x) => x.client(),
-        }
-    }
-    pub async fn search_by_id(&self, id: &str) -> Result<SManga, anyhow::Error> {
-        match self {
-            Self::Epub(x) => x.search_by_id(id).await,
-            Self::NHentai(x) => x.search_by_id(id).await,
-            Self::Manhuagui(x) => x.search_by_id(id).await,
-        }
-    }
-    pub async fn fetch_chapters(&self, manga: &SManga) -> Result<Vec<SChapter>, anyhow::Error> {
-        match self {
-            Self::Epub(x) => x.fetch_chapters(manga).await,
-            Self::NHentai(x) => x.fetch_chapters(manga).await,
-            Self::Manhuagui(x) => x.fetch_chapters(manga).await,
-        }
-    }
-    pub async fn fetch_pages(&self, chapter: &SChapter) -> Result<Vec<Page>, anyhow::Error> {
-        match self {
-            Self::Epub(x) => x.fetch_pages(chapter).await,
-            Self::NHentai(x) => x.fetch_pages(chapter).await,
-            Self::Manhuagui(x) => x.fetch_pages(chapter).await,
-        }
-    }
+    /// The id this source is registered under in the [`SourceRegistry`].
+    fn id(&self) -> String;
 }
 
 impl Default for SManga {
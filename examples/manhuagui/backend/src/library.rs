@@ -0,0 +1,146 @@
+//! Chapters saved for reading offline. Unlike the [`crate::cache`], nothing here expires.
+//!
+//! A chapter lives in `{dir}/{source}/{series}/{chapter}`, where the last two are hashes of their
+//! urls. The directory holds `pages.json`, the image urls the source listed, and one file per
+//! page named after its index. A page is written to a `.part` file first and renamed when
+//! complete, so a page file without the extension is always whole. The library is off until
+//! [`set_dir`] is called.
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use anyhow::{Context, bail};
+
+use crate::{ImageUrl, SChapter, SManga, http};
+
+/// Downloads stop when less space than this is free, leaving room for the rest of the system.
+pub const MIN_FREE_SPACE: u64 = 256 * 1024 * 1024;
+
+static DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// `~/.local/share/mangarr/library`.
+pub fn default_dir() -> Option<PathBuf> {
+    #[allow(deprecated)]
+    Some(std::env::home_dir()?.join(".local/share/mangarr/library"))
+}
+
+/// Keeps downloads in `dir`, or turns the library off with `None`.
+pub fn set_dir(dir: Option<PathBuf>) {
+    *DIR.write().unwrap_or_else(|x| x.into_inner()) = dir;
+}
+
+pub fn dir() -> Option<PathBuf> {
+    DIR.read().unwrap_or_else(|x| x.into_inner()).clone()
+}
+
+/// Where `chapter` of `manga` from `source` is saved, whether it was downloaded or not.
+pub fn chapter_dir(source: &str, manga: &SManga, chapter: &SChapter) -> Option<PathBuf> {
+    let source = source.replace(|x: char| !x.is_ascii_alphanumeric() && x != '-', "_");
+    let hash = |url: &ImageUrl| format!("{:016x}", http::stable_hash(&url.get_distinguisher()));
+    Some(
+        dir()?
+            .join(source)
+            .join(hash(&manga.url))
+            .join(hash(&chapter.url)),
+    )
+}
+
+/// `name` made into a portable file name that stays inside its directory, for the files users
+/// see such as exports and OPDS downloads.
+pub fn file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|x| {
+            if r#"/\:*?"<>|"#.contains(x) || x.is_control() {
+                '_'
+            } else {
+                x
+            }
+        })
+        .collect::<String>();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        "untitled".to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+pub fn page_path(chapter_dir: &Path, index: usize) -> PathBuf {
+    chapter_dir.join(format!("{index:04}"))
+}
+
+/// The page list saved by [`save_pages`].
+pub fn load_pages(chapter_dir: &Path) -> Option<Vec<ImageUrl>> {
+    let file = std::fs::read(chapter_dir.join("pages.json")).ok()?;
+    serde_json::from_slice(&file).ok()
+}
+
+/// Saves the page list first, so a download can resume without asking the source again.
+pub async fn save_pages(chapter_dir: &Path, pages: &[ImageUrl]) -> anyhow::Result<()> {
+    smol::fs::create_dir_all(chapter_dir).await?;
+    smol::fs::write(chapter_dir.join("pages.json"), serde_json::to_vec(pages)?).await?;
+    Ok(())
+}
+
+/// The saved page, if it was downloaded completely.
+pub fn page(chapter_dir: &Path, index: usize) -> Option<PathBuf> {
+    Some(page_path(chapter_dir, index)).filter(|x| x.is_file())
+}
+
+pub async fn save_page(chapter_dir: &Path, index: usize, image: &[u8]) -> anyhow::Result<()> {
+    let path = page_path(chapter_dir, index);
+    let part = path.with_extension("part");
+    smol::fs::write(&part, image).await?;
+    smol::fs::rename(&part, &path).await?;
+    Ok(())
+}
+
+/// Whether the page list and every page of the chapter are saved.
+pub fn is_downloaded(chapter_dir: &Path) -> bool {
+    load_pages(chapter_dir)
+        .is_some_and(|pages| (0..pages.len()).all(|index| page(chapter_dir, index).is_some()))
+}
+
+/// Deletes a chapter, e.g. one whose download was cancelled.
+pub async fn remove_chapter(chapter_dir: &Path) -> anyhow::Result<()> {
+    if chapter_dir.exists() {
+        smol::fs::remove_dir_all(chapter_dir).await?;
+    }
+    Ok(())
+}
+
+/// Bytes free for unprivileged users on the file system holding `path`, or its closest existing
+/// parent.
+pub fn free_space(path: &Path) -> anyhow::Result<u64> {
+    let path = path
+        .ancestors()
+        .find(|x| x.exists())
+        .context("no existing parent directory")?;
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stats = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stats` is only read after statvfs filled it in.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let stats = unsafe { stats.assume_init() };
+    #[allow(clippy::useless_conversion)]
+    Ok(u64::from(stats.f_bavail) * u64::from(stats.f_frsize))
+}
+
+/// Fails when saving more into `path` would leave less than [`MIN_FREE_SPACE`].
+pub fn ensure_space(path: &Path) -> anyhow::Result<()> {
+    let free = free_space(path)?;
+    if free < MIN_FREE_SPACE {
+        bail!(
+            "only {} MiB are free in {}, at least {} MiB have to stay free",
+            free / 1024 / 1024,
+            path.display(),
+            MIN_FREE_SPACE / 1024 / 1024
+        );
+    }
+    Ok(())
+}
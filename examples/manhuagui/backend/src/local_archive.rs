@@ -0,0 +1,563 @@
+//! Reads a comic library from disk. Every entry of the library directory is a series:
+//!
+//! ```text
+//! library/
+//!   Series A/
+//!     ComicInfo.xml      optional, applies to the whole series
+//!     Vol. 1.cbz
+//!     Vol. 2.cbr
+//!     Chapter 3/         a folder of images
+//!   Series B.cbz         a series with a single chapter
+//!   Series C/            images directly inside make the series its own chapter
+//! ```
+//!
+//! Pages and chapters are ordered naturally, so `2.jpg` comes before `10.jpg`. Metadata is taken
+//! from `ComicInfo.xml` when there is one.
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    fs::File,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use reqwest::Client;
+
+use crate::{
+    Capabilities, ImageUrl, MangaBackend, MangaStatus, MangasPage, Page, SChapter, SManga,
+    SearchFilter,
+    settings::{SettingField, Settings, SettingsSchema},
+};
+
+const IMAGE_EXTENSIONS: [&str; 7] = ["jpg", "jpeg", "png", "gif", "webp", "bmp", "avif"];
+
+#[derive(Debug)]
+pub struct LocalArchive {
+    base: PathBuf,
+}
+
+/// Something pages can be read from.
+#[derive(Debug, Clone)]
+enum Container {
+    Folder(PathBuf),
+    Zip(PathBuf),
+    Rar(PathBuf),
+}
+
+impl Container {
+    /// Archives are recognised by their magic bytes first, since a `.cbr` is often a zip.
+    fn open(path: &Path) -> Option<Self> {
+        if path.is_dir() {
+            return Some(Self::Folder(path.to_owned()));
+        }
+        let mut magic = [0; 4];
+        File::open(path).ok()?.read_exact(&mut magic).ok()?;
+        match &magic {
+            b"PK\x03\x04" => return Some(Self::Zip(path.to_owned())),
+            b"Rar!" => return Some(Self::Rar(path.to_owned())),
+            _ => {}
+        }
+        match extension(path).as_str() {
+            "cbz" | "zip" => Some(Self::Zip(path.to_owned())),
+            "cbr" | "rar" => Some(Self::Rar(path.to_owned())),
+            _ => None,
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Self::Folder(x) | Self::Zip(x) | Self::Rar(x) => x,
+        }
+    }
+
+    /// Every file, relative to the container. Folders are not descended into since nested
+    /// folders are chapters of their own.
+    fn entries(&self) -> anyhow::Result<Vec<String>> {
+        Ok(match self {
+            Self::Folder(path) => std::fs::read_dir(path)?
+                .filter_map(|x| x.ok())
+                .filter(|x| x.path().is_file())
+                .map(|x| x.file_name().to_string_lossy().to_string())
+                .collect(),
+            Self::Zip(path) => {
+                let archive = zip::ZipArchive::new(File::open(path)?)?;
+                archive
+                    .file_names()
+                    .filter_map(|x| x.ok())
+                    .filter(|x| !x.ends_with('/'))
+                    .map(|x| x.into_owned())
+                    .collect()
+            }
+            Self::Rar(path) => unrar::Archive::new(path)
+                .open_for_listing()?
+                .filter_map(|x| x.ok())
+                .filter(|x| x.is_file())
+                .map(|x| rar_name(&x.filename))
+                .collect(),
+        })
+    }
+
+    /// Image entries in reading order, skipping resource forks and hidden files.
+    fn images(&self) -> anyhow::Result<Vec<String>> {
+        let mut images = self
+            .entries()?
+            .into_iter()
+            .filter(|x| !x.starts_with("__MACOSX/"))
+            .filter(|x| {
+                let name = x.rsplit('/').next().unwrap_or(x);
+                !name.starts_with('.')
+                    && IMAGE_EXTENSIONS.contains(&extension(Path::new(name)).as_str())
+            })
+            .collect::<Vec<_>>();
+        images.sort_by(|a, b| natural_cmp(a, b));
+        Ok(images)
+    }
+
+    fn read(&self, entry: &str) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Folder(path) => {
+                if entry.contains('/') || entry.contains('\\') || entry == ".." {
+                    bail!("invalid entry {entry}");
+                }
+                Ok(std::fs::read(path.join(entry))?)
+            }
+            Self::Zip(path) => read_zip_entry(File::open(path)?, entry),
+            Self::Rar(path) => {
+                let mut archive = unrar::Archive::new(path).open_for_processing()?;
+                while let Some(header) = archive.read_header()? {
+                    if rar_name(&header.entry().filename) == entry {
+                        return Ok(header.read()?.0);
+                    }
+                    archive = header.skip()?;
+                }
+                bail!("{entry} not found in {}", path.display())
+            }
+        }
+    }
+
+    fn comic_info(&self) -> Option<ComicInfo> {
+        let entry = self
+            .entries()
+            .ok()?
+            .into_iter()
+            .find(|x| x.eq_ignore_ascii_case("comicinfo.xml"))?;
+        let xml = String::from_utf8(self.read(&entry).ok()?).ok()?;
+        match ComicInfo::parse(&xml) {
+            Ok(info) => Some(info),
+            Err(err) => {
+                println!(
+                    "ignoring invalid ComicInfo.xml in {}: {err:#}",
+                    self.path().display()
+                );
+                None
+            }
+        }
+    }
+}
+
+fn read_zip_entry(reader: impl Read + Seek, entry: &str) -> anyhow::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut file = archive.by_name(entry)?;
+    let mut bytes = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Rar entries use the separator of the system that created them.
+fn rar_name(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn stem(path: &Path) -> String {
+    if path.is_dir() {
+        path.file_name()
+    } else {
+        path.file_stem()
+    }
+    .map(|x| x.to_string_lossy().to_string())
+    .unwrap_or_default()
+}
+
+/// Compares runs of digits by their value and everything else case-insensitively.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    digits
+                };
+                let (x, y) = (take_number(&mut a), take_number(&mut b));
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let ordering = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// The fields of a `ComicInfo.xml` the app shows, see
+/// <https://anansi-project.github.io/docs/comicinfo/documentation>.
+#[derive(Debug, Clone, Default)]
+struct ComicInfo {
+    title: Option<String>,
+    series: Option<String>,
+    number: Option<String>,
+    summary: Option<String>,
+    writer: Option<String>,
+    penciller: Option<String>,
+    genre: Option<String>,
+    tags: Option<String>,
+    year: Option<String>,
+    month: Option<String>,
+    day: Option<String>,
+}
+
+impl ComicInfo {
+    fn parse(xml: &str) -> anyhow::Result<Self> {
+        let document = roxmltree::Document::parse(xml)?;
+        let mut info = Self::default();
+        for node in document
+            .root_element()
+            .children()
+            .filter(|x| x.is_element())
+        {
+            let Some(text) = node.text().map(str::trim).filter(|x| !x.is_empty()) else {
+                continue;
+            };
+            let field = match node.tag_name().name() {
+                "Title" => &mut info.title,
+                "Series" => &mut info.series,
+                "Number" => &mut info.number,
+                "Summary" => &mut info.summary,
+                "Writer" => &mut info.writer,
+                "Penciller" => &mut info.penciller,
+                "Genre" => &mut info.genre,
+                "Tags" => &mut info.tags,
+                "Year" => &mut info.year,
+                "Month" => &mut info.month,
+                "Day" => &mut info.day,
+                _ => continue,
+            };
+            *field = Some(text.to_owned());
+        }
+        Ok(info)
+    }
+
+    fn genres(&self) -> Option<String> {
+        let genres = [&self.genre, &self.tags]
+            .into_iter()
+            .flatten()
+            .flat_map(|x| x.split(','))
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>();
+        (!genres.is_empty()).then(|| genres.join(", "))
+    }
+
+    fn date(&self) -> Option<String> {
+        let year = self.year.as_ref()?;
+        Some(match (&self.month, &self.day) {
+            (Some(month), Some(day)) => format!("{year}-{month:0>2}-{day:0>2}"),
+            (Some(month), None) => format!("{year}-{month:0>2}"),
+            _ => year.clone(),
+        })
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+impl LocalArchive {
+    pub const ID: &'static str = "local";
+
+    #[must_use]
+    pub const fn new(path: PathBuf) -> Self {
+        Self { base: path }
+    }
+
+    pub fn schema() -> SettingsSchema {
+        SettingsSchema(vec![
+            SettingField::string(
+                "directory",
+                "Library directory",
+                Self::default().base.to_string_lossy(),
+            )
+            .with_description(
+                "One folder or archive per series, chapters are CBZ/ZIP/CBR files or image folders",
+            ),
+        ])
+    }
+
+    /// Expects settings already validated against [`Self::schema`].
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings
+            .string("directory")
+            .map_or_else(Self::default, |x| Self::new(PathBuf::from(x)))
+    }
+
+    /// Every series in the library, in natural order of their file names.
+    fn series(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut series = std::fs::read_dir(&self.base)
+            .with_context(|| format!("can't read library {}", self.base.display()))?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| {
+                !x.file_name()
+                    .is_some_and(|x| x.to_string_lossy().starts_with('.'))
+            })
+            .filter(|x| Container::open(x).is_some())
+            .collect::<Vec<_>>();
+        series.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        Ok(series)
+    }
+
+    /// The chapters of a series, in natural order.
+    fn containers(series: &Path) -> anyhow::Result<Vec<Container>> {
+        let container = Container::open(series).context("not a folder or archive")?;
+        if !matches!(container, Container::Folder(_)) {
+            return Ok(vec![container]);
+        }
+        let mut paths = std::fs::read_dir(series)?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .collect::<Vec<_>>();
+        paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        let mut containers = paths
+            .iter()
+            .filter(|x| x.is_dir() || ["cbz", "zip", "cbr", "rar"].contains(&extension(x).as_str()))
+            .filter_map(|x| Container::open(x))
+            .collect::<Vec<_>>();
+        if !container.images()?.is_empty() {
+            containers.insert(0, container);
+        }
+        Ok(containers)
+    }
+
+    /// A listing entry, without opening any archive.
+    fn summary(path: &Path) -> SManga {
+        SManga {
+            url: ImageUrl::LocalFile {
+                path: path.to_owned(),
+                entry: None,
+            },
+            title: stem(path),
+            thumbnail_url: None,
+            author: None,
+            description: None,
+            genre: None,
+            status: MangaStatus::Unknown,
+            last_updated_time: String::new(),
+        }
+    }
+
+    fn manga(path: &Path) -> anyhow::Result<SManga> {
+        let info = Container::open(path)
+            .and_then(|x| x.comic_info())
+            .or_else(|| {
+                Self::containers(path)
+                    .ok()?
+                    .iter()
+                    .find_map(Container::comic_info)
+            })
+            .unwrap_or_default();
+        Ok(SManga {
+            title: info.series.clone().unwrap_or_else(|| stem(path)),
+            author: info.writer.clone().or_else(|| info.penciller.clone()),
+            description: info.summary.clone(),
+            genre: info.genres(),
+            last_updated_time: info.date().unwrap_or_default(),
+            ..Self::summary(path)
+        })
+    }
+
+    fn chapters(series: &Path) -> anyhow::Result<Vec<SChapter>> {
+        let number_regex = regex::Regex::new(r"\d+(\.\d+)?")?;
+        Ok(Self::containers(series)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, container)| {
+                let info = container.comic_info().unwrap_or_default();
+                let name = stem(container.path());
+                let chapter_number = info
+                    .number
+                    .as_deref()
+                    .and_then(|x| x.parse().ok())
+                    .or_else(|| number_regex.find(&name)?.as_str().parse().ok())
+                    .unwrap_or((i + 1) as f32);
+                SChapter {
+                    url: ImageUrl::LocalFile {
+                        path: container.path().to_owned(),
+                        entry: None,
+                    },
+                    name: info.title.unwrap_or(name),
+                    chapter_number,
+                    date_upload: modified(container.path())
+                        .and_then(|x| x.duration_since(SystemTime::UNIX_EPOCH).ok())
+                        .map(|x| x.as_millis() as i64),
+                }
+            })
+            .collect())
+    }
+
+    fn path_of(url: &ImageUrl) -> anyhow::Result<&Path> {
+        match url {
+            ImageUrl::LocalFile { path, .. } => Ok(path),
+            _ => bail!("not a local file: {url:?}"),
+        }
+    }
+}
+
+impl Display for LocalArchive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Local library")
+    }
+}
+
+impl Default for LocalArchive {
+    fn default() -> Self {
+        Self::new(
+            std::env::home_dir()
+                .context("no home dir")
+                .unwrap()
+                .join("mangarr-library"),
+        )
+    }
+}
+
+#[async_trait]
+impl MangaBackend for LocalArchive {
+    fn id(&self) -> String {
+        Self::ID.to_owned()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            search: true,
+            popular: true,
+            latest: true,
+            ..Default::default()
+        }
+    }
+
+    fn settings_schema(&self) -> SettingsSchema {
+        Self::schema()
+    }
+
+    /// `id` is the file name of the series inside the library.
+    async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga> {
+        let path = self.base.join(id);
+        if id.contains(['/', '\\']) || !path.exists() {
+            bail!("no series {id} in {}", self.base.display());
+        }
+        smol::unblock(move || Self::manga(&path)).await
+    }
+
+    /// Matches titles case-insensitively, the whole library is a single page.
+    async fn search(
+        &self,
+        query: &str,
+        _page: u32,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<MangasPage> {
+        let query = query.trim().to_lowercase();
+        Ok(MangasPage {
+            mangas: self
+                .series()?
+                .iter()
+                .map(|x| Self::summary(x))
+                .filter(|x| x.title.to_lowercase().contains(&query))
+                .collect(),
+            has_next_page: false,
+        })
+    }
+
+    /// The whole library by name.
+    async fn popular(&self, _page: u32) -> anyhow::Result<MangasPage> {
+        self.search("", 1, &SearchFilter::default()).await
+    }
+
+    /// The whole library, most recently modified first.
+    async fn latest(&self, _page: u32) -> anyhow::Result<MangasPage> {
+        let mut series = self.series()?;
+        series.sort_by_key(|x| std::cmp::Reverse(modified(x)));
+        Ok(MangasPage {
+            mangas: series.iter().map(|x| Self::summary(x)).collect(),
+            has_next_page: false,
+        })
+    }
+
+    async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
+        let path = Self::path_of(&manga.url)?.to_owned();
+        smol::unblock(move || Self::chapters(&path)).await
+    }
+
+    async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
+        let path = Self::path_of(&chapter.url)?.to_owned();
+        smol::unblock(move || {
+            let container = Container::open(&path).context("not a folder or archive")?;
+            Ok(container
+                .images()?
+                .into_iter()
+                .enumerate()
+                .map(|(index, entry)| Page {
+                    index,
+                    url: String::new(),
+                    image_url: ImageUrl::LocalFile {
+                        path: path.clone(),
+                        entry: Some(entry),
+                    },
+                })
+                .collect())
+        })
+        .await
+    }
+
+    fn client(&self) -> Option<Client> {
+        None
+    }
+
+    fn read_local_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        let ImageUrl::LocalFile { path, entry } = url else {
+            bail!("not a local file: {url:?}");
+        };
+        match entry {
+            Some(entry) => Container::open(path)
+                .context("not a folder or archive")?
+                .read(entry),
+            None => Ok(std::fs::read(path)?),
+        }
+    }
+}
@@ -1,24 +1,28 @@
 mod bookshelf;
+mod downloads;
 mod manga_reader;
 mod message;
+mod updates;
 
-use std::{
-    collections::HashMap, fs::remove_dir_all, future::Future, path::PathBuf, pin::Pin,
-    process::exit, task::Poll,
-};
+use std::{collections::HashMap, future::Future, pin::Pin, process::exit, task::Poll};
 
 use anyhow::{Context, bail};
 use appload_client::{AppLoadBackend, Message};
 use async_compat::Compat;
 use async_trait::async_trait;
-use backend::MangaBackend;
-use bookshelf::BookShelf;
+use backend::{
+    SourceError, SourceRegistry, cache, credentials::CredentialStore, image_cache, library,
+    registry,
+};
+use bookshelf::{BookShelf, BookShelfKey};
 use futures::stream::{AbortHandle, Abortable, Aborted};
 use smol::future::block_on;
 
 use crate::{
+    downloads::Downloads,
     manga_reader::{MangaReader, Page},
     message::{RecvMessage, ReplierExt, SendMessage},
+    updates::UpdateChecker,
 };
 
 type BackendReplier = appload_client::BackendReplier<MyBackend>;
@@ -50,6 +54,10 @@ impl<T: Send + 'static> AbortableTask<T> {
     pub fn detach(self) {
         self.task.detach();
     }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
 }
 
 impl<T> Future for AbortableTask<T> {
@@ -64,6 +72,8 @@ impl<T> Future for AbortableTask<T> {
 fn main() {
     unsafe { std::env::set_var("SMOL_THREADS", "4") };
     unsafe { std::env::set_var("RUST_BACKTRACE", "1") };
+    cache::set_dir(cache::default_dir());
+    library::set_dir(library::default_dir());
 
     block_on(Compat::new(async {
         appload_client::AppLoad::new(MyBackend::new())
@@ -79,6 +89,8 @@ struct MyBackend {
     manga: MangaReader,
     handlers: HashMap<usize, AbortableTask<(usize, usize)>>,
     state: State,
+    updates: UpdateChecker,
+    downloads: Downloads,
 }
 
 macro_rules! init_functionality {
@@ -95,12 +107,20 @@ macro_rules! init_functionality {
 }
 impl MyBackend {
     fn new() -> Self {
-        Self {
-            bookshelf: BookShelf::new().unwrap(),
+        let backend = Self {
+            bookshelf: BookShelf::new().unwrap_or_else(|err| {
+                println!("can't load the bookshelf, starting with an empty one: {err:#}");
+                BookShelf::set_aside();
+                BookShelf::default()
+            }),
             manga: MangaReader::new(None, None).unwrap(),
             state: State::default(),
             handlers: HashMap::new(),
-        }
+            updates: UpdateChecker::default(),
+            downloads: Downloads::default(),
+        };
+        backend.updates.watch(&backend.bookshelf);
+        backend
     }
     #[allow(clippy::too_many_lines)]
     async fn handle_message(
@@ -113,6 +133,18 @@ impl MyBackend {
         match RecvMessage::try_from(message)? {
             RecvMessage::Connect => {
                 send_status!("connected frontend")?;
+                let sources = SourceRegistry::global()
+                    .read()
+                    .map_err(|_| anyhow::anyhow!("source registry poisoned"))?
+                    .sources()
+                    .to_vec();
+                functionality
+                    .send_typed_message(SendMessage::SourceList(sources))
+                    .await?;
+                functionality
+                    .send_typed_message(SendMessage::Capabilities(self.manga.api.capabilities()))
+                    .await?;
+                self.updates.start(functionality);
 
                 println!("A frontend has connected");
             }
@@ -198,7 +230,7 @@ impl MyBackend {
                 self.manga = *manga;
             }
             RecvMessage::SelectBackend(supported_backend) => {
-                let is_different = { dbg!(supported_backend.id()) != dbg!(self.manga.api.id()) };
+                let is_different = supported_backend.id() != self.manga.api.id();
                 if is_different {
                     let mut manga_reader = MangaReader::new(None, None)?;
                     manga_reader.api = supported_backend;
@@ -209,11 +241,12 @@ impl MyBackend {
                         *manga = Box::new(self.manga.clone());
                     }
                 }
+                functionality
+                    .send_typed_message(SendMessage::Capabilities(self.manga.api.capabilities()))
+                    .await?;
             }
             RecvMessage::Quit => {
-                if PathBuf::from("/tmp/mangarr").exists() {
-                    remove_dir_all("/tmp/mangarr")?;
-                }
+                image_cache::global().flush().await?;
                 exit(0);
             }
             RecvMessage::SaveActiveToBookShelf => {
@@ -228,17 +261,181 @@ impl MyBackend {
                     .clone();
                 self.manga = manga;
                 self.state = State::Reading;
+                // opening a series counts as seeing its new chapters
+                self.manga.updates.clear();
+                self.bookshelf.update(&key, |x| x.updates.clear()).await?;
                 self.manga.update_chapter().await?;
                 self.manga.send_details(functionality).await?;
             }
             RecvMessage::BookShelfView => {
                 self.state = State::Bookshelf;
             }
+            RecvMessage::GetSettings(source_id) if source_id == image_cache::SETTINGS_ID => {
+                functionality
+                    .send_typed_message(SendMessage::Settings {
+                        source_id,
+                        schema: image_cache::settings_schema(),
+                        values: image_cache::load_settings(),
+                    })
+                    .await?;
+                return Ok(());
+            }
+            RecvMessage::GetSettings(source_id) => {
+                let (schema, values) = {
+                    let registry = SourceRegistry::global()
+                        .read()
+                        .map_err(|_| anyhow::anyhow!("source registry poisoned"))?;
+                    let info = registry.get(&source_id).context("unknown source")?;
+                    (info.settings.clone(), info.load_settings())
+                };
+                functionality
+                    .send_typed_message(SendMessage::Settings {
+                        source_id,
+                        schema,
+                        values,
+                    })
+                    .await?;
+                return Ok(());
+            }
+            RecvMessage::UpdateSettings {
+                source_id,
+                settings,
+            } if source_id == image_cache::SETTINGS_ID => {
+                let settings = image_cache::settings_schema().validate(&settings)?;
+                settings.save(&source_id)?;
+                image_cache::global()
+                    .set_max_size(image_cache::max_size(&settings))
+                    .await?;
+                send_status!("settings saved")?;
+                return Ok(());
+            }
+            RecvMessage::UpdateSettings {
+                source_id,
+                settings,
+            } => {
+                let source = SourceRegistry::global()
+                    .write()
+                    .map_err(|_| anyhow::anyhow!("source registry poisoned"))?
+                    .update_settings(&source_id, &settings)?;
+                // already open manga keep the old instance otherwise
+                if self.manga.api.id() == source_id {
+                    self.manga.api = source.clone();
+                    functionality
+                        .send_typed_message(SendMessage::Capabilities(source.capabilities()))
+                        .await?;
+                }
+                self.bookshelf.replace_source(&source);
+                send_status!("settings saved")?;
+                return Ok(());
+            }
+            RecvMessage::SetCredentials {
+                source_id,
+                credentials,
+            } => {
+                let mut store = CredentialStore::load();
+                store.set(&source_id, credentials.clone());
+                store.save()?;
+                registry::source(&source_id)?.set_credentials(&credentials)?;
+                send_status!("credentials saved")?;
+                return Ok(());
+            }
+            RecvMessage::Search(request) => {
+                send_status!("searching")?;
+                let results = self
+                    .manga
+                    .api
+                    .search(&request.query, request.page, &request.filter)
+                    .await?;
+                functionality
+                    .send_typed_message(SendMessage::SearchResults {
+                        page: request.page,
+                        results,
+                    })
+                    .await?;
+                return Ok(());
+            }
+            RecvMessage::Popular(page) => {
+                let results = self.manga.api.popular(page).await?;
+                functionality
+                    .send_typed_message(SendMessage::SearchResults { page, results })
+                    .await?;
+                return Ok(());
+            }
+            RecvMessage::Latest(page) => {
+                let results = self.manga.api.latest(page).await?;
+                functionality
+                    .send_typed_message(SendMessage::SearchResults { page, results })
+                    .await?;
+                return Ok(());
+            }
+            RecvMessage::CheckUpdates => {
+                self.updates.check_now(functionality);
+                send_status!("checking the bookshelf for new chapters")?;
+                return Ok(());
+            }
+            RecvMessage::DownloadChapters(chapters) => {
+                let chapters = if chapters.is_empty() {
+                    (0..self.manga.chapters.len()).collect()
+                } else {
+                    chapters
+                };
+                self.downloads
+                    .enqueue(&self.manga, chapters, functionality)
+                    .await?;
+                return Ok(());
+            }
+            RecvMessage::PauseDownloads => {
+                self.downloads.pause(functionality).await;
+                return Ok(());
+            }
+            RecvMessage::ResumeDownloads => {
+                self.downloads.resume(functionality);
+                return Ok(());
+            }
+            RecvMessage::CancelDownloads(id) => {
+                self.downloads.cancel(id, functionality).await?;
+                return Ok(());
+            }
+            RecvMessage::Export { format, chapters } => {
+                let manga = self.manga.clone();
+                let functionality = functionality.clone();
+                spawn(async move {
+                    let message = match manga.export(format, chapters, &functionality).await {
+                        Ok(dir) => SendMessage::status(format!("exported to {}", dir.display())),
+                        Err(err) => {
+                            println!("exporting failed: {err:#}");
+                            SendMessage::Error(SourceError::from(err))
+                        }
+                    };
+                    if let Err(err) = functionality.send_typed_message(message).await {
+                        println!("can't report the export: {err:#}");
+                    }
+                })
+                .detach();
+                send_status!(format!("exporting {format}"))?;
+                return Ok(());
+            }
         }
         self.react_to_state(functionality).await?;
         Ok(())
     }
 
+    /// Moves the chapters the update checker found onto the bookshelf and the open manga.
+    async fn apply_updates(&mut self) -> anyhow::Result<()> {
+        for found in self.updates.take_found() {
+            if BookShelfKey::from_manga(&self.manga) == found.key {
+                // the prefetches are keyed by the old chapter indices
+                self.clear_download_handles().await;
+                self.manga
+                    .add_chapters(found.chapters.clone(), found.new.clone());
+            }
+            self.bookshelf
+                .update(&found.key, |x| x.add_chapters(found.chapters, found.new))
+                .await?;
+        }
+        Ok(())
+    }
+
     async fn clear_download_handles(&mut self) {
         for x in self.handlers.values_mut() {
             x.abort();
@@ -271,6 +468,7 @@ impl MyBackend {
                 self.manga
                     .save_to_disk(self.manga.current_page, functionality)
                     .await?;
+                self.manga.sync_progress();
 
                 if !self
                     .handlers
@@ -320,10 +518,9 @@ impl MyBackend {
                 search.send_details(functionality).await?;
                 search.send_page_information(functionality).await?;
                 search.save_to_disk(Page::default(), functionality).await?;
+                let (_, key) = search.get_url_with_key(Page::default())?;
                 functionality
-                    .send_typed_message(SendMessage::MangaPreview(
-                        search.get_url_with_path(Page::default())?.1,
-                    ))
+                    .send_typed_message(SendMessage::MangaPreview(image_cache::global().path(&key)))
                     .await?;
             }
             State::Bookshelf => {
@@ -359,21 +556,21 @@ impl Default for State {
 #[async_trait]
 impl AppLoadBackend for MyBackend {
     async fn handle_message(&mut self, functionality: &BackendReplier, message: Message) {
-        let v = self.handle_message(functionality, message);
-        v.await.unwrap();
-        // if let Err(err) = v.await {
-        // err.unwrap();
-        // let err = err
-        //     .chain()
-        //     .enumerate()
-        //     .map(|(i, x)| format!("{}:{x:#?}", i + 1))
-        //     .collect::<Vec<_>>()
-        //     .join("\n");
-        // panic!("{err:#?}")
-        // functionality
-        //     .send_typed_message(SendMessage::Error(format!("error: {err:#?}")))
-        //     .await
-        //     .expect("can't send message");
-        // }
+        if let Err(err) = self.apply_updates().await {
+            println!("can't add the new chapters: {err:#}");
+        }
+        let result = self.handle_message(functionality, message).await;
+        self.updates.watch(&self.bookshelf);
+        let Err(err) = result else {
+            return;
+        };
+        println!("{err:?}");
+        let message = match SourceError::from(err) {
+            SourceError::NeedsCredentials(needs) => SendMessage::NeedsCredentials(needs),
+            err => SendMessage::Error(err),
+        };
+        if let Err(err) = functionality.send_typed_message(message).await {
+            println!("can't report the error to the frontend: {err:#}");
+        }
     }
 }
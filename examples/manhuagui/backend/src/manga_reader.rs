@@ -1,49 +1,46 @@
 use std::{
     collections::{HashMap, HashSet},
-    hash::{DefaultHasher, Hash, Hasher},
-    io::Cursor,
-    path::{Path, PathBuf},
+    hash::Hash,
+    path::PathBuf,
     sync::Arc,
 };
 
 use anyhow::{Context, bail};
 use backend::{
-    Backend, ImageUrl, SChapter, SManga,
-    manhuagui::{Manhuagui, Preferences},
+    ImageUrl, SChapter, SManga, Source,
+    export::{self, ExportFormat},
+    image_cache, library,
+    manhuagui::Manhuagui,
+    registry,
 };
-use futures::{StreamExt, TryStreamExt, stream};
-use palette::{Clamp, IntoColor, Oklch, Srgb, encoding::srgb};
+use futures::{StreamExt, stream};
 use photon_rs::{
-    PhotonImage, Rgb, Rgba,
-    colour_spaces::{saturate_hsluv, saturate_hsv, saturate_lch},
-    effects,
-    helpers::save_dyn_image,
+    PhotonImage,
+    colour_spaces::saturate_hsluv,
     monochrome,
-    native::{open_image_from_bytes, save_image},
+    native::open_image_from_bytes,
     transform::{SamplingFilter, resize},
 };
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use smol::{
-    Task,
-    fs::{self, File, create_dir},
-    io::AsyncWriteExt,
-    lock::RwLock,
-};
+use smol::{fs, lock::RwLock};
 
 use crate::{
     AbortableTask, BackendReplier,
     message::{ReplierExt, SendMessage},
     spawn,
+    updates::ChapterUpdate,
 };
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MangaReader {
-    pub api: Arc<Backend>,
+    pub api: Source,
     pub details: Arc<SManga>,
     pub chapters: Arc<[SChapter]>,
     pub pages: HashMap<usize, Arc<[ImageUrl]>>,
     pub current_page: Page,
+    /// Chapters the update checker found, until the manga is opened again.
+    #[serde(default)]
+    pub updates: Vec<ChapterUpdate>,
     #[serde(skip)]
     download_manager: Arc<RwLock<DownloadManager>>,
     #[serde(skip)]
@@ -80,12 +77,12 @@ impl Page {
 
 impl MangaReader {
     pub fn new(
-        api: impl Into<Option<Arc<Backend>>>,
+        api: impl Into<Option<Source>>,
         params: impl Into<Option<(SManga, Vec<SChapter>, Vec<ImageUrl>, Page)>>,
     ) -> anyhow::Result<Self> {
         let api = match api.into() {
             Some(x) => x,
-            None => Arc::new(Manhuagui::new(Preferences::default())?.into()),
+            None => registry::source(Manhuagui::ID)?,
         };
         let manga_reader = if let Some((details, chapters, pages, active)) = params.into() {
             Self {
@@ -94,6 +91,7 @@ impl MangaReader {
                 chapters: chapters.into(),
                 pages: HashMap::from([(0, pages.into())]),
                 current_page: active,
+                updates: Vec::new(),
                 download_manager: Default::default(),
                 chapters_manager: Default::default(),
             }
@@ -104,6 +102,7 @@ impl MangaReader {
                 chapters: Default::default(),
                 pages: Default::default(),
                 current_page: Default::default(),
+                updates: Vec::new(),
                 download_manager: Default::default(),
                 chapters_manager: Default::default(),
             }
@@ -152,6 +151,35 @@ impl MangaReader {
             .await?;
         Ok(())
     }
+    /// Switches to a newer chapter list of the same series. Chapters are matched by url, so the
+    /// page lists already fetched and the chapter being read survive a reordered list.
+    pub fn add_chapters(&mut self, chapters: Arc<[SChapter]>, new: Vec<ChapterUpdate>) {
+        let old = self.chapters.clone();
+        let index_of = |index: usize| {
+            let url = &old.get(index)?.url;
+            chapters.iter().position(|x| x.url == *url)
+        };
+        let Some(current) = index_of(self.current_page.chapter) else {
+            println!(
+                "the chapter being read of {} is gone, keeping its chapter list",
+                self.details.title
+            );
+            return;
+        };
+        self.pages = std::mem::take(&mut self.pages)
+            .into_iter()
+            .filter_map(|(index, pages)| Some((index_of(index)?, pages)))
+            .collect();
+        self.current_page.chapter = current;
+        self.chapters = chapters;
+        // the prefetched page lists are keyed by the old indices
+        self.chapters_manager = Default::default();
+        let new = new
+            .into_iter()
+            .filter(|x| !self.updates.iter().any(|y| y.url == x.url))
+            .collect::<Vec<_>>();
+        self.updates.extend(new);
+    }
     pub fn pages(&self) -> &[ImageUrl] {
         &self.pages[&self.current_page.chapter]
     }
@@ -220,11 +248,10 @@ impl MangaReader {
                 .collect::<Vec<_>>()
                 .await;
 
-            // reason: we only discard the `Aborted` case of error
-            #[allow(clippy::manual_flatten)]
+            // aborted downloads are expected, failed ones only lose the prefetch
             for x in results {
-                if let Ok(x) = x {
-                    x.expect("manga download issue");
+                if let Ok(Err(err)) = x {
+                    println!("prefetching chapter failed: {err:#}");
                 }
             }
             (current, target)
@@ -258,17 +285,31 @@ impl MangaReader {
                 .collect::<Vec<_>>()
                 .await;
 
-            // reason: we only discard the `Aborted` case of error
-            #[allow(clippy::manual_flatten)]
+            // aborted downloads are expected, failed ones only lose the prefetch
             for x in results {
-                if let Ok(x) = x {
-                    x.expect("manga download issue");
+                if let Ok(Err(err)) = x {
+                    println!("prefetching chapter failed: {err:#}");
                 }
             }
         })
         .detach();
     }
 
+    /// Reports the current page to the source in the background, failures are only logged.
+    pub fn sync_progress(&self) {
+        let Some(chapter) = self.chapters.get(self.current_page.chapter).cloned() else {
+            return;
+        };
+        let api = self.api.clone();
+        let (page, pages) = (self.current_page.page, self.pages_len());
+        spawn(async move {
+            if let Err(err) = api.sync_progress(&chapter, page, pages).await {
+                println!("syncing read progress failed: {err:#}");
+            }
+        })
+        .detach();
+    }
+
     pub async fn clear_download_managear(&self) {
         self.download_manager.write().await.clear();
     }
@@ -284,59 +325,42 @@ impl MangaReader {
             ))
             .await?;
 
-        if !PathBuf::from("/tmp/mangarr").exists() {
-            create_dir("/tmp/mangarr/").await?;
-        }
-        if !PathBuf::from("/tmp/mangarr/preview").exists() {
-            create_dir("/tmp/mangarr/preview").await?;
-        }
-
-        let (url, path, part) = self.get_url_with_path(page)?;
-
-        if !self.download_manager.read().await.contains(&page) && (part.exists() || !path.exists())
-        {
-            File::create(&part).await?;
-
-            self.download_manager.write().await.insert(page);
-
-            let image = self.save_page(url, &path).await?;
-            self.generate_scaled_version(&image, page).await?;
-
+        let (url, key) = self.get_url_with_key(page)?;
+        let path = if let Some(path) = image_cache::global().get(&key).await {
+            path
+        } else {
+            // another task is preparing the page and reports it when done
+            if !self.download_manager.write().await.insert(page) {
+                return Ok(());
+            }
+            let saved = self.save_page(page, url, &key).await;
             self.download_manager.write().await.remove(&page);
+            saved?
+        };
 
-            fs::remove_file(&part).await?;
-        }
-
-        if path.exists() && !part.exists() {
-            functionality
-                .send_typed_message(SendMessage::PageModify {
-                    chapter: self.current_page.chapter + 1,
-                    page: page.page,
-                    path,
-                })
-                .await?;
-        }
+        functionality
+            .send_typed_message(SendMessage::PageModify {
+                chapter: self.current_page.chapter + 1,
+                page: page.page,
+                path,
+            })
+            .await?;
 
         Ok(())
     }
-    pub async fn generate_scaled_version(
-        &self,
-        original_image: &PhotonImage,
-        page: Page,
-    ) -> anyhow::Result<()> {
-        let image = resize(original_image, 405, 660, SamplingFilter::Lanczos3);
 
-        let (_, mut path, _) = self.get_url_with_path(page)?;
-        path = PathBuf::from("/tmp/mangarr/preview").join(path.file_name().context("no")?);
-
-        if !path.exists() {
-            let bytes = image.get_bytes();
-            smol::fs::write(path, bytes).await?;
-        }
-        Ok(())
+    /// Where `chapter` is saved when it was downloaded for offline reading.
+    fn library_dir(&self, chapter: usize) -> Option<PathBuf> {
+        library::chapter_dir(&self.api.id(), &self.details, self.chapters.get(chapter)?)
     }
 
     async fn download_pages_url(&self, chapter: usize) -> anyhow::Result<Arc<[ImageUrl]>> {
+        if let Some(pages) = self
+            .library_dir(chapter)
+            .and_then(|x| library::load_pages(&x))
+        {
+            return Ok(pages.into());
+        }
         let s_chapter = &self.chapters[chapter];
         let pages = self
             .api
@@ -348,67 +372,100 @@ impl MangaReader {
         Ok(pages)
     }
 
-    pub fn get_url_with_path(&self, page: Page) -> anyhow::Result<(&ImageUrl, PathBuf, PathBuf)> {
+    /// The image of `page` in the current chapter and its key in the [`image_cache`].
+    pub fn get_url_with_key(&self, page: Page) -> anyhow::Result<(&ImageUrl, String)> {
         let Some(url) = self.pages().get(page.page) else {
             bail!("out of bounds");
         };
+        Ok((url, image_cache::key(&self.api.id(), url)))
+    }
 
-        let mut hasher = DefaultHasher::new();
-        url.hash(&mut hasher);
-        let hashed_value = hasher.finish();
+    /// Prepares the image of `page` for the screen and caches it with its preview.
+    async fn save_page(&self, page: Page, url: &ImageUrl, key: &str) -> anyhow::Result<PathBuf> {
+        let downloaded = self
+            .library_dir(page.chapter)
+            .and_then(|x| library::page(&x, page.page));
+        let bytes = match downloaded {
+            Some(downloaded) => fs::read(downloaded).await?,
+            None => self.api.fetch_image(url).await?,
+        };
+        let image = process_page(&bytes)?;
+        let preview = resize(&image, 405, 660, SamplingFilter::Lanczos3);
 
-        let ps = PathBuf::from(format!("/tmp/mangarr/pic{hashed_value}.png"));
-        Ok((url, ps.clone(), ps.with_extension("part")))
+        image_cache::global()
+            .insert(key, &image.get_bytes(), &preview.get_bytes())
+            .await
     }
 
-    async fn save_page(
+    /// Packages downloaded chapters, processed like the pages being read, into the
+    /// [`export::default_dir`] of `format`. No chapters means every downloaded one.
+    pub async fn export(
         &self,
-        url: &ImageUrl,
-        path: impl AsRef<Path> + Send,
-    ) -> anyhow::Result<PhotonImage> {
-        let path = path.as_ref();
-        let mut image = match url {
-            ImageUrl::Web(url) => {
-                let bytes = self
-                    .api
-                    .client()
-                    .unwrap()
-                    .get(url)
-                    .send()
-                    .await?
-                    .error_for_status()?
-                    .bytes()
-                    .await?;
+        format: ExportFormat,
+        chapters: Vec<usize>,
+        functionality: &BackendReplier,
+    ) -> anyhow::Result<PathBuf> {
+        let dir = export::default_dir(format).context("no home directory to export to")?;
+        let chapters = if chapters.is_empty() {
+            (0..self.chapters.len())
+                .filter(|&x| {
+                    self.library_dir(x)
+                        .is_some_and(|x| library::is_downloaded(&x))
+                })
+                .collect()
+        } else {
+            chapters
+        };
+        if chapters.is_empty() {
+            bail!(
+                "download chapters of {} before exporting them",
+                self.details.title
+            );
+        }
 
-                open_image_from_bytes(&bytes)?
-            }
-            ImageUrl::LocalEpub {
-                epub_path,
-                img_path,
-            } => {
-                let Backend::Epub(x) = &*self.api else {
-                    unreachable!()
-                };
-                let bytes = x.fetch_img(epub_path.clone(), &img_path.clone().unwrap())?;
-                open_image_from_bytes(&bytes)?
+        for index in chapters {
+            let chapter = self
+                .chapters
+                .get(index)
+                .with_context(|| format!("there is no chapter {}", index + 1))?;
+            let library_dir = self
+                .library_dir(index)
+                .filter(|x| library::is_downloaded(x))
+                .with_context(|| format!("download {} before exporting it", chapter.name))?;
+            let pages = library::load_pages(&library_dir).unwrap_or_default();
+            let mut images = Vec::with_capacity(pages.len());
+            for page in 0..pages.len() {
+                let bytes = fs::read(library::page_path(&library_dir, page)).await?;
+                images.push(process_page(&bytes)?.get_bytes());
             }
-        };
-        let is_almost_grayscale = image.get_raw_pixels().chunks_exact(3).all(|x| {
-            let [r, g, b] = *x else {
-                unreachable!("somehow not equal to 3");
-            };
-            r.abs_diff(g) < 3 && g.abs_diff(b) < 3 && r.abs_diff(b) < 3
-        });
 
-        if is_almost_grayscale {
-            monochrome::grayscale(&mut image);
-        } else {
-            saturate_hsluv(&mut image, 0.3);
+            let path = format.path(&dir, &self.details, index + 1, chapter);
+            let (manga, chapter) = (self.details.clone(), chapter.clone());
+            let written = path.clone();
+            smol::unblock(move || format.write(&written, &manga, &chapter, &images)).await?;
+            functionality
+                .send_typed_message(SendMessage::status(format!("exported {}", path.display())))
+                .await?;
         }
+        Ok(dir)
+    }
+}
 
-        let bytes = image.get_bytes();
-        smol::fs::write(path, bytes).await?;
+/// Makes pages easier to read on the e-ink screen: nearly gray pages become pure grayscale and
+/// coloured ones are saturated.
+fn process_page(bytes: &[u8]) -> anyhow::Result<PhotonImage> {
+    let mut image = open_image_from_bytes(bytes)?;
+    let is_almost_grayscale = image.get_raw_pixels().chunks_exact(3).all(|x| {
+        let [r, g, b] = *x else {
+            unreachable!("somehow not equal to 3");
+        };
+        r.abs_diff(g) < 3 && g.abs_diff(b) < 3 && r.abs_diff(b) < 3
+    });
 
-        Ok(image)
+    if is_almost_grayscale {
+        monochrome::grayscale(&mut image);
+    } else {
+        saturate_hsluv(&mut image, 0.3);
     }
+    Ok(image)
 }
@@ -1,39 +1,115 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
 /// This code is based on the Manhuagui extension for Tachiyomi.
 /// Source: <https://github.com/keiyoushi/extensions-source/blob/main/src/zh/manhuagui/src/eu/kanade/tachiyomi/extension/zh/manhuagui/Manhuagui.ktb>
 use anyhow::{Context, bail};
+use async_trait::async_trait;
+use futures::future::join_all;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 use regex::Regex;
 use reqwest::{
-    Client,
+    Client, Url,
+    cookie::Jar,
     header::{HeaderMap, HeaderValue, REFERER, USER_AGENT},
 };
 use scraper::Html;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-
-use crate::{ImageUrl, MangaBackend, MangaStatus, Page, SChapter, SManga};
+use serde::{Deserialize, Serialize};
+use smol::lock::OnceCell;
+
+use crate::{
+    Capabilities, Genre, ImageUrl, MangaBackend, MangaStatus, MangasPage, Page, SChapter, SManga,
+    SearchFilter,
+    cache::{self, CacheKind},
+    http::{self, Policy},
+    settings::{SettingField, Settings, SettingsSchema},
+};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone)]
 pub struct Manhuagui {
     pub name: String,
     pub lang: String,
     base_url: String,
-    image_server: [String; 2],
-
-    #[serde(skip, default = "deserialize_client")]
+    image_servers: Arc<ImageServers>,
+    preferences: Preferences,
     client: Client,
 }
 
-fn deserialize_client() -> Client {
-    Manhuagui::build_client(Preferences::default()).unwrap()
-}
-
 impl Display for Manhuagui {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Manhuagui")
     }
 }
 
+/// Image hosts serving the same files, fastest first once ranked. The order is saved with the
+/// [`cache`], so after a restart the servers are only probed again once the ranking is older than
+/// [`Manhuagui::RANKING_TTL`].
+#[derive(Debug, Default)]
+struct ImageServers {
+    ranking: RwLock<Ranking>,
+    ranked: OnceCell<()>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct Ranking {
+    hosts: Vec<String>,
+    /// Unix time in seconds of the last probe.
+    ranked_at: i64,
+}
+
+impl Ranking {
+    fn path() -> Option<std::path::PathBuf> {
+        cache::source_file(Manhuagui::ID, "image-servers.json")
+    }
+
+    /// The saved order of the `known` hosts, any host it lacks goes last.
+    fn load(known: &[&str]) -> Self {
+        let saved = Self::path()
+            .and_then(|x| std::fs::read(x).ok())
+            .and_then(|x| serde_json::from_slice::<Self>(&x).ok())
+            .unwrap_or_default();
+        let mut hosts = saved
+            .hosts
+            .into_iter()
+            .filter(|x| known.contains(&x.as_str()))
+            .collect::<Vec<_>>();
+        for host in known {
+            if !hosts.iter().any(|x| x == host) {
+                hosts.push((*host).to_owned());
+            }
+        }
+        Self {
+            hosts,
+            ranked_at: saved.ranked_at,
+        }
+    }
+
+    /// Does nothing while the cache is off.
+    fn save(&self) {
+        let Some(path) = Self::path() else {
+            return;
+        };
+        let write = || {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, serde_json::to_vec(self)?)?;
+            anyhow::Ok(())
+        };
+        if let Err(err) = write() {
+            println!("can't save the image server ranking: {err:#}");
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        let age = chrono::Utc::now().timestamp() - self.ranked_at;
+        (0..Manhuagui::RANKING_TTL.as_secs() as i64).contains(&age)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Preferences {
     pub show_r18: bool,
@@ -41,6 +117,31 @@ pub struct Preferences {
     pub use_mirror_url: bool,
 }
 
+impl Preferences {
+    pub fn schema() -> SettingsSchema {
+        SettingsSchema(vec![
+            SettingField::bool("show_r18", "Show R18 series", true)
+                .with_description("Sends the isAdult cookie so adult chapter lists are shown."),
+            SettingField::bool("show_zh_hant_website", "Traditional Chinese website", true),
+            SettingField::bool("use_mirror_url", "Use the mhgui.com mirror", false),
+        ])
+    }
+
+    /// Expects settings already validated against [`Self::schema`].
+    pub fn from_settings(settings: &Settings) -> Self {
+        let default = Self::default();
+        Self {
+            show_r18: settings.bool("show_r18").unwrap_or(default.show_r18),
+            show_zh_hant_website: settings
+                .bool("show_zh_hant_website")
+                .unwrap_or(default.show_zh_hant_website),
+            use_mirror_url: settings
+                .bool("use_mirror_url")
+                .unwrap_or(default.use_mirror_url),
+        }
+    }
+}
+
 impl Default for Preferences {
     fn default() -> Self {
         Self {
@@ -68,13 +169,79 @@ struct Sl {
     m: String,
 }
 
+#[async_trait]
 impl MangaBackend for Manhuagui {
+    fn id(&self) -> String {
+        Self::ID.to_owned()
+    }
+    fn settings_schema(&self) -> SettingsSchema {
+        Preferences::schema()
+    }
     fn client(&self) -> std::option::Option<reqwest::Client> {
         self.client.clone().into()
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            search: true,
+            popular: true,
+            latest: true,
+            status_filter: true,
+            genres: Self::GENRES
+                .iter()
+                .map(|(id, name)| Genre::new(*id, *name))
+                .collect(),
+        }
+    }
+    async fn search(
+        &self,
+        query: &str,
+        page: u32,
+        filter: &SearchFilter,
+    ) -> anyhow::Result<MangasPage> {
+        // keyword search ignores filters on the site itself, so filters without a keyword
+        // browse the category listing instead
+        if query.trim().is_empty() {
+            let params = [
+                filter.genre.clone(),
+                match filter.status {
+                    Some(MangaStatus::Ongoing) => Some("lianzai".to_owned()),
+                    Some(MangaStatus::Completed) => Some("wanjie".to_owned()),
+                    Some(MangaStatus::Unknown) | None => None,
+                },
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("_");
+            let url = if params.is_empty() {
+                format!("{}/list/view_p{page}.html", self.base_url)
+            } else {
+                format!("{}/list/{params}/view_p{page}.html", self.base_url)
+            };
+            return self.fetch_listing(&url).await;
+        }
+        let query = utf8_percent_encode(query.trim(), NON_ALPHANUMERIC);
+        let url = format!("{}/s/{query}_p{page}.html", self.base_url);
+        let body = http::send(self.client.get(&url))
+            .await?
+            .error_for_status()?;
+        let document = Html::parse_document(&body.text().await?);
+        Ok(Self::parse_search_results(&document))
+    }
+    async fn popular(&self, page: u32) -> anyhow::Result<MangasPage> {
+        let url = format!("{}/list/view_p{page}.html", self.base_url);
+        self.fetch_listing(&url).await
+    }
+    async fn latest(&self, page: u32) -> anyhow::Result<MangasPage> {
+        let url = format!("{}/list/update_p{page}.html", self.base_url);
+        self.fetch_listing(&url).await
+    }
     async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga> {
         let url = format!("{}/comic/{}", self.base_url, id);
-        let response = self.client.get(&url).send().await?.error_for_status()?;
+        let request = self.client.get(&url);
+        let response = cache::send(Self::ID, CacheKind::Details, request).await?;
+        let response = response.error_for_status()?;
         let body = response.text().await?;
         let document = Html::parse_document(&body);
 
@@ -84,63 +251,305 @@ impl MangaBackend for Manhuagui {
 
     async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
         let manga_url = self.chapter_url(manga);
-        let response = self.client.get(manga_url).send().await?;
+        let request = self.client.get(manga_url);
+        let response = cache::send(Self::ID, CacheKind::Chapters, request).await?;
+        let response = response.error_for_status()?;
         let body = response.text().await?;
-        let document = Html::parse_document(&body);
-
-        let mut chapters = Vec::new();
+        self.parse_chapters(&Html::parse_document(&body))
+    }
 
-        let viewstate_selector = Selector::parse("#__VIEWSTATE");
-        let erroraudit_show_selector = Selector::parse("#erroraudit_show");
-        if let Some(hidden_encrypted_chapter_list) = document.select(&viewstate_selector).next() {
-            if let Some(val) = hidden_encrypted_chapter_list.value().attr("value") {
-                let js_decode_func = Self::JS_DECODE_FUNC;
-                let decoded_hidden_chapter_list = quick_js::Context::new()?
-                    .eval(&format!(
-                        "{js_decode_func}LZString.decompressFromBase64('{val}');",
-                    ))?
-                    .as_str()
-                    .context("not string")?
-                    .to_owned();
-
-                let hidden_chapter_list = Html::parse_fragment(&decoded_hidden_chapter_list);
-
-                if let Some(error_audit_show) = document.select(&erroraudit_show_selector).next() {
-                    // document = document.replacen(error_audit_show, &hidden_chapter_list, 1);
-                    // TODO: Implement a proper way to replace nodes in the document
-                    println!("R18 content detected, but node replacement is not implemented yet.");
+    async fn fetch_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        let ImageUrl::Web(url) = url else {
+            bail!("not a web image: {url:?}");
+        };
+        let hosts = self.image_servers().await;
+        let Some(path) = hosts
+            .iter()
+            .find_map(|host| url.strip_prefix(host.as_str()))
+        else {
+            return self.fetch_bytes(url).await;
+        };
+        let mut last_error = None;
+        for host in &hosts {
+            match self.fetch_bytes(&format!("{host}{path}")).await {
+                Ok(bytes) => {
+                    if last_error.is_some() {
+                        self.promote_image_server(host);
+                    }
+                    return Ok(bytes);
+                }
+                Err(err) => {
+                    println!("image server {host} failed: {err:#}");
+                    last_error = Some(err);
                 }
             }
         }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("no image servers")))
+    }
 
-        let latest_chapter_selector =
-            Selector::parse("div.book-detail > ul.detail-list > li.status > span > a.blue");
-        let latest_chapter_href = document
-            .select(&latest_chapter_selector)
+    async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
+        let manga_url = self.page_url(chapter);
+        let request = self.client.get(manga_url);
+        let response = cache::send(Self::ID, CacheKind::Pages, request).await?;
+        let body = response.text().await?;
+        let image_server = self
+            .image_servers()
+            .await
+            .into_iter()
             .next()
-            .and_then(|el| el.value().attr("href"))
-            .map(String::from);
+            .context("no image servers")?;
+        self.parse_pages(&body, &image_server)
+    }
+}
 
-        let ch_num_regex = Regex::new(r"\d+").unwrap();
+// should only be used for craetion of constly parsed values
+struct Selector;
+
+impl Selector {
+    #[must_use]
+    pub fn parse(s: &str) -> scraper::Selector {
+        scraper::Selector::parse(s)
+            .map_err(|x| anyhow::anyhow!("{x:#?}"))
+            .expect("invalid input")
+    }
+}
+impl Manhuagui {
+    pub const ID: &'static str = "manhuagui";
+
+    const JS_DECODE_FUNC: &'static str = r#"
+        var LZString=(function(){var f=String.fromCharCode;var keyStrBase64="ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/=";var baseReverseDic={};function getBaseValue(alphabet,character){if(!baseReverseDic[alphabet]){baseReverseDic[alphabet]={};for(var i=0;i<alphabet.length;i++){baseReverseDic[alphabet][alphabet.charAt(i)]=i}}return baseReverseDic[alphabet][character]}var LZString={decompressFromBase64:function(input){if(input==null)return"";if(input=="")return null;return LZString._0(input.length,32,function(index){return getBaseValue(keyStrBase64,input.charAt(index))})},_0:function(length,resetValue,getNextValue){var dictionary=[],next,enlargeIn=4,dictSize=4,numBits=3,entry="",result=[],i,w,bits,resb,maxpower,power,c,data={val:getNextValue(0),position:resetValue,index:1};for(i=0;i<3;i+=1){dictionary[i]=i}bits=0;maxpower=Math.pow(2,2);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}switch(next=bits){case 0:bits=0;maxpower=Math.pow(2,8);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}c=f(bits);break;case 1:bits=0;maxpower=Math.pow(2,16);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}c=f(bits);break;case 2:return""}dictionary[3]=c;w=c;result.push(c);while(true){if(data.index>length){return""}bits=0;maxpower=Math.pow(2,numBits);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}switch(c=bits){case 0:bits=0;maxpower=Math.pow(2,8);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}dictionary[dictSize++]=f(bits);c=dictSize-1;enlargeIn--;break;case 1:bits=0;maxpower=Math.pow(2,16);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}dictionary[dictSize++]=f(bits);c=dictSize-1;enlargeIn--;break;case 2:return result.join('')}if(enlargeIn==0){enlargeIn=Math.pow(2,numBits);numBits++}if(dictionary[c]){entry=dictionary[c]}else{if(c===dictSize){entry=w+w.charAt(0)}else{return null}}result.push(entry);dictionary[dictSize++]=w+entry.charAt(0);enlargeIn--;w=entry;if(enlargeIn==0){enlargeIn=Math.pow(2,numBits);numBits++}}}};return LZString})();String.prototype.splic=function(f){return LZString.decompressFromBase64(this).split(f)};
+        "#;
+    const GENRES: [(&'static str, &'static str); 38] = [
+        ("rexue", "热血"),
+        ("maoxian", "冒险"),
+        ("mohuan", "魔幻"),
+        ("shengui", "神鬼"),
+        ("gaoxiao", "搞笑"),
+        ("mengxi", "萌系"),
+        ("aiqing", "爱情"),
+        ("kehuan", "科幻"),
+        ("mofa", "魔法"),
+        ("gedou", "格斗"),
+        ("wuxia", "武侠"),
+        ("jizhan", "机战"),
+        ("zhanzheng", "战争"),
+        ("jingji", "竞技"),
+        ("tiyu", "体育"),
+        ("xiaoyuan", "校园"),
+        ("shenghuo", "生活"),
+        ("lizhi", "励志"),
+        ("lishi", "历史"),
+        ("weiniang", "伪娘"),
+        ("zhainan", "宅男"),
+        ("funv", "腐女"),
+        ("danmei", "耽美"),
+        ("baihe", "百合"),
+        ("hougong", "后宫"),
+        ("zhiyu", "治愈"),
+        ("meishi", "美食"),
+        ("tuili", "推理"),
+        ("xuanyi", "悬疑"),
+        ("kongbu", "恐怖"),
+        ("sige", "四格"),
+        ("zhichang", "职场"),
+        ("zhentan", "侦探"),
+        ("shehui", "社会"),
+        ("yinyue", "音乐"),
+        ("wudao", "舞蹈"),
+        ("zazhi", "杂志"),
+        ("heidao", "黑道"),
+    ];
+    const IMAGE_SERVERS: [&str; 2] = ["https://i.hamreus.com", "https://cf.hamreus.com"];
+
+    /// How long a latency ranking of the image servers is trusted.
+    const RANKING_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+    /// Gives up on an image server quickly since the next one is tried anyway.
+    const IMAGE_POLICY: Policy = Policy {
+        retries: 1,
+        timeout: Duration::from_secs(15),
+        ..Policy::DEFAULT
+    };
+
+    /// A server too slow to answer a HEAD request is ranked last instead of waited for.
+    const PROBE_POLICY: Policy = Policy {
+        retries: 0,
+        timeout: Duration::from_secs(5),
+        ..Policy::DEFAULT
+    };
+
+    pub fn new(preferences: Preferences) -> anyhow::Result<Self> {
+        let base_host = if preferences.use_mirror_url {
+            "mhgui.com"
+        } else {
+            "manhuagui.com"
+        }
+        .to_owned();
+
+        let base_url = if preferences.show_zh_hant_website {
+            format!("https://tw.{base_host}")
+        } else {
+            format!("https://www.{base_host}")
+        };
+
+        // the site blocks clients that browse faster than a person, the image servers are laxer
+        http::limit_host(
+            base_url.trim_start_matches("https://"),
+            Duration::from_millis(1000),
+        );
+        for host in Self::IMAGE_SERVERS {
+            http::limit_host(
+                host.trim_start_matches("https://"),
+                Duration::from_millis(200),
+            );
+        }
+
+        let ranking = Ranking::load(&Self::IMAGE_SERVERS);
+        let image_servers = ImageServers {
+            ranked: if ranking.is_fresh() {
+                OnceCell::from(())
+            } else {
+                OnceCell::new()
+            },
+            ranking: RwLock::new(ranking),
+        };
+
+        let client = Self::build_client(preferences)?;
 
+        Ok(Self {
+            name: String::from("漫画柜"),
+            lang: String::from("zh"),
+            base_url,
+            image_servers: Arc::new(image_servers),
+            preferences,
+            client,
+        })
+    }
+
+    async fn image_servers(&self) -> Vec<String> {
+        self.image_servers
+            .ranked
+            .get_or_init(|| self.rank_image_servers())
+            .await;
+        self.image_servers.ranking.read().unwrap().hosts.clone()
+    }
+
+    /// Orders the image servers by how fast they answer, unreachable ones last.
+    async fn rank_image_servers(&self) {
+        let hosts = self.image_servers.ranking.read().unwrap().hosts.clone();
+        let latencies = join_all(hosts.iter().map(|host| async move {
+            let start = Instant::now();
+            let response = http::send_with(self.client.head(host), &Self::PROBE_POLICY).await;
+            response.ok().map(|_| start.elapsed())
+        }))
+        .await;
+        let mut ranked = hosts.into_iter().zip(latencies).collect::<Vec<_>>();
+        ranked.sort_by_key(|(_, latency)| latency.unwrap_or(Duration::MAX));
+        println!("image servers ranked by latency: {ranked:?}");
+        let mut ranking = self.image_servers.ranking.write().unwrap();
+        *ranking = Ranking {
+            hosts: ranked.into_iter().map(|(host, _)| host).collect(),
+            ranked_at: chrono::Utc::now().timestamp(),
+        };
+        ranking.save();
+    }
+
+    /// Moves a server that just worked in front of one that failed.
+    fn promote_image_server(&self, host: &str) {
+        let mut ranking = self.image_servers.ranking.write().unwrap();
+        if let Some(i) = ranking.hosts.iter().position(|x| x == host) {
+            let host = ranking.hosts.remove(i);
+            ranking.hosts.insert(0, host);
+            ranking.save();
+        }
+    }
+
+    async fn fetch_bytes(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let response = http::send_with(self.client.get(url), &Self::IMAGE_POLICY).await?;
+        let response = response.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    fn build_client(preferences: Preferences) -> anyhow::Result<Client> {
+        let base_host = if preferences.use_mirror_url {
+            "mhgui.com"
+        } else {
+            "manhuagui.com"
+        }
+        .to_owned();
+
+        let base_url = if preferences.show_zh_hant_website {
+            format!("https://tw.{base_host}")
+        } else {
+            format!("https://www.{base_host}")
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(REFERER, HeaderValue::from_str(&base_url)?);
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_static(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/84.0.4147.105 Safari/537.36",
+            ),
+        );
+
+        // a jar instead of a fixed header keeps the cookies the site sets, and scopes `isAdult`
+        // to the site instead of sending it to the image servers as well
+        let jar = Jar::default();
+        if preferences.show_r18 {
+            jar.add_cookie_str(
+                &format!("isAdult=1; Domain=.{base_host}; Path=/"),
+                &Url::parse(&base_url)?,
+            );
+        }
+
+        let client = Client::builder()
+            .default_headers(headers)
+            .cookie_provider(Arc::new(jar))
+            .build()?;
+        Ok(client)
+    }
+
+    fn decode_hidden_chapter_list(encoded: &str) -> anyhow::Result<Html> {
+        let js_decode_func = Self::JS_DECODE_FUNC;
+        let context = quick_js::Context::new()?;
+        context.set_global("encoded", encoded)?;
+        let decoded = context
+            .eval(&format!(
+                "{js_decode_func}LZString.decompressFromBase64(encoded);"
+            ))?
+            .as_str()
+            .context("not string")?
+            .to_owned();
+        Ok(Html::parse_fragment(&decoded))
+    }
+
+    /// Parses the `#chapter-list-*` sections, oldest chapter first.
+    fn parse_chapter_list(
+        document: &Html,
+        latest_chapter_href: Option<&str>,
+        latest_chapter_date: Option<i64>,
+    ) -> Vec<SChapter> {
+        let ch_num_regex = Regex::new(r"\d+").unwrap();
         let section_list_selector = Selector::parse("[id^=chapter-list-]");
+        let page_list_selector = Selector::parse("ul");
+        let chapter_list_selector = Selector::parse("li > a.status0");
+        let span_selector = Selector::parse("span");
+
+        let mut chapters = Vec::new();
         for section in document.select(&section_list_selector) {
-            let page_list_selector = Selector::parse("ul");
             let page_list = section.select(&page_list_selector).collect::<Vec<_>>();
 
             for page in page_list.iter().rev() {
-                let chapter_list_selector = Selector::parse("li > a.status0");
                 for chapter_link in page.select(&chapter_list_selector) {
-                    let url = chapter_link.value().attr("href").unwrap().to_string();
+                    let Some(url) = chapter_link.value().attr("href") else {
+                        continue;
+                    };
                     let name = chapter_link.value().attr("title").map_or_else(
                         || {
                             chapter_link
-                                .select(&Selector::parse("span"))
-                                .next()
-                                .unwrap()
-                                .text()
+                                .select(&span_selector)
                                 .next()
-                                .unwrap()
+                                .and_then(|x| x.text().next())
+                                .unwrap_or_default()
                                 .trim()
                                 .to_string()
                         },
@@ -152,43 +561,69 @@ impl MangaBackend for Manhuagui {
                         .and_then(|m| m.as_str().parse::<f32>().ok())
                         .unwrap_or(-1.0);
 
-                    // mutable because post date_upload usage
-                    let mut current_chapter = SChapter {
-                        url: ImageUrl::Web(url),
+                    chapters.push(SChapter {
+                        url: ImageUrl::Web(url.to_string()),
                         name,
                         chapter_number,
-                        date_upload: None,
-                    };
-
-                    if let Some(ref latest_href) = latest_chapter_href
-                        && current_chapter.url == ImageUrl::Web(latest_href.clone())
-                    {
-                        let date_selector = Selector::parse(
-                            "div.book-detail > ul.detail-list > li.status > span > span.red",
-                        );
-                        if let Some(date_element) = document.select(&date_selector).next_back() {
-                            current_chapter.date_upload =
-                                Some(Self::parse_date(date_element.text().next().unwrap()));
-                        }
-                    }
-                    chapters.push(current_chapter);
+                        date_upload: latest_chapter_date
+                            .filter(|_| latest_chapter_href == Some(url)),
+                    });
                 }
             }
         }
-        let chapters = chapters.into_iter().rev().collect();
+        chapters.into_iter().rev().collect()
+    }
+
+    /// Parses the chapters from the page of a manga, see [`Self::chapter_url`].
+    pub fn parse_chapters(&self, document: &Html) -> anyhow::Result<Vec<SChapter>> {
+        let viewstate_selector = Selector::parse("#__VIEWSTATE");
+        let hidden_chapter_list = if self.preferences.show_r18 {
+            document
+                .select(&viewstate_selector)
+                .next()
+                .and_then(|x| x.value().attr("value"))
+                .map(Self::decode_hidden_chapter_list)
+                .transpose()?
+        } else {
+            None
+        };
 
+        let latest_chapter_selector =
+            Selector::parse("div.book-detail > ul.detail-list > li.status > span > a.blue");
+        let latest_chapter_href = document
+            .select(&latest_chapter_selector)
+            .next()
+            .and_then(|el| el.value().attr("href"))
+            .map(String::from);
+        let date_selector =
+            Selector::parse("div.book-detail > ul.detail-list > li.status > span > span.red");
+        let latest_chapter_date = document
+            .select(&date_selector)
+            .next_back()
+            .and_then(|x| x.text().next())
+            .map(Self::parse_date);
+
+        // adult series only list their chapters inside the encoded view state, which the site
+        // swaps in for the `#erroraudit_show` notice
+        let chapters = Self::parse_chapter_list(
+            hidden_chapter_list.as_ref().unwrap_or(document),
+            latest_chapter_href.as_deref(),
+            latest_chapter_date,
+        );
         Ok(chapters)
     }
 
-    async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
-        let manga_url = self.page_url(chapter);
-        let response = self.client.get(manga_url).send().await?;
-        let body = response.text().await?;
-        let document = Html::parse_document(&body);
+    /// Parses the image urls from the page of a chapter, see [`Self::page_url`], pointing them
+    /// to `image_server`.
+    pub fn parse_pages(&self, body: &str, image_server: &str) -> anyhow::Result<Vec<Page>> {
+        let document = Html::parse_document(body);
 
         let erroraudit_show_selector = Selector::parse("#erroraudit_show");
         if document.select(&erroraudit_show_selector).next().is_some() {
-            bail!("R18作品显示开关未开启或未生效");
+            if self.preferences.show_r18 {
+                bail!("R18作品显示开关未生效");
+            }
+            bail!("R18作品显示开关未开启");
         }
 
         let re = Regex::new(r#"window\[".*?"\](\(.*\)\s*\{[\s\S]+\}\s*\(.*\))"#).unwrap();
@@ -197,7 +632,7 @@ impl MangaBackend for Manhuagui {
         let js_decode_func = Self::JS_DECODE_FUNC;
 
         let img_code = re
-            .captures(&body)
+            .captures(body)
             .and_then(|cap| cap.get(1))
             .map(|mat| mat.as_str())
             .context("Failed to find image code")?;
@@ -224,7 +659,7 @@ impl MangaBackend for Manhuagui {
             .map(|(i, img_str)| {
                 let imgurl = format!(
                     "{}{}{}?e={}&m={}",
-                    self.image_server[0],
+                    image_server,
                     image_json.path,
                     img_str,
                     image_json.sl.as_ref().map_or(0, |sl| sl.e),
@@ -240,81 +675,6 @@ impl MangaBackend for Manhuagui {
 
         Ok(pages)
     }
-}
-
-// should only be used for craetion of constly parsed values
-struct Selector;
-
-impl Selector {
-    #[must_use]
-    pub fn parse(s: &str) -> scraper::Selector {
-        scraper::Selector::parse(s)
-            .map_err(|x| anyhow::anyhow!("{x:#?}"))
-            .expect("invalid input")
-    }
-}
-impl Manhuagui {
-    const JS_DECODE_FUNC: &'static str = r#"
-        var LZString=(function(){var f=String.fromCharCode;var keyStrBase64="ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/=";var baseReverseDic={};function getBaseValue(alphabet,character){if(!baseReverseDic[alphabet]){baseReverseDic[alphabet]={};for(var i=0;i<alphabet.length;i++){baseReverseDic[alphabet][alphabet.charAt(i)]=i}}return baseReverseDic[alphabet][character]}var LZString={decompressFromBase64:function(input){if(input==null)return"";if(input=="")return null;return LZString._0(input.length,32,function(index){return getBaseValue(keyStrBase64,input.charAt(index))})},_0:function(length,resetValue,getNextValue){var dictionary=[],next,enlargeIn=4,dictSize=4,numBits=3,entry="",result=[],i,w,bits,resb,maxpower,power,c,data={val:getNextValue(0),position:resetValue,index:1};for(i=0;i<3;i+=1){dictionary[i]=i}bits=0;maxpower=Math.pow(2,2);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}switch(next=bits){case 0:bits=0;maxpower=Math.pow(2,8);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}c=f(bits);break;case 1:bits=0;maxpower=Math.pow(2,16);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}c=f(bits);break;case 2:return""}dictionary[3]=c;w=c;result.push(c);while(true){if(data.index>length){return""}bits=0;maxpower=Math.pow(2,numBits);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}switch(c=bits){case 0:bits=0;maxpower=Math.pow(2,8);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}dictionary[dictSize++]=f(bits);c=dictSize-1;enlargeIn--;break;case 1:bits=0;maxpower=Math.pow(2,16);power=1;while(power!=maxpower){resb=data.val&data.position;data.position>>=1;if(data.position==0){data.position=resetValue;data.val=getNextValue(data.index++)}bits|=(resb>0?1:0)*power;power<<=1}dictionary[dictSize++]=f(bits);c=dictSize-1;enlargeIn--;break;case 2:return result.join('')}if(enlargeIn==0){enlargeIn=Math.pow(2,numBits);numBits++}if(dictionary[c]){entry=dictionary[c]}else{if(c===dictSize){entry=w+w.charAt(0)}else{return null}}result.push(entry);dictionary[dictSize++]=w+entry.charAt(0);enlargeIn--;w=entry;if(enlargeIn==0){enlargeIn=Math.pow(2,numBits);numBits++}}}};return LZString})();String.prototype.splic=function(f){return LZString.decompressFromBase64(this).split(f)};
-        "#;
-    pub fn new(preferences: Preferences) -> anyhow::Result<Self> {
-        let base_host = if preferences.use_mirror_url {
-            "mhgui.com"
-        } else {
-            "manhuagui.com"
-        }
-        .to_owned();
-
-        let base_url = if preferences.show_zh_hant_website {
-            format!("https://tw.{base_host}")
-        } else {
-            format!("https://www.{base_host}")
-        };
-
-        let image_server = [
-            "https://i.hamreus.com".to_string(),
-            "https://cf.hamreus.com".to_string(),
-        ];
-
-        let client = Self::build_client(preferences)?;
-
-        Ok(Self {
-            name: String::from("漫画柜"),
-            lang: String::from("zh"),
-            base_url,
-            image_server,
-            client,
-        })
-    }
-
-    fn build_client(preferences: Preferences) -> anyhow::Result<Client> {
-        let base_host = if preferences.use_mirror_url {
-            "mhgui.com"
-        } else {
-            "manhuagui.com"
-        }
-        .to_owned();
-
-        let base_url = if preferences.show_zh_hant_website {
-            format!("https://tw.{base_host}")
-        } else {
-            format!("https://www.{base_host}")
-        };
-        let mut headers = HeaderMap::new();
-        headers.insert(REFERER, HeaderValue::from_str(&base_url)?);
-        headers.insert(
-        USER_AGENT,
-        HeaderValue::from_static(
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/84.0.4147.105 Safari/537.36",
-        ),
-    );
-        if preferences.show_r18 {
-            headers.insert("Cookie", HeaderValue::from_static("isAdult=1"));
-        }
-
-        let client = Client::builder().default_headers(headers).build()?;
-        Ok(client)
-    }
 
     pub fn smanga_creation(document: &Html, url: impl Into<String>) -> SManga {
         let title_selector = Selector::parse("div.book-title > h1:nth-child(1)");
@@ -416,6 +776,126 @@ impl Manhuagui {
             last_updated_time,
         }
     }
+    async fn fetch_listing(&self, url: &str) -> anyhow::Result<MangasPage> {
+        let response = http::send(self.client.get(url)).await?.error_for_status()?;
+        let document = Html::parse_document(&response.text().await?);
+        Ok(Self::parse_listing(&document))
+    }
+
+    /// Parses the `/list/` pages used for popular, latest and filtered browsing.
+    pub fn parse_listing(document: &Html) -> MangasPage {
+        let item_selector = Selector::parse("ul#contList > li");
+        let link_selector = Selector::parse("a.bcover");
+        let image_selector = Selector::parse("a.bcover > img");
+        let updated_selector = Selector::parse("span.updateon");
+        let status_selector = Selector::parse("a.bcover > span");
+
+        let mangas = document
+            .select(&item_selector)
+            .filter_map(|item| {
+                let link = item.select(&link_selector).next()?;
+                let status = match item
+                    .select(&status_selector)
+                    .next()
+                    .map(|x| x.value().classes().any(|x| x == "fd"))
+                {
+                    Some(true) => MangaStatus::Completed,
+                    Some(false) => MangaStatus::Ongoing,
+                    None => MangaStatus::Unknown,
+                };
+                Some(SManga {
+                    url: ImageUrl::Web(link.value().attr("href")?.to_owned()),
+                    title: link
+                        .value()
+                        .attr("title")
+                        .unwrap_or_default()
+                        .trim()
+                        .to_owned(),
+                    thumbnail_url: item
+                        .select(&image_selector)
+                        .next()
+                        .and_then(|x| x.value().attr("data-src").or(x.value().attr("src")))
+                        .map(Self::absolute_thumbnail),
+                    status,
+                    last_updated_time: item
+                        .select(&updated_selector)
+                        .next()
+                        .map(|x| x.text().collect::<String>().trim().to_owned())
+                        .unwrap_or_default(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        MangasPage {
+            mangas,
+            has_next_page: Self::has_next_page(document),
+        }
+    }
+
+    /// Parses keyword search results from `/s/`.
+    pub fn parse_search_results(document: &Html) -> MangasPage {
+        let item_selector = Selector::parse("div.book-result > ul > li");
+        let link_selector = Selector::parse("div.book-detail > dl > dt > a");
+        let image_selector = Selector::parse("div.book-cover > a.bcover > img");
+        let author_selector = Selector::parse("div.book-detail > dl > dd.tags > span > a");
+        let description_selector = Selector::parse("div.book-detail > dl > dd.intro > span");
+
+        let mangas = document
+            .select(&item_selector)
+            .filter_map(|item| {
+                let link = item.select(&link_selector).next()?;
+                let author = item
+                    .select(&author_selector)
+                    .filter(|x| {
+                        x.value()
+                            .attr("href")
+                            .is_some_and(|x| x.contains("/author/"))
+                    })
+                    .map(|x| x.text().collect::<String>())
+                    .collect::<Vec<_>>();
+                Some(SManga {
+                    url: ImageUrl::Web(link.value().attr("href")?.to_owned()),
+                    title: link
+                        .value()
+                        .attr("title")
+                        .map_or_else(|| link.text().collect::<String>(), str::to_owned)
+                        .trim()
+                        .to_owned(),
+                    thumbnail_url: item
+                        .select(&image_selector)
+                        .next()
+                        .and_then(|x| x.value().attr("data-src").or(x.value().attr("src")))
+                        .map(Self::absolute_thumbnail),
+                    author: (!author.is_empty()).then(|| author.join(", ")),
+                    description: item
+                        .select(&description_selector)
+                        .next()
+                        .map(|x| x.text().collect::<String>().trim().to_owned()),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        MangasPage {
+            mangas,
+            has_next_page: Self::has_next_page(document),
+        }
+    }
+
+    fn has_next_page(document: &Html) -> bool {
+        let next_selector = Selector::parse("span.current + a");
+        document.select(&next_selector).next().is_some()
+    }
+
+    fn absolute_thumbnail(url: &str) -> String {
+        if url.starts_with("//") {
+            format!("https:{url}")
+        } else {
+            url.to_owned()
+        }
+    }
+
     #[must_use]
     pub fn chapter_url(&self, api: &SManga) -> String {
         let ImageUrl::Web(url) = &api.url else {
@@ -1,16 +1,21 @@
-use std::{path::PathBuf, sync::Arc};
+use std::path::PathBuf;
 
 use anyhow::{Context, bail};
 use appload_client::Message;
 use backend::{
-    Backend,
-    epub::Epub,
-    manhuagui::{Manhuagui, Preferences},
-    nhentai::NHentai,
+    Capabilities, MangasPage, SearchFilter, Source, SourceError, SourceInfo,
+    credentials::{Credentials, NeedsCredentials},
+    export::ExportFormat,
+    registry,
+    settings::{Settings, SettingsSchema},
 };
+use serde::Deserialize;
 use serde_json::json;
 
-use crate::{BackendReplier, bookshelf::BookShelfKey, manga_reader::MangaReader};
+use crate::{
+    BackendReplier, bookshelf::BookShelfKey, downloads::DownloadProgress,
+    manga_reader::MangaReader, updates::SeriesUpdate,
+};
 
 #[derive(Debug)]
 pub enum RecvMessage {
@@ -24,32 +29,55 @@ pub enum RecvMessage {
     SelectChapter(usize),
     SelectPage(usize),
     ConfirmMangaSearch,
-    SelectBackend(Arc<Backend>),
+    SelectBackend(Source),
     SaveActiveToBookShelf,
     SelectBookFromBookShelf(BookShelfKey),
     BookShelfView,
+    Search(SearchRequest),
+    Popular(u32),
+    Latest(u32),
+    GetSettings(String),
+    UpdateSettings {
+        source_id: String,
+        settings: Settings,
+    },
+    SetCredentials {
+        source_id: String,
+        credentials: Credentials,
+    },
+    CheckUpdates,
+    /// chapter indices of the open manga, every chapter when empty
+    DownloadChapters(Vec<usize>),
+    PauseDownloads,
+    ResumeDownloads,
+    /// a download id, every download with `None`
+    CancelDownloads(Option<u64>),
+    /// downloaded chapter indices of the open manga, every downloaded chapter when empty
+    Export {
+        format: ExportFormat,
+        chapters: Vec<usize>,
+    },
     Quit,
 }
 
+/// Contents of a search message, sent by the frontend as JSON.
+#[derive(Debug, Deserialize)]
+pub struct SearchRequest {
+    #[serde(default)]
+    pub query: String,
+    #[serde(default = "first_page")]
+    pub page: u32,
+    #[serde(flatten)]
+    pub filter: SearchFilter,
+}
+
+const fn first_page() -> u32 {
+    1
+}
+
 impl TryFrom<Message> for RecvMessage {
     type Error = anyhow::Error;
     fn try_from(message: Message) -> Result<Self, Self::Error> {
-        let backend_from_str: fn(&str) -> Result<Arc<Backend>, anyhow::Error> = |x| {
-            let x = match x {
-                "NHentai" => Arc::new(NHentai::new("zh-tw".into(), "zh-tw".into(), true)?.into()),
-                "Manhuagui" => Arc::new(Manhuagui::new(Preferences::default())?.into()),
-                "Epub" => Arc::new(
-                    Epub::new(
-                        std::env::home_dir()
-                            .context("no home dir")?
-                            .join("mangarr-books"),
-                    )
-                    .into(),
-                ),
-                _ => bail!("Unsupported backend."),
-            };
-            anyhow::Ok(x)
-        };
         let msg = match message.msg_type {
             appload_client::MSG_SYSTEM_NEW_COORDINATOR | 69420 => Self::Connect,
             1 => Self::SearchManga(message.contents),
@@ -61,15 +89,71 @@ impl TryFrom<Message> for RecvMessage {
             7 => Self::SelectChapter(message.contents.parse()?),
             9 => Self::SelectPage(message.contents.parse()?),
             10 => Self::ConfirmMangaSearch,
-            11 => Self::SelectBackend(backend_from_str(&message.contents)?),
+            11 => Self::SelectBackend(registry::source(&message.contents)?),
             12 => Self::SaveActiveToBookShelf,
             13 => {
-                let (backend, manga_url) = message.contents.split_once("\n").unwrap();
-                let backend = backend_from_str(backend)?;
+                let (backend, manga_url) = message
+                    .contents
+                    .split_once("\n")
+                    .context("missing manga url")?;
+                let backend = registry::source(backend)?;
                 let key = BookShelfKey::new(&backend, manga_url.to_string());
                 Self::SelectBookFromBookShelf(key)
             }
             14 => Self::BookShelfView,
+            15 => Self::Search(serde_json::from_str(&message.contents)?),
+            16 => Self::Popular(message.contents.parse()?),
+            17 => Self::Latest(message.contents.parse()?),
+            18 => Self::GetSettings(message.contents),
+            19 => {
+                let (source_id, settings) = message
+                    .contents
+                    .split_once('\n')
+                    .context("missing settings")?;
+                Self::UpdateSettings {
+                    source_id: source_id.to_owned(),
+                    settings: serde_json::from_str(settings)?,
+                }
+            }
+            20 => {
+                let (source_id, credentials) = message
+                    .contents
+                    .split_once('\n')
+                    .context("missing credentials")?;
+                Self::SetCredentials {
+                    source_id: source_id.to_owned(),
+                    credentials: serde_json::from_str(credentials)?,
+                }
+            }
+            21 => Self::CheckUpdates,
+            22 => Self::DownloadChapters(
+                message
+                    .contents
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?,
+            ),
+            23 => Self::PauseDownloads,
+            24 => Self::ResumeDownloads,
+            25 => Self::CancelDownloads(
+                Some(message.contents.trim())
+                    .filter(|x| !x.is_empty())
+                    .map(str::parse)
+                    .transpose()?,
+            ),
+            26 => {
+                let (format, chapters) = message
+                    .contents
+                    .split_once('\n')
+                    .unwrap_or((&message.contents, ""));
+                Self::Export {
+                    format: format.parse()?,
+                    chapters: chapters
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?,
+                }
+            }
             99 => Self::Quit,
             x => bail!("Unknown message received. {x}"),
         };
@@ -98,14 +182,34 @@ pub enum SendMessage {
     MangaName(String),
     MangaLastUpdatedTime(String),
     BookshelfMangaDetails(Box<MangaReader>),
-    Error(String),
+    Capabilities(Capabilities),
+    /// every registered source, so the frontend does not hardcode them
+    SourceList(Vec<SourceInfo>),
+    /// the schema and current values of a source's settings
+    Settings {
+        source_id: String,
+        schema: SettingsSchema,
+        values: Settings,
+    },
+    /// asks the user to solve a challenge and enter the resulting credentials
+    NeedsCredentials(NeedsCredentials),
+    /// one page of search, popular or latest results
+    SearchResults {
+        page: u32,
+        results: MangasPage,
+    },
+    /// a failed request, for the frontend to show instead of the backend crashing
+    Error(SourceError),
+    /// the bookshelf series with new chapters since the last check
+    UpdatesAvailable(Vec<SeriesUpdate>),
+    DownloadProgress(DownloadProgress),
     /// the display for the image on each active page
     BackendImage,
 }
 
 impl SendMessage {
-    pub fn display(self) -> (u32, Option<String>) {
-        match self {
+    pub fn display(self) -> anyhow::Result<(u32, Option<String>)> {
+        Ok(match self {
             Self::ActivePageNumber(x) => (4, Some(x.to_string())),
             Self::TotalPageSize(x) => (5, Some(x.to_string())),
             Self::ActiveChapterNumber(x) => (6, Some(x.to_string())),
@@ -136,19 +240,123 @@ impl SendMessage {
                 let v = json![{
                     "url"            : url,
                     "title"          : details.title,
-                    "backend"        : manga.api.to_string(),
+                    "backend"        : manga.api.id(),
+                    "backendName"    : manga.api.to_string(),
                     "lastReadPage"   : (manga.current_page.page + 1).to_string(),
                     "totalPages"     : total_pages.to_string(),
                     "lastReadChapter": (manga.current_page.chapter() + 1).to_string(),
                     "totalChapters"  : manga.chapters.len().to_string(),
                     "description"    : details.description.clone().unwrap_or_default(),
+                    "newChapters"    : manga.updates.len(),
                 }];
 
                 (17, Some(v.to_string()))
             }
-            Self::Error(s) => (1000, Some(s)),
+            Self::Capabilities(capabilities) => (18, Some(serde_json::to_string(&capabilities)?)),
+            Self::SourceList(sources) => {
+                let v = sources
+                    .iter()
+                    .map(|source| {
+                        json![{
+                            "id"         : source.id,
+                            "name"       : source.name,
+                            "lang"       : source.lang,
+                            "description": source.description,
+                        }]
+                    })
+                    .collect::<Vec<_>>();
+
+                (20, Some(serde_json::Value::from(v).to_string()))
+            }
+            Self::Settings {
+                source_id,
+                schema,
+                values,
+            } => {
+                let v = json![{
+                    "id"    : source_id,
+                    "fields": schema,
+                    "values": values,
+                }];
+
+                (21, Some(v.to_string()))
+            }
+            Self::NeedsCredentials(needs) => {
+                let v = json![{
+                    "id"     : needs.source_id,
+                    "url"    : needs.url,
+                    "cookies": needs.cookies,
+                    "message": needs.to_string(),
+                }];
+
+                (22, Some(v.to_string()))
+            }
+            Self::SearchResults { page, results } => {
+                let mangas = results
+                    .mangas
+                    .iter()
+                    .map(|manga| {
+                        let url = manga.url.get_distinguisher();
+                        let id = url.trim_matches('/').rsplit('/').next().unwrap_or_default();
+                        json![{
+                            "id"       : id,
+                            "title"    : manga.title,
+                            "thumbnail": manga.thumbnail_url.clone().unwrap_or_default(),
+                        }]
+                    })
+                    .collect::<Vec<_>>();
+                let v = json![{
+                    "page"       : page,
+                    "hasNextPage": results.has_next_page,
+                    "mangas"     : mangas,
+                }];
+
+                (19, Some(v.to_string()))
+            }
+            Self::Error(err) => {
+                let v = json![{
+                    "kind"     : err.kind(),
+                    "message"  : err.to_string(),
+                    "transient": err.is_transient(),
+                }];
+
+                (1000, Some(v.to_string()))
+            }
+            Self::UpdatesAvailable(updates) => {
+                let series = updates
+                    .iter()
+                    .map(|update| {
+                        json![{
+                            "url"        : update.key.manga_distinguisher,
+                            "backend"    : update.key.backend_id(),
+                            "title"      : update.title,
+                            "newChapters": update.new_chapters,
+                        }]
+                    })
+                    .collect::<Vec<_>>();
+                let v = json![{
+                    "total" : updates.iter().map(|x| x.new_chapters).sum::<usize>(),
+                    "series": series,
+                }];
+
+                (23, Some(v.to_string()))
+            }
+            Self::DownloadProgress(progress) => {
+                let (downloaded, total) = progress.pages.unzip();
+                let v = json![{
+                    "id"        : progress.id,
+                    "title"     : progress.title,
+                    "chapter"   : progress.chapter,
+                    "state"     : progress.state.as_str(),
+                    "downloaded": downloaded,
+                    "total"     : total,
+                    "message"   : progress.message,
+                }];
+
+                (24, Some(v.to_string()))
+            }
             Self::BackendImage => (101, None),
-        }
+        })
     }
     pub fn status(s: impl Into<String>) -> Self {
         Self::Status(s.into())
@@ -161,7 +369,7 @@ pub trait ReplierExt {
 
 impl ReplierExt for BackendReplier {
     async fn send_typed_message(&self, msg: SendMessage) -> anyhow::Result<()> {
-        let (msg, contents) = msg.display();
+        let (msg, contents) = msg.display()?;
         let mut contents = contents.as_ref().map_or("placeholder", |v| v);
         if contents.is_empty() {
             println!("empty content! adding placeholder text for protection");
@@ -1,31 +1,40 @@
 /// Based on <https://github.com/keiyoushi/extensions-source/blob/main/src/all/nhentai/src/eu/kanade/tachiyomi/extension/all/nhentai/NHentai.kt>
-use crate::{ImageUrl, MangaBackend, MangaStatus, Page, SChapter, SManga};
+use crate::{
+    Capabilities, Genre, ImageUrl, MangaBackend, MangaStatus, MangasPage, Page, SChapter, SManga,
+    SearchFilter, SourceError,
+    cache::{self, CacheKind},
+    credentials::{CredentialStore, Credentials, NeedsCredentials},
+    http,
+    settings::{SettingField, Settings, SettingsSchema},
+};
 use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
 use regex::Regex;
 use reqwest::{
-    Client, Url,
+    Client, RequestBuilder, Response, StatusCode, Url,
     cookie::Jar,
-    header::{HeaderMap, HeaderValue, REFERER, USER_AGENT},
+    header::{HeaderMap, HeaderValue, REFERER, SERVER, USER_AGENT},
 };
 use scraper::{Html, Selector};
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::{env, fmt::Display, sync::Arc};
+use serde::Deserialize;
+use std::{
+    env,
+    fmt::Display,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone)]
 pub struct NHentai {
     pub lang: String,
+    /// nhentai's language tag, empty for every language.
     nh_lang: String,
     base_url: String,
-
-    #[serde(skip, default = "deserialize_client")]
-    client: Client,
+    /// Swapped out when the user enters new credentials.
+    client: Arc<RwLock<Client>>,
     display_full_title: bool,
 }
 
-fn deserialize_client() -> Client {
-    NHentai::build_client().unwrap()
-}
-
 impl Display for NHentai {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "NHentai")
@@ -43,28 +52,20 @@ struct NHentaiTitle {
 #[derive(Debug, Deserialize)]
 struct NHentaiImage {
     t: String, // Type: "j" (jpg), "p" (png), "g" (gif), "w"(webp)
-    w: u32,    // Width
-    h: u32,    // Height
 }
 
 #[derive(Debug, Deserialize)]
 struct NHentaiImages {
     pages: Vec<NHentaiImage>,
-    cover: Option<NHentaiImage>,
-    thumbnail: Option<NHentaiImage>,
 }
 #[derive(Debug, Deserialize)]
 struct NHentaiTag {
-    id: u64,
     r#type: String,
     name: String,
-    url: String,
-    count: u64,
 }
 
 #[derive(Debug, Deserialize)]
 struct NHentaiData {
-    id: u64,
     media_id: String,
     title: NHentaiTitle,
     images: NHentaiImages,
@@ -109,55 +110,133 @@ impl NHentaiData {
         })
     }
 
+    /// Every tag, prefixed with its type unless it is a plain tag, in the syntax the search
+    /// accepts, e.g. `parody:touhou project, character:reimu hakurei, full color`.
     fn get_tags(&self) -> String {
-        self.tags
+        const ORDER: [&str; 5] = ["parody", "character", "tag", "language", "category"];
+        let rank = |t: &NHentaiTag| ORDER.iter().position(|x| *x == t.r#type);
+        let mut tags = self
+            .tags
             .iter()
-            .filter(|t| t.r#type == "tag")
-            .map(|t| t.name.clone())
+            .filter(|t| rank(t).is_some())
+            .collect::<Vec<_>>();
+        tags.sort_by_key(|t| rank(t));
+        tags.iter()
+            .map(|t| match t.r#type.as_str() {
+                "tag" => t.name.clone(),
+                kind => format!("{kind}:{}", t.name),
+            })
             .collect::<Vec<_>>()
             .join(", ")
     }
 }
 
 impl NHentai {
+    pub const ID: &'static str = "nhentai";
+
+    /// Found in the page Cloudflare serves instead of the site while it checks the browser.
+    const CHALLENGE_MARKERS: [&'static str; 3] = [
+        "challenge-platform",
+        "cf-chl",
+        "<title>Just a moment...</title>",
+    ];
+
+    /// Common tags offered as filters, any other one can be typed as `tag:"name"`.
+    const TAGS: [&'static str; 12] = [
+        "full color",
+        "sole female",
+        "sole male",
+        "group",
+        "stockings",
+        "glasses",
+        "schoolgirl uniform",
+        "uncensored",
+        "anthology",
+        "multi-work series",
+        "story arc",
+        "webtoon",
+    ];
+
     pub fn new(lang: String, nh_lang: String, display_full_title: bool) -> Result<Self> {
         let base_url = "https://nhentai.net".to_string();
-        let client = Self::build_client()?;
+        // Cloudflare asks for a new challenge when pages are requested in quick succession
+        http::limit_host("nhentai.net", Duration::from_millis(500));
+        let client = Self::build_client(&Self::stored_credentials())?;
         Ok(Self {
             lang,
             nh_lang,
             base_url,
-            client,
+            client: Arc::new(RwLock::new(client)),
             display_full_title,
         })
     }
 
-    fn build_client() -> anyhow::Result<Client> {
-        let base_url = "https://nhentai.net".to_string();
-        let mut headers = HeaderMap::new();
-        headers.insert(REFERER, HeaderValue::from_str(&base_url)?);
+    pub fn schema() -> SettingsSchema {
+        SettingsSchema(vec![
+            SettingField::choice(
+                "language",
+                "Language",
+                &[
+                    ("", "All"),
+                    ("english", "English"),
+                    ("japanese", "Japanese"),
+                    ("chinese", "Chinese"),
+                ],
+                "",
+            ),
+            SettingField::bool("display_full_title", "Display full titles", true)
+                .with_description("Keeps the bracketed circle, artist and language tags."),
+        ])
+    }
 
-        let jar = Jar::default();
+    /// Expects settings already validated against [`Self::schema`].
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        Self::new(
+            "all".to_owned(),
+            settings.string("language").unwrap_or_default().to_owned(),
+            settings.bool("display_full_title").unwrap_or(true),
+        )
+    }
 
+    /// Credentials from the store, or from the files earlier versions asked users to create.
+    fn stored_credentials() -> Credentials {
+        let credentials = CredentialStore::load().get(Self::ID);
+        if !credentials.is_empty() {
+            return credentials;
+        }
         #[allow(deprecated)]
-        let home = env::home_dir().context("not present")?;
-
-        let cf_clearance = std::fs::read_to_string(home.join(".config/mangarr/cf_token"))?;
+        let Some(dir) = env::home_dir().map(|x| x.join(".config/mangarr")) else {
+            return credentials;
+        };
+        let read = |name: &str| {
+            std::fs::read_to_string(dir.join(name))
+                .ok()
+                .map(|x| x.trim().to_owned())
+        };
+        Credentials {
+            cookies: read("cf_token")
+                .map(|x| ("cf_clearance".to_owned(), x))
+                .into_iter()
+                .collect(),
+            user_agent: read("user_agent"),
+        }
+    }
 
-        jar.add_cookie_str(
-            &format!("cf_clearance={cf_clearance}"),
-            &Url::parse(&base_url)?,
-        );
+    fn build_client(credentials: &Credentials) -> anyhow::Result<Client> {
+        let base_url = Url::parse("https://nhentai.net")?;
+        let mut headers = HeaderMap::new();
+        headers.insert(REFERER, HeaderValue::from_str(base_url.as_str())?);
 
-        let user_agent_path = home.join(".config/mangarr/user_agent");
+        let jar = Jar::default();
+        for (name, value) in &credentials.cookies {
+            jar.add_cookie_str(&format!("{name}={value}"), &base_url);
+        }
 
-        let user_agent = if user_agent_path.exists() {
-            let src = std::fs::read_to_string(user_agent_path)?;
-            HeaderValue::from_str(&src)?
-        } else {
-            HeaderValue::from_static(
+        let user_agent = match &credentials.user_agent {
+            Some(user_agent) => HeaderValue::from_str(user_agent)?,
+            None => HeaderValue::from_static(
                 "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/133.0.0.0 Safari/537.36 Edg/133.0.0.0",
-            )
+            ),
         };
 
         headers.insert(USER_AGENT, user_agent);
@@ -170,11 +249,118 @@ impl NHentai {
         Ok(client)
     }
 
+    /// Restricts a search query to the configured language. An empty query is quoted since
+    /// nhentai rejects searching for nothing.
+    fn with_language(&self, query: &str) -> String {
+        let query = query.trim();
+        match (query.is_empty(), self.nh_lang.is_empty()) {
+            (true, true) => "\"\"".to_owned(),
+            (true, false) => format!("language:{}", self.nh_lang),
+            (false, true) => query.to_owned(),
+            (false, false) => format!("{query} language:{}", self.nh_lang),
+        }
+    }
+
+    fn http(&self) -> Client {
+        self.client.read().unwrap().clone()
+    }
+
+    /// Sends a request, reporting Cloudflare challenges as [`NeedsCredentials`].
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.check(http::send(request).await?).await
+    }
+
+    /// [`Self::send`] through the [`cache`].
+    async fn send_cached(&self, kind: CacheKind, request: RequestBuilder) -> Result<Response> {
+        self.check(cache::send(Self::ID, kind, request).await?)
+            .await
+    }
+
+    /// Only a challenge means the `cf_clearance` cookie expired, any other 403 or 503 is an
+    /// outage or maintenance that new credentials won't fix.
+    async fn check(&self, response: Response) -> Result<Response> {
+        if !http::is_challenge(response.headers()) {
+            if response.status() != StatusCode::FORBIDDEN {
+                return Ok(response.error_for_status()?);
+            }
+            let cloudflare = response
+                .headers()
+                .get(SERVER)
+                .is_some_and(|x| x.as_bytes().eq_ignore_ascii_case(b"cloudflare"));
+            let url = response.url().to_string();
+            let body = response.text().await.unwrap_or_default();
+            if !cloudflare && !Self::CHALLENGE_MARKERS.iter().any(|x| body.contains(x)) {
+                return Err(SourceError::HttpStatus {
+                    status: StatusCode::FORBIDDEN.as_u16(),
+                    url: Some(url),
+                }
+                .into());
+            }
+        }
+        Err(NeedsCredentials {
+            source_id: Self::ID.to_owned(),
+            url: self.base_url.clone(),
+            cookies: vec!["cf_clearance".to_owned()],
+        }
+        .into())
+    }
+
     fn shorten_title(title: &str) -> String {
         let re = Regex::new(r"(\[[^]]*]|[({][^)}]*[)}])").unwrap();
         re.replace_all(title, "").trim().to_string()
     }
 
+    async fn fetch_listing(&self, url: &str, query: &[(&str, String)]) -> Result<MangasPage> {
+        let response = self.send(self.http().get(url).query(query)).await?;
+        let document = Html::parse_document(&response.text().await?);
+        Ok(self.parse_listing(&document))
+    }
+
+    /// Parses the gallery grid shared by the index, search and tag pages.
+    pub fn parse_listing(&self, document: &Html) -> MangasPage {
+        let gallery_selector = Selector::parse("#content .gallery").unwrap();
+        let link_selector = Selector::parse("a.cover").unwrap();
+        let caption_selector = Selector::parse(".caption").unwrap();
+        let image_selector = Selector::parse("img").unwrap();
+        let next_selector = Selector::parse("section.pagination a.next").unwrap();
+
+        let mangas = document
+            .select(&gallery_selector)
+            .filter_map(|gallery| {
+                let link = gallery.select(&link_selector).next()?;
+                let caption = gallery
+                    .select(&caption_selector)
+                    .next()
+                    .map(|x| x.text().collect::<String>())
+                    .unwrap_or_default();
+                let title = if self.display_full_title {
+                    caption.trim().to_owned()
+                } else {
+                    Self::shorten_title(&caption)
+                };
+                Some(SManga {
+                    url: link.value().attr("href")?.to_owned().into(),
+                    title,
+                    thumbnail_url: link
+                        .select(&image_selector)
+                        .next()
+                        .and_then(|x| x.value().attr("data-src").or(x.value().attr("src")))
+                        .map(String::from),
+                    status: MangaStatus::Completed,
+                    author: None,
+                    description: None,
+                    genre: None,
+                    last_updated_time: String::new(),
+                })
+            })
+            .collect();
+
+        MangasPage {
+            mangas,
+            has_next_page: document.select(&next_selector).next().is_some(),
+        }
+    }
+
     // Helper function to extract and parse the JSON data from the script tag.
     fn extract_hentai_data(document: &Html) -> Result<NHentaiData> {
         let data_regex = Regex::new(r#"JSON\.parse\(\s*"(.*)"\s*\)"#).unwrap();
@@ -198,26 +384,12 @@ impl NHentai {
 
         Ok(data)
     }
-}
 
-impl MangaBackend for NHentai {
-    fn client(&self) -> std::option::Option<reqwest::Client> {
-        self.client.clone().into()
-    }
-    async fn search_by_id(&self, id: &str) -> Result<SManga> {
-        let search_id = id.to_owned();
-
-        let url = format!("{}/g/{}", self.base_url, search_id);
-        let client = self.client.clone();
-        let display_full_title = self.display_full_title;
-
-        let response = client.get(&url).send().await?.error_for_status()?;
-        let body = response.text().await?;
-        let document = Html::parse_document(&body);
-
-        let data = Self::extract_hentai_data(&document)?;
+    /// Parses the page of a gallery, `/g/<id>/`.
+    pub fn parse_gallery(&self, document: &Html, id: &str) -> Result<SManga> {
+        let data = Self::extract_hentai_data(document)?;
 
-        let title = if display_full_title {
+        let title = if self.display_full_title {
             data.title.english.clone().unwrap_or_else(|| {
                 data.title
                     .japanese
@@ -242,7 +414,10 @@ impl MangaBackend for NHentai {
             .and_then(|x| x.value().attr("data-src"))
             .map(String::from);
 
-        let author = data.get_groups().or_else(|| data.get_artists());
+        let author = data
+            .get_groups()
+            .or_else(|| data.get_artists())
+            .or_else(|| data.scanlator.clone().filter(|x| !x.is_empty()));
         let description = format!(
             "Full English and Japanese titles:\n{}\n{}\n\nPages: {}\nFavorited by: {}\n{}",
             data.title.english.clone().unwrap_or_else(|| data
@@ -251,14 +426,14 @@ impl MangaBackend for NHentai {
                 .clone()
                 .unwrap_or_default()),
             data.title.japanese.clone().unwrap_or_default(),
-            data.images.pages.len(),
+            data.num_pages,
             data.num_favorites,
             data.get_tags_desc()
         );
         let genre = data.get_tags();
 
         Ok(SManga {
-            url: format!("/g/{search_id}/").into(), // Relative URL
+            url: format!("/g/{id}/").into(), // Relative URL
             title,
             thumbnail_url,
             status: MangaStatus::Completed,
@@ -269,33 +444,156 @@ impl MangaBackend for NHentai {
         })
     }
 
+    /// The single chapter of a gallery from its page.
+    pub fn parse_chapters(document: &Html, manga: &SManga) -> Result<Vec<SChapter>> {
+        let data = Self::extract_hentai_data(document)?;
+        let date_upload = if data.upload_date > 0 {
+            Some(data.upload_date * 1000)
+        } else {
+            None
+        };
+        Ok(vec![SChapter {
+            url: manga.url.clone(),
+            name: "Chapter".to_string(),
+            chapter_number: 1.0, // NHentai typically has only one chapter
+            date_upload,
+        }])
+    }
+
+    /// Parses the image urls from the page of a gallery.
+    pub fn parse_pages(document: &Html) -> Result<Vec<Page>> {
+        let script_selector = Selector::parse("script").unwrap();
+        let script = document
+            .select(&script_selector)
+            .map(|x| x.inner_html())
+            .find(|x| x.contains("media_server"))
+            .context("media server script not found")?;
+        let re = Regex::new(r#"media_server\s*:\s*(\d+)"#).unwrap();
+        let media_server = re
+            .captures(&script)
+            .and_then(|cap| cap.get(1))
+            .map(|mat| mat.as_str())
+            .context("media server number failed")?
+            .to_string();
+
+        let data = Self::extract_hentai_data(document)?;
+
+        let pages = data
+            .images
+            .pages
+            .iter()
+            .enumerate()
+            .map(|(i, img)| {
+                let img_url = format!(
+                    "https://i{}.nhentai.net/galleries/{}/{}{}",
+                    media_server,
+                    data.media_id,
+                    i + 1,
+                    match img.t.as_str() {
+                        "p" => ".png",
+                        "g" => ".gif",
+                        "w" => ".webp",
+                        _ => ".jpg", // Default to .jpg
+                    }
+                );
+                Page {
+                    index: i,
+                    url: String::new(), // nhentai does not require this
+                    image_url: ImageUrl::Web(img_url),
+                }
+            })
+            .collect();
+
+        Ok(pages)
+    }
+}
+
+#[async_trait]
+impl MangaBackend for NHentai {
+    fn id(&self) -> String {
+        Self::ID.to_owned()
+    }
+    fn settings_schema(&self) -> SettingsSchema {
+        Self::schema()
+    }
+    fn client(&self) -> std::option::Option<reqwest::Client> {
+        self.http().into()
+    }
+    fn set_credentials(&self, credentials: &Credentials) -> Result<()> {
+        *self.client.write().unwrap() = Self::build_client(credentials)?;
+        Ok(())
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            search: true,
+            popular: true,
+            latest: true,
+            status_filter: false,
+            genres: Self::TAGS.iter().map(|x| Genre::new(*x, *x)).collect(),
+        }
+    }
+    async fn search(&self, query: &str, page: u32, filter: &SearchFilter) -> Result<MangasPage> {
+        let mut query = query.trim().to_owned();
+        if let Some(genre) = &filter.genre {
+            query.push_str(&format!(" tag:\"{genre}\""));
+        }
+        if query.trim().is_empty() {
+            return self.latest(page).await;
+        }
+        let url = format!("{}/search/", self.base_url);
+        self.fetch_listing(
+            &url,
+            &[
+                ("q", self.with_language(&query)),
+                ("page", page.to_string()),
+            ],
+        )
+        .await
+    }
+    async fn popular(&self, page: u32) -> Result<MangasPage> {
+        let url = format!("{}/search/", self.base_url);
+        self.fetch_listing(
+            &url,
+            &[
+                ("q", self.with_language("")),
+                ("sort", "popular".to_owned()),
+                ("page", page.to_string()),
+            ],
+        )
+        .await
+    }
+    async fn latest(&self, page: u32) -> Result<MangasPage> {
+        let url = if self.nh_lang.is_empty() {
+            format!("{}/", self.base_url)
+        } else {
+            format!("{}/language/{}/", self.base_url, self.nh_lang)
+        };
+        self.fetch_listing(&url, &[("page", page.to_string())])
+            .await
+    }
+    async fn search_by_id(&self, id: &str) -> Result<SManga> {
+        let url = format!("{}/g/{id}", self.base_url);
+        let response = self
+            .send_cached(CacheKind::Details, self.http().get(&url))
+            .await?;
+        let document = Html::parse_document(&response.text().await?);
+        self.parse_gallery(&document, id)
+    }
+
     async fn fetch_chapters(&self, manga: &SManga) -> Result<Vec<SChapter>> {
         let ImageUrl::Web(url) = &manga.url else {
             unreachable!()
         };
         // Extract manga ID from the URL.  We assume the URL is in the format `/g/<id>/`.
         let manga_id = url.trim_matches('/').split('/').nth(1);
-        let client = self.client.clone();
 
         if let Some(manga_id) = manga_id {
             let url = format!("{}/g/{}", self.base_url, manga_id);
-            let response = client.get(&url).send().await?.error_for_status()?;
-            let body = response.text().await?;
-            let document = Html::parse_document(&body);
-
-            let data = Self::extract_hentai_data(&document)?;
-
-            let date_upload = if data.upload_date > 0 {
-                Some(data.upload_date * 1000)
-            } else {
-                None
-            };
-            Ok(vec![SChapter {
-                url: manga.url.clone(),
-                name: "Chapter".to_string(),
-                chapter_number: 1.0, // NHentai typically has only one chapter
-                date_upload,
-            }])
+            let response = self
+                .send_cached(CacheKind::Chapters, self.http().get(&url))
+                .await?;
+            Self::parse_chapters(&Html::parse_document(&response.text().await?), manga)
         } else {
             bail!("Invalid manga URL format: {}", url);
         }
@@ -306,58 +604,13 @@ impl MangaBackend for NHentai {
             unreachable!()
         };
         let chapter_id = url.trim_matches('/').split('/').nth(1);
-        let client = self.client.clone();
 
         if let Some(chapter_id) = chapter_id {
             let url = format!("{}/g/{}", self.base_url, chapter_id);
-            let response = client.get(&url).send().await?.error_for_status()?;
-
-            let body = response.text().await?;
-            let document = Html::parse_document(&body);
-
-            let script_selector = Selector::parse("script").unwrap();
-            let script = document
-                .select(&script_selector)
-                .map(|x| x.inner_html())
-                .find(|x| x.contains("media_server"))
-                .context("media server script not found")?;
-            let re = Regex::new(r#"media_server\s*:\s*(\d+)"#).unwrap();
-            let media_server = re
-                .captures(&script)
-                .and_then(|cap| cap.get(1))
-                .map(|mat| mat.as_str())
-                .context("media server number failed")?
-                .to_string();
-
-            let data = Self::extract_hentai_data(&document)?;
-
-            let pages = data
-                .images
-                .pages
-                .iter()
-                .enumerate()
-                .map(|(i, img)| {
-                    let img_url = format!(
-                        "https://i{}.nhentai.net/galleries/{}/{}{}",
-                        media_server,
-                        data.media_id,
-                        i + 1,
-                        match img.t.as_str() {
-                            "p" => ".png",
-                            "g" => ".gif",
-                            "w" => ".webp",
-                            _ => ".jpg", // Default to .jpg
-                        }
-                    );
-                    Page {
-                        index: i,
-                        url: String::new(), // nhentai does not require this
-                        image_url: ImageUrl::Web(img_url),
-                    }
-                })
-                .collect();
-
-            Ok(pages)
+            let response = self
+                .send_cached(CacheKind::Pages, self.http().get(&url))
+                .await?;
+            Self::parse_pages(&Html::parse_document(&response.text().await?))
         } else {
             bail!("Invalid chapter URL format: {}", url);
         }
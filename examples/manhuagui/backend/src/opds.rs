@@ -0,0 +1,804 @@
+//! Browses an OPDS catalog, either OPDS 1.2 (Atom) or OPDS 2.0 (JSON).
+//!
+//! Navigation entries become mangas whose chapters are the publications of the linked feed, and
+//! publications found directly in a listing become mangas with a single chapter. Publications
+//! offering OPDS-PSE page streaming are read page by page, anything else is downloaded into
+//! `[directory]/[feed title]/` and read with the matching local source.
+//!
+//! Mangas and chapters are identified by the hex encoded feed url, followed by a newline and
+//! the entry id for publications, since ids handed to the frontend can't contain slashes.
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder, Url};
+use serde_json::Value;
+use smol::io::AsyncWriteExt;
+
+use crate::{
+    Capabilities, ImageUrl, MangaBackend, MangaStatus, MangasPage, Page, SChapter, SManga,
+    SearchFilter,
+    epub::Epub,
+    http::{self, Policy},
+    library,
+    local_archive::LocalArchive,
+    pdf::Pdf,
+    settings::{SettingField, Settings, SettingsSchema},
+};
+
+const PSE_STREAM: &str = "http://vaemendis.net/opds-pse/stream";
+const ACQUISITION: &str = "http://opds-spec.org/acquisition";
+const THUMBNAIL: [&str; 4] = [
+    "http://opds-spec.org/image/thumbnail",
+    "http://opds-spec.org/thumbnail",
+    "http://opds-spec.org/image",
+    "http://opds-spec.org/cover",
+];
+const SORT_NEW: &str = "http://opds-spec.org/sort/new";
+/// Width requested from servers that resize streamed pages.
+const PAGE_WIDTH: u32 = 1404;
+/// Whole books take longer than the default timeout on slow connections.
+const DOWNLOAD_POLICY: Policy = Policy {
+    timeout: Duration::from_secs(600),
+    ..Policy::DEFAULT
+};
+/// Upper bound on `next` links followed when collecting the chapters of a feed.
+const MAX_FEED_PAGES: usize = 50;
+
+/// Downloadable formats, by preference, as `(mime type, extension)`.
+const FORMATS: [(&str, &str); 8] = [
+    ("application/vnd.comicbook+zip", "cbz"),
+    ("application/x-cbz", "cbz"),
+    ("application/zip", "zip"),
+    ("application/epub+zip", "epub"),
+    ("application/pdf", "pdf"),
+    ("application/vnd.comicbook-rar", "cbr"),
+    ("application/x-cbr", "cbr"),
+    ("application/x-rar-compressed", "cbr"),
+];
+
+#[derive(Debug, Clone, Default)]
+struct Link {
+    rel: String,
+    href: String,
+    r#type: String,
+    /// `pse:count`, the number of pages of a stream.
+    count: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Entry {
+    id: String,
+    title: String,
+    author: Option<String>,
+    summary: Option<String>,
+    categories: Vec<String>,
+    links: Vec<Link>,
+}
+
+impl Entry {
+    fn link(&self, matches: impl Fn(&Link) -> bool) -> Option<&Link> {
+        self.links.iter().find(|x| matches(x))
+    }
+
+    fn stream(&self) -> Option<&Link> {
+        self.link(|x| x.rel == PSE_STREAM && x.count.is_some())
+    }
+
+    fn acquisition(&self) -> Option<(&Link, &'static str)> {
+        let acquisitions = self
+            .links
+            .iter()
+            .filter(|x| x.rel.starts_with(ACQUISITION))
+            .collect::<Vec<_>>();
+        FORMATS.iter().find_map(|(mime, extension)| {
+            let link = acquisitions.iter().find(|x| {
+                x.r#type
+                    .split(';')
+                    .next()
+                    .is_some_and(|x| x.trim() == *mime)
+            })?;
+            Some((*link, *extension))
+        })
+    }
+
+    fn is_publication(&self) -> bool {
+        self.stream().is_some() || self.links.iter().any(|x| x.rel.starts_with(ACQUISITION))
+    }
+
+    /// The feed a navigation entry leads to.
+    fn navigation(&self) -> Option<&Link> {
+        self.link(|x| {
+            x.rel == "subsection"
+                || x.r#type.contains("profile=opds-catalog")
+                || x.r#type.contains("application/atom+xml")
+                || x.r#type.contains("application/opds+json")
+        })
+    }
+
+    fn thumbnail(&self) -> Option<String> {
+        THUMBNAIL
+            .iter()
+            .find_map(|rel| self.link(|x| x.rel == *rel))
+            .map(|x| x.href.clone())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Feed {
+    url: String,
+    title: String,
+    subtitle: Option<String>,
+    entries: Vec<Entry>,
+    links: Vec<Link>,
+}
+
+impl Feed {
+    fn link(&self, rel: &str) -> Option<&Link> {
+        self.links.iter().find(|x| x.rel == rel)
+    }
+}
+
+/// Resolves `href` against `base`, keeping URI template braces intact.
+fn absolute(base: &Url, href: &str) -> String {
+    base.join(href).map_or_else(
+        |_| href.to_owned(),
+        |x| x.to_string().replace("%7B", "{").replace("%7D", "}"),
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|x| match x {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(x).to_string()
+            }
+            _ => format!("%{x:02X}"),
+        })
+        .collect()
+}
+
+fn encode_id(reference: &str) -> String {
+    reference.bytes().map(|x| format!("{x:02x}")).collect()
+}
+
+fn decode_id(id: &str) -> anyhow::Result<String> {
+    let bytes = (0..id.len())
+        .step_by(2)
+        .map(|i| {
+            let byte = id.get(i..i + 2).context("odd length")?;
+            Ok(u8::from_str_radix(byte, 16)?)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .with_context(|| format!("invalid opds id {id}"))?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// The feed url and, for publications, the entry id an id refers to.
+fn reference(url: &ImageUrl) -> anyhow::Result<(String, Option<String>)> {
+    let ImageUrl::Web(id) = url else {
+        bail!("not an opds id: {url:?}");
+    };
+    let reference = decode_id(id)?;
+    Ok(match reference.split_once('\n') {
+        Some((feed, entry)) => (feed.to_owned(), Some(entry.to_owned())),
+        None => (reference, None),
+    })
+}
+
+fn parse_atom(url: &Url, xml: &str) -> anyhow::Result<Feed> {
+    let document = roxmltree::Document::parse(xml)?;
+    let root = document.root_element();
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|x| x.has_tag_name(name))
+            .and_then(|x| x.text())
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+    };
+    let links = |node: roxmltree::Node| {
+        node.children()
+            .filter(|x| x.has_tag_name("link"))
+            .filter_map(|x| {
+                Some(Link {
+                    rel: x.attribute("rel").unwrap_or_default().to_owned(),
+                    href: absolute(url, x.attribute("href")?),
+                    r#type: x.attribute("type").unwrap_or_default().to_owned(),
+                    count: x
+                        .attributes()
+                        .find(|x| x.name() == "count")
+                        .and_then(|x| x.value().parse().ok()),
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let entry = |node: roxmltree::Node| Entry {
+        id: child_text(node, "id").unwrap_or_default(),
+        title: child_text(node, "title").unwrap_or_default(),
+        author: node
+            .children()
+            .filter(|x| x.has_tag_name("author"))
+            .filter_map(|x| child_text(x, "name"))
+            .reduce(|a, b| format!("{a}, {b}")),
+        summary: child_text(node, "summary").or_else(|| child_text(node, "content")),
+        categories: node
+            .children()
+            .filter(|x| x.has_tag_name("category"))
+            .filter_map(|x| x.attribute("label").or_else(|| x.attribute("term")))
+            .map(str::to_owned)
+            .collect(),
+        links: links(node),
+    };
+
+    // a standalone entry document
+    if root.has_tag_name("entry") {
+        let entry = entry(root);
+        return Ok(Feed {
+            url: url.to_string(),
+            title: entry.title.clone(),
+            subtitle: None,
+            links: Vec::new(),
+            entries: vec![entry],
+        });
+    }
+    if !root.has_tag_name("feed") {
+        bail!("{url} is not an atom feed");
+    }
+    Ok(Feed {
+        url: url.to_string(),
+        title: child_text(root, "title").unwrap_or_default(),
+        subtitle: child_text(root, "subtitle"),
+        links: links(root),
+        entries: root
+            .children()
+            .filter(|x| x.has_tag_name("entry"))
+            .map(entry)
+            .map(|mut x| {
+                // entries without an id are still addressable by their title
+                if x.id.is_empty() {
+                    x.id.clone_from(&x.title);
+                }
+                x
+            })
+            .collect(),
+    })
+}
+
+fn parse_json(url: &Url, json: &Value) -> Feed {
+    let str_of = |value: &Value| value.as_str().map(str::to_owned);
+    let links = |value: &Value| {
+        value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|x| {
+                Some(Link {
+                    rel: match &x["rel"] {
+                        Value::Array(rels) => rels.first().and_then(str_of).unwrap_or_default(),
+                        rel => str_of(rel).unwrap_or_default(),
+                    },
+                    href: absolute(url, x["href"].as_str()?),
+                    r#type: str_of(&x["type"]).unwrap_or_default(),
+                    count: None,
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let names = |value: &Value| match value {
+        Value::Array(x) => x
+            .iter()
+            .filter_map(|x| str_of(x).or_else(|| str_of(&x["name"])))
+            .collect(),
+        Value::Null => Vec::new(),
+        x => str_of(x)
+            .or_else(|| str_of(&x["name"]))
+            .into_iter()
+            .collect(),
+    };
+    let publication = |value: &Value| {
+        let metadata = &value["metadata"];
+        let mut links = links(&value["links"]);
+        links.extend(links_of_images(url, &value["images"]));
+        Entry {
+            id: str_of(&metadata["identifier"])
+                .or_else(|| str_of(&metadata["title"]))
+                .unwrap_or_default(),
+            title: str_of(&metadata["title"]).unwrap_or_default(),
+            author: Some(names(&metadata["author"]).join(", ")).filter(|x| !x.is_empty()),
+            summary: str_of(&metadata["description"]),
+            categories: names(&metadata["subject"]),
+            links,
+        }
+    };
+    let navigation = |value: &Value| {
+        let link = links(&Value::Array(vec![value.clone()]));
+        Entry {
+            id: link.first().map(|x| x.href.clone()).unwrap_or_default(),
+            title: str_of(&value["title"]).unwrap_or_default(),
+            links: link
+                .into_iter()
+                .map(|mut x| {
+                    if x.rel.is_empty() {
+                        x.rel = "subsection".to_owned();
+                    }
+                    x
+                })
+                .collect(),
+            ..Default::default()
+        }
+    };
+
+    let mut entries = Vec::new();
+    for group in std::iter::once(json).chain(json["groups"].as_array().into_iter().flatten()) {
+        entries.extend(
+            group["navigation"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(navigation),
+        );
+        entries.extend(
+            group["publications"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(publication),
+        );
+    }
+    // a standalone publication
+    if entries.is_empty() && json["metadata"]["identifier"].is_string() {
+        entries.push(publication(json));
+    }
+    Feed {
+        url: url.to_string(),
+        title: str_of(&json["metadata"]["title"]).unwrap_or_default(),
+        subtitle: str_of(&json["metadata"]["subtitle"]),
+        links: links(&json["links"]),
+        entries,
+    }
+}
+
+fn links_of_images(url: &Url, images: &Value) -> Vec<Link> {
+    images
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|x| {
+            Some(Link {
+                rel: THUMBNAIL[0].to_owned(),
+                href: absolute(url, x["href"].as_str()?),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Opds {
+    base_url: String,
+    /// Where acquisitions are downloaded to.
+    directory: PathBuf,
+    login: Option<(String, String)>,
+    client: Client,
+    /// Reads downloaded PDFs, kept around since it caches the open document.
+    pdf: Pdf,
+}
+
+impl Opds {
+    pub const ID: &'static str = "opds";
+
+    pub fn new(base_url: impl Into<String>, directory: PathBuf) -> Self {
+        Self {
+            base_url: base_url.into().trim().to_owned(),
+            pdf: Pdf::new(directory.clone()),
+            directory,
+            login: None,
+            client: Client::new(),
+        }
+    }
+
+    /// Sends basic auth with every request.
+    #[must_use]
+    pub fn with_login(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.login = Some((username.into(), password.into()));
+        self
+    }
+
+    pub fn schema() -> SettingsSchema {
+        SettingsSchema(vec![
+            SettingField::string("url", "Catalog url", "")
+                .with_description("The root feed, e.g. http://server:8080/opds"),
+            SettingField::string("username", "Username", "")
+                .with_description("Leave empty if the catalog needs no login"),
+            SettingField::string("password", "Password", ""),
+            SettingField::string(
+                "directory",
+                "Download directory",
+                Self::default().directory.to_string_lossy(),
+            )
+            .with_description("Downloads are stored as [feed title]/[title].[format]"),
+        ])
+    }
+
+    /// Expects settings already validated against [`Self::schema`].
+    pub fn from_settings(settings: &Settings) -> Self {
+        let url = settings.string("url").unwrap_or_default();
+        let opds = settings.string("directory").map_or_else(
+            || Self {
+                base_url: url.trim().to_owned(),
+                ..Self::default()
+            },
+            |x| Self::new(url, PathBuf::from(x)),
+        );
+        match settings.string("username").filter(|x| !x.is_empty()) {
+            Some(username) => {
+                opds.with_login(username, settings.string("password").unwrap_or_default())
+            }
+            None => opds,
+        }
+    }
+
+    fn get(&self, url: &str) -> RequestBuilder {
+        let request = self.client.get(url);
+        match &self.login {
+            Some((username, password)) => request.basic_auth(username, Some(password)),
+            None => request,
+        }
+    }
+
+    async fn feed(&self, url: &str) -> anyhow::Result<Feed> {
+        let parsed = Url::parse(url).with_context(|| format!("invalid feed url {url}"))?;
+        let response = http::send(self.get(url)).await?.error_for_status()?;
+        let is_json = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .is_some_and(|x| x.contains("json"));
+        let body = response.text().await?;
+        if is_json || body.trim_start().starts_with('{') {
+            Ok(parse_json(&parsed, &serde_json::from_str(&body)?))
+        } else {
+            parse_atom(&parsed, &body)
+        }
+    }
+
+    async fn root(&self) -> anyhow::Result<Feed> {
+        if self.base_url.is_empty() {
+            bail!("set the catalog url in the settings of {self}");
+        }
+        self.feed(&self.base_url).await
+    }
+
+    /// Follows `next` links until the `page`th page of a feed, `page` starting at 1.
+    async fn feed_page(&self, url: &str, page: u32) -> anyhow::Result<Option<Feed>> {
+        let mut feed = self.feed(url).await?;
+        for _ in 1..page {
+            match feed.link("next") {
+                Some(next) => feed = self.feed(&next.href.clone()).await?,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(feed))
+    }
+
+    fn manga(feed: &Feed, entry: &Entry) -> SManga {
+        let reference = match entry.navigation().filter(|_| !entry.is_publication()) {
+            Some(link) => link.href.clone(),
+            None => format!("{}\n{}", feed.url, entry.id),
+        };
+        SManga {
+            url: ImageUrl::Web(encode_id(&reference)),
+            title: entry.title.clone(),
+            thumbnail_url: entry.thumbnail(),
+            author: entry.author.clone(),
+            description: entry.summary.clone(),
+            genre: Some(entry.categories.join(", ")).filter(|x| !x.is_empty()),
+            status: MangaStatus::Unknown,
+            last_updated_time: String::new(),
+        }
+    }
+
+    async fn listing(&self, url: &str, page: u32) -> anyhow::Result<MangasPage> {
+        let Some(feed) = self.feed_page(url, page).await? else {
+            return Ok(MangasPage::default());
+        };
+        Ok(MangasPage {
+            mangas: feed
+                .entries
+                .iter()
+                .filter(|x| x.is_publication() || x.navigation().is_some())
+                .map(|x| Self::manga(&feed, x))
+                .collect(),
+            has_next_page: feed.link("next").is_some(),
+        })
+    }
+
+    /// The search url for `query`, from an OpenSearch description or a templated link.
+    async fn search_url(&self, root: &Feed, query: &str) -> anyhow::Result<String> {
+        let link = root
+            .link("search")
+            .context("the catalog does not support searching")?;
+        let template = if link.r#type.contains("opensearchdescription") {
+            let response = http::send(self.get(&link.href)).await?;
+            let body = response.error_for_status()?.text().await?;
+            let description = roxmltree::Document::parse(&body)?;
+            let urls = description
+                .descendants()
+                .filter(|x| x.has_tag_name("Url"))
+                .collect::<Vec<_>>();
+            let url = urls
+                .iter()
+                .find(|x| x.attribute("type").is_some_and(|x| x.contains("atom")))
+                .or_else(|| urls.first())
+                .and_then(|x| x.attribute("template"))
+                .context("OpenSearch description without a url template")?;
+            absolute(&Url::parse(&link.href)?, url)
+        } else {
+            link.href.clone()
+        };
+        let query = percent_encode(query);
+        Ok(template
+            .replace("{searchTerms}", &query)
+            .replace("{?query}", &format!("?query={query}"))
+            .replace("{query}", &query)
+            .replace("{startPage?}", "")
+            .replace("{startIndex?}", "")
+            .replace("{count?}", "")
+            .replace("{language?}", "")
+            .replace("{inputEncoding?}", "UTF-8")
+            .replace("{outputEncoding?}", "UTF-8"))
+    }
+
+    /// The feed a publication reference points to, along with the publication.
+    async fn publication(&self, feed: &str, id: &str) -> anyhow::Result<(Feed, Entry)> {
+        let feed = self.feed(feed).await?;
+        let entry = feed
+            .entries
+            .iter()
+            .find(|x| x.id == id)
+            .cloned()
+            .with_context(|| format!("{id} is no longer in {}", feed.url))?;
+        Ok((feed, entry))
+    }
+
+    /// Downloads an acquisition unless it was already, returning its path.
+    async fn download(&self, feed: &Feed, entry: &Entry) -> anyhow::Result<PathBuf> {
+        let (link, extension) = entry.acquisition().with_context(|| {
+            format!(
+                "{} offers neither page streaming nor a supported format",
+                entry.title
+            )
+        })?;
+        let directory = self.directory.join(library::file_name(&feed.title));
+        let path = directory.join(format!("{}.{extension}", library::file_name(&entry.title)));
+        if path.exists() {
+            return Ok(path);
+        }
+        smol::fs::create_dir_all(&directory).await?;
+        let response = http::send_with(self.get(&link.href), &DOWNLOAD_POLICY).await?;
+        let mut response = response.error_for_status()?;
+        // volumes can be hundreds of megabytes, more than is worth holding in memory
+        let part = path.with_extension("part");
+        let mut file = smol::fs::File::create(&part).await?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        drop(file);
+        smol::fs::rename(&part, &path).await?;
+        Ok(path)
+    }
+
+    async fn local_pages(&self, path: &Path) -> anyhow::Result<Vec<Page>> {
+        let is = |extension: &str| path.extension().is_some_and(|x| x == extension);
+        let local = |url| SChapter {
+            url,
+            name: String::new(),
+            chapter_number: 1.0,
+            date_upload: None,
+        };
+        if is("epub") {
+            let chapter = local(ImageUrl::new_epub(path.to_owned()));
+            return smol::unblock(move || Epub::fetch_pages(&chapter)).await;
+        }
+        let chapter = local(ImageUrl::LocalFile {
+            path: path.to_owned(),
+            entry: None,
+        });
+        if is("pdf") {
+            self.pdf.fetch_pages(&chapter).await
+        } else {
+            LocalArchive::new(self.directory.clone())
+                .fetch_pages(&chapter)
+                .await
+        }
+    }
+}
+
+impl Default for Opds {
+    fn default() -> Self {
+        Self::new(
+            String::new(),
+            std::env::home_dir()
+                .context("no home dir")
+                .unwrap()
+                .join("mangarr-library"),
+        )
+    }
+}
+
+impl Display for Opds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OPDS")
+    }
+}
+
+#[async_trait]
+impl MangaBackend for Opds {
+    fn id(&self) -> String {
+        Self::ID.to_owned()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            search: true,
+            popular: true,
+            latest: true,
+            ..Default::default()
+        }
+    }
+
+    fn settings_schema(&self) -> SettingsSchema {
+        Self::schema()
+    }
+
+    async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga> {
+        let url = ImageUrl::Web(id.to_owned());
+        match reference(&url)? {
+            (feed, Some(entry)) => {
+                let (feed, entry) = self.publication(&feed, &entry).await?;
+                Ok(Self::manga(&feed, &entry))
+            }
+            (feed, None) => {
+                let feed = self.feed(&feed).await?;
+                Ok(SManga {
+                    url,
+                    title: feed.title,
+                    thumbnail_url: feed.entries.iter().find_map(Entry::thumbnail),
+                    author: None,
+                    description: feed.subtitle,
+                    genre: None,
+                    status: MangaStatus::Unknown,
+                    last_updated_time: String::new(),
+                })
+            }
+        }
+    }
+
+    /// Uses the OpenSearch description or search template of the root feed.
+    async fn search(
+        &self,
+        query: &str,
+        page: u32,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<MangasPage> {
+        let root = self.root().await?;
+        let url = self.search_url(&root, query.trim()).await?;
+        self.listing(&url, page).await
+    }
+
+    /// The root feed of the catalog.
+    async fn popular(&self, page: u32) -> anyhow::Result<MangasPage> {
+        self.listing(&self.root().await?.url, page).await
+    }
+
+    /// The feed of new publications if the catalog advertises one, otherwise the root feed.
+    async fn latest(&self, page: u32) -> anyhow::Result<MangasPage> {
+        let root = self.root().await?;
+        let url = root
+            .link(SORT_NEW)
+            .or_else(|| {
+                root.entries
+                    .iter()
+                    .find_map(|x| x.link(|x| x.rel == SORT_NEW))
+            })
+            .map_or_else(|| root.url.clone(), |x| x.href.clone());
+        self.listing(&url, page).await
+    }
+
+    async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
+        let (url, entry) = reference(&manga.url)?;
+        if entry.is_some() {
+            return Ok(vec![SChapter {
+                url: manga.url.clone(),
+                name: manga.title.clone(),
+                chapter_number: 1.0,
+                date_upload: None,
+            }]);
+        }
+        let mut chapters = Vec::new();
+        let mut next = Some(url);
+        for _ in 0..MAX_FEED_PAGES {
+            let Some(url) = next.take() else {
+                break;
+            };
+            let feed = self.feed(&url).await?;
+            chapters.extend(
+                feed.entries
+                    .iter()
+                    .filter(|x| x.is_publication())
+                    .map(|x| SChapter {
+                        url: ImageUrl::Web(encode_id(&format!("{}\n{}", feed.url, x.id))),
+                        name: x.title.clone(),
+                        chapter_number: 0.0,
+                        date_upload: None,
+                    }),
+            );
+            next = feed.link("next").map(|x| x.href.clone());
+        }
+        for (i, chapter) in chapters.iter_mut().enumerate() {
+            chapter.chapter_number = (i + 1) as f32;
+        }
+        Ok(chapters)
+    }
+
+    /// Streams the pages with OPDS-PSE when possible, otherwise downloads the publication.
+    async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
+        let (feed, Some(id)) = reference(&chapter.url)? else {
+            bail!("{} is not a publication", chapter.name);
+        };
+        let (feed, entry) = self.publication(&feed, &id).await?;
+        if let Some(stream) = entry.stream() {
+            return Ok((0..stream.count.unwrap_or_default())
+                .map(|index| Page {
+                    index,
+                    url: String::new(),
+                    image_url: ImageUrl::Web(
+                        stream
+                            .href
+                            .replace("{pageNumber}", &index.to_string())
+                            .replace("{maxWidth}", &PAGE_WIDTH.to_string()),
+                    ),
+                })
+                .collect());
+        }
+        let path = self.download(&feed, &entry).await?;
+        self.local_pages(&path).await
+    }
+
+    fn client(&self) -> Option<Client> {
+        Some(self.client.clone())
+    }
+
+    async fn fetch_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        match url {
+            ImageUrl::Web(url) => {
+                let response = http::send(self.get(url)).await?.error_for_status()?;
+                Ok(response.bytes().await?.to_vec())
+            }
+            ImageUrl::LocalFile { path, .. } if path.extension().is_some_and(|x| x == "pdf") => {
+                self.pdf.fetch_image(url).await
+            }
+            _ => self.read_local_image(url),
+        }
+    }
+
+    fn read_local_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        match url {
+            ImageUrl::LocalEpub { .. } => Epub::new(self.directory.clone()).read_local_image(url),
+            ImageUrl::LocalFile { path, .. } if path.extension().is_some_and(|x| x == "pdf") => {
+                self.pdf.read_local_image(url)
+            }
+            ImageUrl::LocalFile { .. } => {
+                LocalArchive::new(self.directory.clone()).read_local_image(url)
+            }
+            ImageUrl::Web(_) => bail!("{url:?} is not a local file"),
+        }
+    }
+}
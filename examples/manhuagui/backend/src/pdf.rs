@@ -0,0 +1,472 @@
+//! Every PDF in the library directory is a manga. Its outline splits it into chapters, or the
+//! whole file is a single chapter when there is none. Pages are rasterized on demand when the
+//! reader fetches them.
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+use hayro::{
+    PixmapSettings, RenderCache, RenderSettings,
+    hayro_interpret::InterpreterSettings,
+    hayro_syntax::{
+        Pdf as Document,
+        object::{Array, Dict, MaybeRef, Name, Object, ObjectIdentifier, String as PdfString},
+    },
+    vello_cpu::color::palette::css::WHITE,
+};
+use reqwest::Client;
+
+use crate::{
+    Capabilities, ImageUrl, MangaBackend, MangaStatus, MangasPage, Page, SChapter, SManga,
+    SearchFilter,
+    local_archive::natural_cmp,
+    settings::{SettingField, Settings, SettingsSchema},
+};
+
+/// Pages are rendered at about the height of the tablet's screen.
+const RENDER_HEIGHT: f32 = 1872.0;
+
+pub struct Pdf {
+    base: PathBuf,
+    /// The last opened document, since pages of the same file are read one after another.
+    document: Mutex<Option<(PathBuf, Arc<Document>)>>,
+}
+
+/// Decodes a PDF text string, either UTF-16BE with a byte order mark or PDFDocEncoding, which
+/// matches Latin-1 for printable characters.
+fn decode_text(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xfe, 0xff]) {
+        Some(utf16) => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|x| u16::from_be_bytes([x[0], x[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        None => bytes.iter().map(|x| char::from(*x)).collect(),
+    }
+    .trim()
+    .to_owned()
+}
+
+/// Index of the page a destination points to, see section 12.3.2 of the PDF spec.
+fn destination_page(document: &Document, catalog: &Dict, destination: &Object) -> Option<usize> {
+    let array = match destination {
+        Object::Array(array) => array.clone(),
+        Object::Name(name) => named_destination(catalog, name.as_ref())?,
+        Object::String(name) => named_destination(catalog, name.as_ref())?,
+        Object::Dict(dict) => dict.get::<Array>("D")?,
+        _ => return None,
+    };
+    match array.raw_iter().next()? {
+        MaybeRef::Ref(page) => {
+            let id = ObjectIdentifier::from(page);
+            document
+                .pages()
+                .iter()
+                .position(|x| x.raw().obj_id() == Some(id))
+        }
+        // remote destinations use page numbers
+        MaybeRef::NotRef(Object::Number(number)) => Some(number.as_f64() as usize),
+        MaybeRef::NotRef(_) => None,
+    }
+}
+
+/// Looks a name up in the `/Dests` dictionary of PDF 1.1 or the `/Dests` name tree.
+fn named_destination<'a>(catalog: &Dict<'a>, name: &[u8]) -> Option<Array<'a>> {
+    let entry = catalog
+        .get::<Dict>("Dests")
+        .and_then(|x| x.get::<Object>(name))
+        .or_else(|| {
+            let tree = catalog.get::<Dict>("Names")?.get::<Dict>("Dests")?;
+            name_tree_lookup(&tree, name, 0)
+        })?;
+    match entry {
+        Object::Array(array) => Some(array),
+        Object::Dict(dict) => dict.get::<Array>("D"),
+        _ => None,
+    }
+}
+
+fn name_tree_lookup<'a>(node: &Dict<'a>, name: &[u8], depth: usize) -> Option<Object<'a>> {
+    // guards against cyclic trees
+    if depth > 32 {
+        return None;
+    }
+    if let Some(names) = node.get::<Array>("Names") {
+        let mut items = names.flex_iter();
+        while let Some(key) = items.next::<PdfString>() {
+            let value = items.next::<Object>()?;
+            if key.as_bytes() == name {
+                return Some(value);
+            }
+        }
+    }
+    node.get::<Array>("Kids")?
+        .iter::<Dict>()
+        .find_map(|kid| name_tree_lookup(&kid, name, depth + 1))
+}
+
+/// Where each top level outline entry starts, as `(page index, title)`. Pages before the first
+/// entry belong to the first chapter.
+fn outline_chapters(document: &Document) -> Vec<(usize, String)> {
+    let xref = document.xref();
+    let Some(catalog) = xref.get::<Dict>(xref.root_id()) else {
+        return Vec::new();
+    };
+    let Some(mut first) = catalog
+        .get::<Dict>("Outlines")
+        .and_then(|x| x.get::<Dict>("First"))
+    else {
+        return Vec::new();
+    };
+    // a single entry for the whole book, its children are the chapters
+    if first.get::<Dict>("Next").is_none()
+        && let Some(child) = first.get::<Dict>("First")
+    {
+        first = child;
+    }
+
+    let mut chapters = Vec::<(usize, String)>::new();
+    let mut item = Some(first);
+    // bounded in case the linked list is cyclic
+    for _ in 0..document.pages().len() * 4 + 16 {
+        let Some(current) = item.take() else {
+            break;
+        };
+        let destination = current.get::<Object>("Dest").or_else(|| {
+            let action = current.get::<Dict>("A")?;
+            (action.get::<Name>("S")?.as_ref() == b"GoTo")
+                .then(|| action.get::<Object>("D"))
+                .flatten()
+        });
+        let page = destination.and_then(|x| destination_page(document, &catalog, &x));
+        if let Some(page) = page.filter(|x| *x < document.pages().len())
+            && chapters.last().is_none_or(|(last, _)| *last < page)
+        {
+            let title = current
+                .get::<PdfString>("Title")
+                .map(|x| decode_text(x.as_bytes()))
+                .unwrap_or_default();
+            chapters.push((page, title));
+        }
+        item = current.get::<Dict>("Next");
+    }
+    if let Some(first) = chapters.first_mut() {
+        first.0 = 0;
+    }
+    chapters
+}
+
+fn render_page(document: &Document, index: usize) -> anyhow::Result<Vec<u8>> {
+    let page = document
+        .pages()
+        .get(index)
+        .with_context(|| format!("no page {index}"))?;
+    let (_, height) = page.render_dimensions();
+    let scale = (RENDER_HEIGHT / height).clamp(0.5, 4.0);
+    let pixmap = hayro::render(
+        page,
+        &RenderCache::new(),
+        &InterpreterSettings::default(),
+        &RenderSettings::default(),
+        &PixmapSettings {
+            x_scale: scale,
+            y_scale: scale,
+            bg_color: WHITE,
+        },
+    );
+    pixmap
+        .into_png()
+        .map_err(|err| anyhow::anyhow!("can't encode page {index}: {err:?}"))
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn open_document(path: &Path) -> anyhow::Result<Arc<Document>> {
+    let data = std::fs::read(path).with_context(|| format!("can't read {}", path.display()))?;
+    let document = Document::new(data)
+        .map_err(|err| anyhow::anyhow!("can't open {}: {err:?}", path.display()))?;
+    Ok(Arc::new(document))
+}
+
+impl Pdf {
+    pub const ID: &'static str = "pdf";
+
+    #[must_use]
+    pub const fn new(path: PathBuf) -> Self {
+        Self {
+            base: path,
+            document: Mutex::new(None),
+        }
+    }
+
+    pub fn schema() -> SettingsSchema {
+        SettingsSchema(vec![
+            SettingField::string(
+                "directory",
+                "Library directory",
+                Self::default().base.to_string_lossy(),
+            )
+            .with_description("Every PDF directly inside is a manga"),
+        ])
+    }
+
+    /// Expects settings already validated against [`Self::schema`].
+    pub fn from_settings(settings: &Settings) -> Self {
+        settings
+            .string("directory")
+            .map_or_else(Self::default, |x| Self::new(PathBuf::from(x)))
+    }
+
+    fn cached(&self, path: &Path) -> anyhow::Result<Option<Arc<Document>>> {
+        let cached = self
+            .document
+            .lock()
+            .map_err(|_| anyhow::anyhow!("pdf cache poisoned"))?;
+        Ok(cached
+            .as_ref()
+            .filter(|(cached_path, _)| cached_path == path)
+            .map(|(_, document)| document.clone()))
+    }
+
+    fn keep(&self, path: &Path, document: &Arc<Document>) -> anyhow::Result<()> {
+        *self
+            .document
+            .lock()
+            .map_err(|_| anyhow::anyhow!("pdf cache poisoned"))? =
+            Some((path.to_owned(), document.clone()));
+        Ok(())
+    }
+
+    fn document(&self, path: &Path) -> anyhow::Result<Arc<Document>> {
+        if let Some(document) = self.cached(path)? {
+            return Ok(document);
+        }
+        let document = open_document(path)?;
+        self.keep(path, &document)?;
+        Ok(document)
+    }
+
+    /// [`Self::document`] with the reading and parsing off the executor.
+    async fn load(&self, path: &Path) -> anyhow::Result<Arc<Document>> {
+        if let Some(document) = self.cached(path)? {
+            return Ok(document);
+        }
+        let owned = path.to_owned();
+        let document = smol::unblock(move || open_document(&owned)).await?;
+        self.keep(path, &document)?;
+        Ok(document)
+    }
+
+    /// Every PDF in the library, in natural order of their file names.
+    fn files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = std::fs::read_dir(&self.base)
+            .with_context(|| format!("can't read library {}", self.base.display()))?
+            .filter_map(|x| x.ok())
+            .map(|x| x.path())
+            .filter(|x| x.is_file() && x.extension().is_some_and(|x| x.eq_ignore_ascii_case("pdf")))
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        Ok(files)
+    }
+
+    /// A listing entry, without opening the file.
+    fn summary(path: &Path) -> SManga {
+        SManga {
+            url: ImageUrl::LocalFile {
+                path: path.to_owned(),
+                entry: None,
+            },
+            title: stem(path),
+            thumbnail_url: None,
+            author: None,
+            description: None,
+            genre: None,
+            status: MangaStatus::Unknown,
+            last_updated_time: String::new(),
+        }
+    }
+
+    /// The file and, if present, the page index a url points into.
+    fn location(url: &ImageUrl) -> anyhow::Result<(PathBuf, Option<usize>)> {
+        let ImageUrl::LocalFile { path, entry } = url else {
+            bail!("not a local file: {url:?}");
+        };
+        let page = entry.as_deref().map(str::parse).transpose()?;
+        Ok((path.clone(), page))
+    }
+}
+
+impl std::fmt::Debug for Pdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pdf")
+            .field("base", &self.base)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Display for Pdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PDF library")
+    }
+}
+
+impl Default for Pdf {
+    fn default() -> Self {
+        Self::new(
+            std::env::home_dir()
+                .context("no home dir")
+                .unwrap()
+                .join("mangarr-library"),
+        )
+    }
+}
+
+#[async_trait]
+impl MangaBackend for Pdf {
+    fn id(&self) -> String {
+        Self::ID.to_owned()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            search_by_id: true,
+            search: true,
+            popular: true,
+            ..Default::default()
+        }
+    }
+
+    fn settings_schema(&self) -> SettingsSchema {
+        Self::schema()
+    }
+
+    /// `id` is the file name of the PDF inside the library.
+    async fn search_by_id(&self, id: &str) -> anyhow::Result<SManga> {
+        let path = self.base.join(id);
+        if id.contains(['/', '\\']) || !path.is_file() {
+            bail!("no pdf {id} in {}", self.base.display());
+        }
+        let document = self.load(&path).await?;
+        let metadata = document.metadata();
+        let text = |x: &Option<Vec<u8>>| x.as_deref().map(decode_text).filter(|x| !x.is_empty());
+        Ok(SManga {
+            title: text(&metadata.title).unwrap_or_else(|| stem(&path)),
+            author: text(&metadata.author),
+            description: text(&metadata.subject),
+            genre: text(&metadata.keywords),
+            ..Self::summary(&path)
+        })
+    }
+
+    /// Matches file names case-insensitively, the whole library is a single page.
+    async fn search(
+        &self,
+        query: &str,
+        _page: u32,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<MangasPage> {
+        let query = query.trim().to_lowercase();
+        Ok(MangasPage {
+            mangas: self
+                .files()?
+                .iter()
+                .map(|x| Self::summary(x))
+                .filter(|x| x.title.to_lowercase().contains(&query))
+                .collect(),
+            has_next_page: false,
+        })
+    }
+
+    async fn popular(&self, _page: u32) -> anyhow::Result<MangasPage> {
+        self.search("", 1, &SearchFilter::default()).await
+    }
+
+    async fn fetch_chapters(&self, manga: &SManga) -> anyhow::Result<Vec<SChapter>> {
+        let (path, _) = Self::location(&manga.url)?;
+        let document = self.load(&path).await?;
+        let chapters = outline_chapters(&document);
+        if chapters.len() < 2 {
+            return Ok(vec![SChapter {
+                name: stem(&path),
+                url: ImageUrl::LocalFile { path, entry: None },
+                chapter_number: 1.0,
+                date_upload: None,
+            }]);
+        }
+        Ok(chapters
+            .into_iter()
+            .enumerate()
+            .map(|(i, (start, title))| SChapter {
+                url: ImageUrl::LocalFile {
+                    path: path.clone(),
+                    entry: Some(start.to_string()),
+                },
+                name: if title.is_empty() {
+                    format!("Chapter {}", i + 1)
+                } else {
+                    title
+                },
+                chapter_number: (i + 1) as f32,
+                date_upload: None,
+            })
+            .collect())
+    }
+
+    /// A chapter runs from its first page up to the start of the next outline entry.
+    async fn fetch_pages(&self, chapter: &SChapter) -> anyhow::Result<Vec<Page>> {
+        let (path, start) = Self::location(&chapter.url)?;
+        let document = self.load(&path).await?;
+        let len = document.pages().len();
+        let range = match start {
+            None => 0..len,
+            Some(start) => {
+                let end = outline_chapters(&document)
+                    .into_iter()
+                    .map(|(page, _)| page)
+                    .find(|page| *page > start)
+                    .unwrap_or(len);
+                start..end
+            }
+        };
+        Ok(range
+            .enumerate()
+            .map(|(index, page)| Page {
+                index,
+                url: String::new(),
+                image_url: ImageUrl::LocalFile {
+                    path: path.clone(),
+                    entry: Some(page.to_string()),
+                },
+            })
+            .collect())
+    }
+
+    fn client(&self) -> Option<Client> {
+        None
+    }
+
+    /// Rendering takes a while, so it runs off the executor.
+    async fn fetch_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        let (path, page) = Self::location(url)?;
+        let page = page.context("not a page of a pdf")?;
+        let document = self.load(&path).await?;
+        smol::unblock(move || render_page(&document, page)).await
+    }
+
+    fn read_local_image(&self, url: &ImageUrl) -> anyhow::Result<Vec<u8>> {
+        let (path, page) = Self::location(url)?;
+        render_page(
+            &*self.document(&path)?,
+            page.context("not a page of a pdf")?,
+        )
+    }
+}
@@ -0,0 +1,293 @@
+//! Every manga source the app knows about, looked up by a stable string id.
+//!
+//! Sources register an id, display name, language and constructor, either here or through a
+//! [`declarative`] definition. Instances are created lazily and shared, and [`Source`]
+//! serializes as its id so saved bookshelves survive changes to a source's internals.
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    ops::Deref,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::IgnoredAny};
+
+use crate::{
+    MangaBackend, declarative,
+    epub::Epub,
+    komga::Komga,
+    local_archive::LocalArchive,
+    manhuagui::{Manhuagui, Preferences},
+    nhentai::NHentai,
+    opds::Opds,
+    pdf::Pdf,
+    settings::{Settings, SettingsSchema},
+};
+
+/// Receives the source's settings, already validated against its schema.
+type Constructor = Arc<dyn Fn(&Settings) -> anyhow::Result<Source> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct SourceInfo {
+    pub id: String,
+    pub name: String,
+    pub lang: String,
+    pub description: String,
+    pub settings: SettingsSchema,
+    constructor: Constructor,
+}
+
+impl SourceInfo {
+    pub fn new(
+        id: impl Into<String>,
+        name: impl Into<String>,
+        lang: impl Into<String>,
+        constructor: impl Fn(&Settings) -> anyhow::Result<Source> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+            lang: lang.into(),
+            description: String::new(),
+            settings: SettingsSchema::default(),
+            constructor: Arc::new(constructor),
+        }
+    }
+
+    #[must_use]
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_settings(mut self, settings: SettingsSchema) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// The saved settings, or the defaults if they no longer match the schema.
+    pub fn load_settings(&self) -> Settings {
+        match Settings::load(&self.id).and_then(|x| self.settings.validate(&x)) {
+            Ok(settings) => settings,
+            Err(err) => {
+                println!("ignoring saved settings of {}: {err:#}", self.id);
+                self.settings.defaults()
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for SourceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceInfo")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("lang", &self.lang)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SourceRegistry {
+    sources: Vec<SourceInfo>,
+    instances: HashMap<String, Source>,
+}
+
+static REGISTRY: LazyLock<RwLock<SourceRegistry>> =
+    LazyLock::new(|| RwLock::new(SourceRegistry::with_builtin()));
+
+impl SourceRegistry {
+    /// The registry used when deserializing [`Source`]s.
+    pub fn global() -> &'static RwLock<Self> {
+        &REGISTRY
+    }
+
+    pub fn with_builtin() -> Self {
+        let mut registry = Self::default();
+        registry.register(
+            SourceInfo::new(Manhuagui::ID, "Manhuagui", "zh", |settings| {
+                Ok(Source::new(Manhuagui::new(Preferences::from_settings(
+                    settings,
+                ))?))
+            })
+            .with_description("The default backend. Works without any configuration.")
+            .with_settings(Preferences::schema()),
+        );
+        registry.register(
+            SourceInfo::new(NHentai::ID, "NHentai", "all", |settings| {
+                Ok(Source::new(NHentai::from_settings(settings)?))
+            })
+            .with_description("Prompts for Cloudflare credentials when the site challenges.")
+            .with_settings(NHentai::schema()),
+        );
+        registry.register(
+            SourceInfo::new(Epub::ID, "Epub", "all", |settings| {
+                Ok(Source::new(Epub::from_settings(settings)))
+            })
+            .with_description(
+                "Reading books from an epub, requires that you place according to folder structure",
+            )
+            .with_settings(Epub::schema()),
+        );
+        registry.register(
+            SourceInfo::new(LocalArchive::ID, "Local library", "all", |settings| {
+                Ok(Source::new(LocalArchive::from_settings(settings)))
+            })
+            .with_description("Reads CBZ, ZIP and CBR archives or folders of images from disk.")
+            .with_settings(LocalArchive::schema()),
+        );
+        registry.register(
+            SourceInfo::new(Pdf::ID, "PDF library", "all", |settings| {
+                Ok(Source::new(Pdf::from_settings(settings)))
+            })
+            .with_description("Reads PDFs from disk, split into chapters by their outline.")
+            .with_settings(Pdf::schema()),
+        );
+        registry.register(
+            SourceInfo::new(Opds::ID, "OPDS", "all", |settings| {
+                Ok(Source::new(Opds::from_settings(settings)))
+            })
+            .with_description("Browses an OPDS 1.2 or 2.0 catalog, such as Calibre-Web or Kavita.")
+            .with_settings(Opds::schema()),
+        );
+        registry.register(
+            SourceInfo::new(Komga::ID, "Komga", "all", |settings| {
+                Ok(Source::new(Komga::from_settings(settings)))
+            })
+            .with_description("Streams from a Komga server and keeps its read progress in sync.")
+            .with_settings(Komga::schema()),
+        );
+        // definitions may override builtin sources by reusing their id
+        if let Some(dir) = declarative::sources_dir() {
+            for definition in declarative::load_dir(&dir) {
+                match definition {
+                    Ok(definition) => registry.register(definition.into_source_info()),
+                    Err(err) => println!("skipping source definition: {err:#}"),
+                }
+            }
+        }
+        registry
+    }
+
+    /// Adds a source, replacing any source already registered under the same id.
+    pub fn register(&mut self, info: SourceInfo) {
+        self.instances.remove(&info.id);
+        match self.sources.iter_mut().find(|x| x.id == info.id) {
+            Some(existing) => *existing = info,
+            None => self.sources.push(info),
+        }
+    }
+
+    pub fn unregister(&mut self, id: &str) {
+        self.instances.remove(id);
+        self.sources.retain(|x| x.id != id);
+    }
+
+    pub fn get(&self, id: &str) -> Option<&SourceInfo> {
+        self.sources.iter().find(|x| x.id == id)
+    }
+
+    /// All sources in registration order.
+    pub fn sources(&self) -> &[SourceInfo] {
+        &self.sources
+    }
+
+    /// Returns the shared instance of a source, constructing it on first use.
+    pub fn create(&mut self, id: &str) -> anyhow::Result<Source> {
+        if let Some(source) = self.instances.get(id) {
+            return Ok(source.clone());
+        }
+        let info = self
+            .get(id)
+            .with_context(|| format!("unknown source `{id}`"))?;
+        let source = (info.constructor)(&info.load_settings())?;
+        if source.id() != id {
+            bail!(
+                "source `{id}` constructed a source with id `{}`",
+                source.id()
+            );
+        }
+        self.instances.insert(id.to_owned(), source.clone());
+        Ok(source)
+    }
+
+    /// Validates and saves new settings, then rebuilds the source with them.
+    pub fn update_settings(&mut self, id: &str, values: &Settings) -> anyhow::Result<Source> {
+        let info = self
+            .get(id)
+            .with_context(|| format!("unknown source `{id}`"))?;
+        let settings = info.settings.validate(values)?;
+        let source = (info.constructor)(&settings)?;
+        settings.save(id)?;
+        self.instances.insert(id.to_owned(), source.clone());
+        Ok(source)
+    }
+}
+
+/// Shorthand for creating a source from the global registry.
+pub fn source(id: &str) -> anyhow::Result<Source> {
+    SourceRegistry::global()
+        .write()
+        .map_err(|_| anyhow::anyhow!("source registry poisoned"))?
+        .create(id)
+}
+
+/// A shared handle to a source.
+#[derive(Clone)]
+pub struct Source(Arc<dyn MangaBackend>);
+
+impl Source {
+    pub fn new(backend: impl MangaBackend + 'static) -> Self {
+        Self(Arc::new(backend))
+    }
+}
+
+impl Deref for Source {
+    type Target = dyn MangaBackend;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl std::fmt::Debug for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for Source {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Id(String),
+            /// Bookshelves saved before the registry stored the whole backend, keyed by the
+            /// name of its enum variant.
+            Legacy(HashMap<String, IgnoredAny>),
+        }
+        let id = match Repr::deserialize(deserializer)? {
+            Repr::Id(id) => id,
+            Repr::Legacy(map) => map
+                .into_keys()
+                .next()
+                .ok_or_else(|| serde::de::Error::custom("empty source"))?
+                .to_lowercase(),
+        };
+        source(&id).map_err(serde::de::Error::custom)
+    }
+}
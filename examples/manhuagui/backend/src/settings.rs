@@ -0,0 +1,173 @@
+//! Typed per-source settings, described by a schema the frontend renders and persisted under
+//! `~/.config/mangarr/settings/<source id>.json`.
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::{Context, bail};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum SettingValue {
+    Bool(bool),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SettingOption {
+    pub value: String,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SettingKind {
+    Bool,
+    Enum { options: Vec<SettingOption> },
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SettingField {
+    pub key: String,
+    pub label: String,
+    pub description: String,
+    #[serde(flatten)]
+    pub kind: SettingKind,
+    pub default: SettingValue,
+}
+
+impl SettingField {
+    pub fn bool(key: &str, label: &str, default: bool) -> Self {
+        Self {
+            key: key.to_owned(),
+            label: label.to_owned(),
+            description: String::new(),
+            kind: SettingKind::Bool,
+            default: SettingValue::Bool(default),
+        }
+    }
+
+    /// `options` are `(value, label)` pairs, `default` must be one of the values.
+    pub fn choice(key: &str, label: &str, options: &[(&str, &str)], default: &str) -> Self {
+        Self {
+            key: key.to_owned(),
+            label: label.to_owned(),
+            description: String::new(),
+            kind: SettingKind::Enum {
+                options: options
+                    .iter()
+                    .map(|(value, label)| SettingOption {
+                        value: (*value).to_owned(),
+                        label: (*label).to_owned(),
+                    })
+                    .collect(),
+            },
+            default: SettingValue::String(default.to_owned()),
+        }
+    }
+
+    pub fn string(key: &str, label: &str, default: impl Into<String>) -> Self {
+        Self {
+            key: key.to_owned(),
+            label: label.to_owned(),
+            description: String::new(),
+            kind: SettingKind::String,
+            default: SettingValue::String(default.into()),
+        }
+    }
+
+    #[must_use]
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    fn validate(&self, value: &SettingValue) -> anyhow::Result<()> {
+        match (&self.kind, value) {
+            (SettingKind::Bool, SettingValue::Bool(_))
+            | (SettingKind::String, SettingValue::String(_)) => Ok(()),
+            (SettingKind::Enum { options }, SettingValue::String(x)) => {
+                if options.iter().any(|option| &option.value == x) {
+                    Ok(())
+                } else {
+                    bail!("`{x}` is not a valid choice for {}", self.label)
+                }
+            }
+            _ => bail!("{value:?} has the wrong type for {}", self.label),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SettingsSchema(pub Vec<SettingField>);
+
+impl SettingsSchema {
+    pub fn defaults(&self) -> Settings {
+        Settings(
+            self.0
+                .iter()
+                .map(|x| (x.key.clone(), x.default.clone()))
+                .collect(),
+        )
+    }
+
+    /// Checks every value against its field and fills in defaults for missing ones.
+    pub fn validate(&self, values: &Settings) -> anyhow::Result<Settings> {
+        if let Some(key) = values
+            .0
+            .keys()
+            .find(|key| !self.0.iter().any(|x| &x.key == *key))
+        {
+            bail!("unknown setting `{key}`");
+        }
+        let mut settings = self.defaults();
+        for field in &self.0 {
+            if let Some(value) = values.0.get(&field.key) {
+                field.validate(value)?;
+                settings.0.insert(field.key.clone(), value.clone());
+            }
+        }
+        Ok(settings)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Settings(pub BTreeMap<String, SettingValue>);
+
+impl Settings {
+    fn path(source_id: &str) -> Option<PathBuf> {
+        #[allow(deprecated)]
+        Some(std::env::home_dir()?.join(format!(".config/mangarr/settings/{source_id}.json")))
+    }
+
+    /// Reads the saved settings of a source, empty if there are none.
+    pub fn load(source_id: &str) -> anyhow::Result<Self> {
+        match Self::path(source_id).filter(|x| x.exists()) {
+            Some(path) => Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, source_id: &str) -> anyhow::Result<()> {
+        let path = Self::path(source_id).context("no home dir")?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn bool(&self, key: &str) -> Option<bool> {
+        match self.0.get(key)? {
+            SettingValue::Bool(x) => Some(*x),
+            SettingValue::String(_) => None,
+        }
+    }
+
+    pub fn string(&self, key: &str) -> Option<&str> {
+        match self.0.get(key)? {
+            SettingValue::String(x) => Some(x),
+            SettingValue::Bool(_) => None,
+        }
+    }
+}
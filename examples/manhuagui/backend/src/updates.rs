@@ -0,0 +1,190 @@
+//! Looks for new chapters of the series on the bookshelf, every few hours and when asked.
+//!
+//! The check runs in the background on a snapshot of the bookshelf, see [`UpdateChecker::watch`],
+//! since the bookshelf itself belongs to the message handler. What it finds is kept until the
+//! handler applies it with [`UpdateChecker::take_found`], and the frontend is told right away.
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use backend::{ImageUrl, SChapter, SManga, Source};
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AbortableTask, BackendReplier,
+    bookshelf::{BookShelf, BookShelfKey},
+    message::{ReplierExt, SendMessage},
+    spawn,
+};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// A chapter that appeared since the series was added or last checked.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChapterUpdate {
+    pub name: String,
+    pub url: ImageUrl,
+    /// Unix time in milliseconds.
+    pub found_at: i64,
+}
+
+/// What the check compares against for one bookshelf entry.
+#[derive(Debug, Clone)]
+struct Watched {
+    key: BookShelfKey,
+    api: Source,
+    details: Arc<SManga>,
+    chapters: Arc<[SChapter]>,
+}
+
+/// The fresh chapter list of a series with new chapters.
+#[derive(Debug, Clone)]
+pub struct Found {
+    pub key: BookShelfKey,
+    pub chapters: Arc<[SChapter]>,
+    pub new: Vec<ChapterUpdate>,
+}
+
+/// New chapters of one series, as reported to the frontend.
+#[derive(Debug, Clone)]
+pub struct SeriesUpdate {
+    pub key: BookShelfKey,
+    pub title: String,
+    pub new_chapters: usize,
+}
+
+#[derive(Default)]
+pub struct UpdateChecker {
+    watched: Arc<Mutex<Vec<Watched>>>,
+    found: Arc<Mutex<Vec<Found>>>,
+    task: Option<AbortableTask<()>>,
+}
+
+impl UpdateChecker {
+    /// Checks whatever is on the bookshelf at the time of each check. Chapters found but not
+    /// taken yet count as known, so they are not reported twice.
+    pub fn watch(&self, bookshelf: &BookShelf) {
+        let found = self.found.lock().unwrap();
+        let watched = bookshelf
+            .bookshelf()
+            .iter()
+            .map(|(key, manga)| Watched {
+                key: key.clone(),
+                api: manga.api.clone(),
+                details: manga.details.clone(),
+                chapters: found
+                    .iter()
+                    .rev()
+                    .find(|x| x.key == *key)
+                    .map_or_else(|| manga.chapters.clone(), |x| x.chapters.clone()),
+            })
+            .collect();
+        *self.watched.lock().unwrap() = watched;
+    }
+
+    /// Checks now and then every [`CHECK_INTERVAL`], replacing the checks for an earlier
+    /// frontend.
+    pub fn start(&mut self, functionality: &BackendReplier) {
+        let (watched, found) = (self.watched.clone(), self.found.clone());
+        let functionality = functionality.clone();
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        self.task = Some(spawn(async move {
+            loop {
+                check(&watched, &found, &functionality).await;
+                smol::Timer::after(CHECK_INTERVAL).await;
+            }
+        }));
+    }
+
+    pub fn check_now(&self, functionality: &BackendReplier) {
+        let (watched, found) = (self.watched.clone(), self.found.clone());
+        let functionality = functionality.clone();
+        spawn(async move { check(&watched, &found, &functionality).await }).detach();
+    }
+
+    /// The new chapters found since the last call, oldest check first.
+    pub fn take_found(&self) -> Vec<Found> {
+        std::mem::take(&mut *self.found.lock().unwrap())
+    }
+}
+
+/// The chapters of `fresh` whose url is not in `known`.
+fn new_chapters(known: &[SChapter], fresh: &[SChapter], found_at: i64) -> Vec<ChapterUpdate> {
+    fresh
+        .iter()
+        .filter(|chapter| !known.iter().any(|x| x.url == chapter.url))
+        .map(|chapter| ChapterUpdate {
+            name: chapter.name.clone(),
+            url: chapter.url.clone(),
+            found_at,
+        })
+        .collect()
+}
+
+async fn check(
+    watched: &Mutex<Vec<Watched>>,
+    found: &Mutex<Vec<Found>>,
+    functionality: &BackendReplier,
+) {
+    let snapshot = watched.lock().unwrap().clone();
+    println!("checking {} series for new chapters", snapshot.len());
+    let results = stream::iter(snapshot)
+        .map(|entry| async move {
+            let fresh = entry.api.fetch_chapters(&entry.details).await;
+            (entry, fresh)
+        })
+        .buffer_unordered(3)
+        .collect::<Vec<_>>()
+        .await;
+
+    let found_at = chrono::Utc::now().timestamp_millis();
+    let mut updates = Vec::new();
+    for (entry, fresh) in results {
+        let fresh = match fresh {
+            Ok(fresh) => fresh,
+            Err(err) => {
+                println!(
+                    "checking {} for new chapters failed: {err:#}",
+                    entry.details.title
+                );
+                continue;
+            }
+        };
+        let new = new_chapters(&entry.chapters, &fresh, found_at);
+        if new.is_empty() {
+            continue;
+        }
+        let chapters: Arc<[SChapter]> = fresh.into();
+        // later checks compare against the fresh list, so chapters are only reported once
+        if let Some(x) = watched
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|x| x.key == entry.key)
+        {
+            x.chapters = chapters.clone();
+        }
+        updates.push(SeriesUpdate {
+            key: entry.key.clone(),
+            title: entry.details.title.clone(),
+            new_chapters: new.len(),
+        });
+        found.lock().unwrap().push(Found {
+            key: entry.key,
+            chapters,
+            new,
+        });
+    }
+
+    if updates.is_empty() {
+        return;
+    }
+    let message = SendMessage::UpdatesAvailable(updates);
+    if let Err(err) = functionality.send_typed_message(message).await {
+        println!("can't report new chapters: {err:#}");
+    }
+}
@@ -0,0 +1,134 @@
+//! A stand-in HTTP server and saved pages for running sources without the network.
+// every test crate uses a different part of this
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+};
+
+use backend::http::recording_path;
+use reqwest::Method;
+
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// With the query.
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serves `route(request)` as `(content type, body)` on a local port, answering 404 when it
+/// returns `None`. Returns the base url of the server.
+pub fn serve(
+    route: impl Fn(&Request) -> Option<(&'static str, Vec<u8>)> + Send + 'static,
+) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let Some(request) = read_request(&mut BufReader::new(&stream)) else {
+                continue;
+            };
+            let head = |status: &str, content_type: &str, length: usize| {
+                format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\n\
+                     Content-Length: {length}\r\nConnection: close\r\n\r\n"
+                )
+            };
+            let _ = match route(&request) {
+                Some((content_type, body)) => stream
+                    .write_all(head("200 OK", content_type, body.len()).as_bytes())
+                    .and_then(|()| stream.write_all(&body)),
+                None => stream.write_all(head("404 Not Found", "text/plain", 0).as_bytes()),
+            };
+        }
+    });
+    base
+}
+
+fn read_request(reader: &mut impl BufRead) -> Option<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split(' ');
+    let method = parts.next()?.to_owned();
+    let path = parts.next()?.to_owned();
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let Some((key, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((key.trim().to_owned(), value.trim().to_owned()));
+    }
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+    let length = request
+        .header("Content-Length")
+        .and_then(|x| x.parse().ok());
+    request.body = vec![0; length.unwrap_or(0)];
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}
+
+/// reqwest needs the tokio reactor, as in the app.
+pub fn run<T>(future: impl Future<Output = T>) -> T {
+    smol::block_on(async_compat::Compat::new(future))
+}
+
+/// A saved page from `tests/fixtures`.
+pub fn fixture(path: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(path);
+    std::fs::read_to_string(&path).unwrap_or_else(|err| panic!("{}: {err}", path.display()))
+}
+
+/// An empty directory for the test `name`.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mangarr-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Saves `body` as the response to `GET url` the way recording does, for replaying it.
+pub fn record(dir: &Path, url: &str, content_type: &str, body: impl AsRef<[u8]>) {
+    record_status(dir, url, 200, &[("content-type", content_type)], body);
+}
+
+/// [`record`] with any status and headers.
+pub fn record_status(
+    dir: &Path,
+    url: &str,
+    status: u16,
+    headers: &[(&str, &str)],
+    body: impl AsRef<[u8]>,
+) {
+    // recordings are keyed by the url as sent, with non-ascii characters escaped
+    let url = reqwest::Url::parse(url).unwrap().to_string();
+    let mut head = format!("GET {url}\n{status}\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\n"));
+    }
+    head.push('\n');
+    let path = recording_path(dir, &Method::GET, &url);
+    std::fs::write(path, [head.as_bytes(), body.as_ref()].concat()).unwrap();
+}
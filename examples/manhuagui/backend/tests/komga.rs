@@ -0,0 +1,191 @@
+//! Runs the Komga source against a mock of the server's REST API.
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use backend::{MangaBackend, MangaStatus, SearchFilter, komga::Komga};
+use common::{Request, run, serve};
+use serde_json::{Value, json};
+
+const JSON: &str = "application/json";
+
+fn series(id: &str, title: &str) -> Value {
+    json!({
+        "id": id,
+        "name": format!("{title} folder"),
+        "lastModified": "2024-05-01T10:00:00Z",
+        "metadata": {
+            "status": "ONGOING",
+            "title": title,
+            "summary": "",
+            "genres": ["Action"],
+            "tags": ["Pirates"]
+        },
+        "booksMetadata": {
+            "authors": [
+                {"name": "Oda", "role": "writer"},
+                {"name": "Oda", "role": "penciller"},
+                {"name": "Someone", "role": "letterer"}
+            ],
+            "summary": "A summary from the books"
+        }
+    })
+}
+
+fn paged(content: Vec<Value>, last: bool) -> Option<(&'static str, Vec<u8>)> {
+    Some((
+        JSON,
+        json!({ "content": content, "last": last })
+            .to_string()
+            .into_bytes(),
+    ))
+}
+
+fn book(id: &str, title: &str, number: f32) -> Value {
+    json!({
+        "id": id,
+        "name": format!("{id}.cbz"),
+        "metadata": {"title": title, "numberSort": number, "releaseDate": "2020-01-02"}
+    })
+}
+
+/// Answers like Komga, recording every read progress update.
+fn server(auth: &'static str) -> (String, Arc<Mutex<Vec<Value>>>) {
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let recorded = progress.clone();
+    let base = serve(move |request: &Request| {
+        let authorized = request.header("Authorization") == Some(auth)
+            || request.header("X-API-Key") == Some(auth);
+        if !authorized {
+            return None;
+        }
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/api/v1/series?sort=metadata.titleSort%2Casc&page=0&size=20") => paged(
+                vec![series("0A", "One Piece"), series("0B", "Berserk")],
+                false,
+            ),
+            ("GET", "/api/v1/series?sort=metadata.titleSort%2Casc&page=1&size=20") => {
+                paged(vec![series("0C", "Vagabond")], true)
+            }
+            (
+                "GET",
+                "/api/v1/series?search=one+piece&sort=metadata.titleSort%2Casc&page=0&size=20",
+            ) => paged(vec![series("0A", "One Piece")], true),
+            ("GET", "/api/v1/series/latest?page=0&size=20") => {
+                paged(vec![series("0C", "Vagabond")], true)
+            }
+            ("GET", "/api/v1/series/0A") => {
+                Some((JSON, series("0A", "One Piece").to_string().into_bytes()))
+            }
+            ("GET", "/api/v1/series/0A/books?unpaged=true&sort=metadata.numberSort%2Casc") => {
+                paged(
+                    vec![book("B1", "Romance Dawn", 1.0), book("B2", "", 2.0)],
+                    true,
+                )
+            }
+            ("GET", "/api/v1/books/B1/pages") => Some((
+                JSON,
+                json!([{"number": 1}, {"number": 2}, {"number": 3}])
+                    .to_string()
+                    .into_bytes(),
+            )),
+            ("GET", "/api/v1/books/B1/pages/2") => Some(("image/png", b"second page".to_vec())),
+            ("PATCH", "/api/v1/books/B1/read-progress") => {
+                let body = serde_json::from_slice(&request.body).unwrap();
+                recorded.lock().unwrap().push(body);
+                Some((JSON, Vec::new()))
+            }
+            _ => None,
+        }
+    });
+    (base, progress)
+}
+
+fn komga() -> (Komga, Arc<Mutex<Vec<Value>>>) {
+    let (base, progress) = server("Basic dXNlcjpwYXNz");
+    (Komga::new(base).with_password("user", "pass"), progress)
+}
+
+#[test]
+fn lists_series_with_their_metadata() {
+    let (komga, _) = komga();
+    run(async {
+        let first = komga.popular(1).await.unwrap();
+        assert!(first.has_next_page);
+        let manga = &first.mangas[0];
+        assert_eq!(manga.title, "One Piece");
+        assert_eq!(manga.author.as_deref(), Some("Oda"));
+        assert_eq!(
+            manga.description.as_deref(),
+            Some("A summary from the books")
+        );
+        assert_eq!(manga.genre.as_deref(), Some("Action, Pirates"));
+        assert_eq!(manga.status, MangaStatus::Ongoing);
+
+        let second = komga.popular(2).await.unwrap();
+        assert!(!second.has_next_page);
+        assert_eq!(second.mangas[0].title, "Vagabond");
+        assert_eq!(komga.latest(1).await.unwrap().mangas[0].title, "Vagabond");
+    });
+}
+
+#[test]
+fn searches_series() {
+    let (komga, _) = komga();
+    run(async {
+        let results = komga
+            .search(" one piece ", 1, &SearchFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(results.mangas.len(), 1);
+        assert_eq!(results.mangas[0].title, "One Piece");
+    });
+}
+
+#[test]
+fn streams_the_pages_of_books() {
+    let (komga, _) = komga();
+    run(async {
+        let manga = komga.search_by_id("0A").await.unwrap();
+        let chapters = komga.fetch_chapters(&manga).await.unwrap();
+        let names = chapters.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Romance Dawn", "B2.cbz"]);
+        assert_eq!(chapters[1].chapter_number, 2.0);
+        assert_eq!(chapters[0].date_upload, Some(1_577_923_200_000));
+
+        let pages = komga.fetch_pages(&chapters[0]).await.unwrap();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(
+            komga.fetch_image(&pages[1].image_url).await.unwrap(),
+            b"second page"
+        );
+    });
+}
+
+#[test]
+fn pushes_read_progress() {
+    let (komga, progress) = komga();
+    run(async {
+        let manga = komga.search_by_id("0A").await.unwrap();
+        let chapter = &komga.fetch_chapters(&manga).await.unwrap()[0];
+        komga.sync_progress(chapter, 0, 3).await.unwrap();
+        komga.sync_progress(chapter, 2, 3).await.unwrap();
+    });
+    assert_eq!(
+        *progress.lock().unwrap(),
+        [
+            json!({"page": 1, "completed": false}),
+            json!({"page": 3, "completed": true})
+        ]
+    );
+}
+
+#[test]
+fn authenticates_with_an_api_key() {
+    let (base, _) = server("secret");
+    run(async {
+        assert!(Komga::new(&base).popular(1).await.is_err());
+        let komga = Komga::new(&base).with_api_key("secret");
+        assert_eq!(komga.popular(1).await.unwrap().mangas.len(), 2);
+    });
+}
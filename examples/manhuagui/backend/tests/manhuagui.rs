@@ -0,0 +1,186 @@
+//! Runs the Manhuagui parsers over saved pages, and the source over a recorded session.
+mod common;
+
+use backend::{
+    ImageUrl, MangaBackend, MangaStatus, SChapter,
+    http::{self, Recording},
+    manhuagui::{Manhuagui, Preferences},
+};
+use common::{fixture, record, run, temp_dir};
+use scraper::Html;
+
+fn document(path: &str) -> Html {
+    Html::parse_document(&fixture(path))
+}
+
+fn manhuagui(show_r18: bool) -> Manhuagui {
+    Manhuagui::new(Preferences {
+        show_r18,
+        ..Preferences::default()
+    })
+    .unwrap()
+}
+
+fn names(chapters: &[SChapter]) -> Vec<&str> {
+    chapters.iter().map(|x| x.name.as_str()).collect()
+}
+
+#[test]
+fn parses_comic_details() {
+    let manga = Manhuagui::smanga_creation(&document("manhuagui/comic.html"), "/comic/17332/");
+    assert_eq!(manga.url, ImageUrl::Web("/comic/17332/".to_owned()));
+    assert_eq!(manga.title, "一拳超人");
+    assert_eq!(manga.author.as_deref(), Some("ONE, 村田雄介"));
+    assert_eq!(manga.genre.as_deref(), Some("熱血, 搞笑"));
+    assert_eq!(manga.status, MangaStatus::Ongoing);
+    assert_eq!(manga.last_updated_time, "2024-05-17");
+    assert_eq!(
+        manga.thumbnail_url.as_deref(),
+        Some("//cf.mhgui.com/cpic/h/17332.jpg")
+    );
+    assert!(manga.description.unwrap().starts_with("主人公埼玉"));
+}
+
+#[test]
+fn parses_chapters_oldest_first() {
+    let chapters = manhuagui(true)
+        .parse_chapters(&document("manhuagui/comic.html"))
+        .unwrap();
+    assert_eq!(
+        names(&chapters),
+        ["第01卷", "第02卷", "第01話", "第02話", "第03話", "第04話"]
+    );
+    let numbers = chapters
+        .iter()
+        .map(|x| x.chapter_number)
+        .collect::<Vec<_>>();
+    assert_eq!(numbers, [1.0, 2.0, 1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(
+        chapters[5].url,
+        ImageUrl::Web("/comic/17332/812345.html".to_owned())
+    );
+    // only the latest chapter has a date on the page
+    let dates = chapters.iter().map(|x| x.date_upload).collect::<Vec<_>>();
+    assert_eq!(
+        dates,
+        [None, None, None, None, None, Some(1_715_904_000_000)]
+    );
+}
+
+#[test]
+fn reads_adult_chapter_lists_from_the_view_state() {
+    let document = document("manhuagui/comic_adult.html");
+    let chapters = manhuagui(true).parse_chapters(&document).unwrap();
+    assert_eq!(names(&chapters), ["第01話 邂逅", "第02話 秘密"]);
+    assert_eq!(chapters[1].date_upload, Some(1_698_883_200_000));
+    assert!(
+        manhuagui(false)
+            .parse_chapters(&document)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn decodes_the_packed_image_list() {
+    let pages = manhuagui(true)
+        .parse_pages(&fixture("manhuagui/chapter.html"), "https://i.hamreus.com")
+        .unwrap();
+    let urls = pages
+        .iter()
+        .map(|x| x.image_url.clone())
+        .collect::<Vec<_>>();
+    let url = |file| {
+        ImageUrl::Web(format!(
+            "https://i.hamreus.com/ps3/y/yiquanchaoren/第04話/{file}\
+             ?e=1716000000&m=Xk3VrnP1dEQyZ5dbA9w2tg"
+        ))
+    };
+    assert_eq!(
+        urls,
+        [
+            url("001.jpg.webp"),
+            url("002.jpg.webp"),
+            url("003.png.webp")
+        ]
+    );
+    assert_eq!(pages[2].index, 2);
+}
+
+#[test]
+fn reports_hidden_adult_chapters() {
+    let err = manhuagui(false)
+        .parse_pages(
+            &fixture("manhuagui/comic_adult.html"),
+            "https://i.hamreus.com",
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("R18"), "{err}");
+}
+
+#[test]
+fn parses_listings() {
+    let page = Manhuagui::parse_listing(&document("manhuagui/list.html"));
+    assert!(page.has_next_page);
+    let titles = page
+        .mangas
+        .iter()
+        .map(|x| x.title.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, ["一拳超人", "進擊的巨人"]);
+    let manga = &page.mangas[1];
+    assert_eq!(manga.url, ImageUrl::Web("/comic/1128/".to_owned()));
+    assert_eq!(
+        manga.thumbnail_url.as_deref(),
+        Some("https://cf.mhgui.com/cpic/b/1128.jpg")
+    );
+    assert_eq!(manga.status, MangaStatus::Completed);
+    assert_eq!(page.mangas[0].status, MangaStatus::Ongoing);
+}
+
+#[test]
+fn replays_a_recorded_session() {
+    let dir = temp_dir("manhuagui-replay");
+    let comic = fixture("manhuagui/comic.html");
+    record(
+        &dir,
+        "https://tw.manhuagui.com/comic/17332",
+        "text/html",
+        &comic,
+    );
+    record(
+        &dir,
+        "https://tw.manhuagui.com/comic/17332/",
+        "text/html",
+        &comic,
+    );
+    record(
+        &dir,
+        "https://tw.manhuagui.com/comic/17332/812345.html",
+        "text/html",
+        fixture("manhuagui/chapter.html"),
+    );
+    record(
+        &dir,
+        "https://i.hamreus.com/ps3/y/yiquanchaoren/第04話/001.jpg.webp\
+         ?e=1716000000&m=Xk3VrnP1dEQyZ5dbA9w2tg",
+        "image/webp",
+        b"first page",
+    );
+    http::set_recording(Recording::Replay(dir));
+
+    let source = manhuagui(true);
+    run(async {
+        let manga = source.search_by_id("17332").await.unwrap();
+        assert_eq!(manga.title, "一拳超人");
+        let chapters = source.fetch_chapters(&manga).await.unwrap();
+        let latest = chapters.last().unwrap();
+        let pages = source.fetch_pages(latest).await.unwrap();
+        assert_eq!(pages.len(), 3);
+        let image = source.fetch_image(&pages[0].image_url).await.unwrap();
+        assert_eq!(image, b"first page");
+        // anything not recorded fails instead of reaching the site
+        assert!(source.fetch_image(&pages[1].image_url).await.is_err());
+    });
+    http::set_recording(Recording::Off);
+}
@@ -0,0 +1,126 @@
+//! Runs the NHentai parsers over saved pages, and the source over a recorded session.
+mod common;
+
+use backend::{
+    ImageUrl, MangaBackend, MangaStatus, SourceError,
+    http::{self, Recording},
+    nhentai::NHentai,
+};
+use common::{fixture, record, record_status, run, temp_dir};
+use scraper::Html;
+
+fn document(path: &str) -> Html {
+    Html::parse_document(&fixture(path))
+}
+
+fn nhentai(display_full_title: bool) -> NHentai {
+    NHentai::new("all".to_owned(), String::new(), display_full_title).unwrap()
+}
+
+#[test]
+fn parses_galleries() {
+    let document = document("nhentai/gallery.html");
+    let manga = nhentai(true).parse_gallery(&document, "177013").unwrap();
+    assert_eq!(manga.url, ImageUrl::Web("/g/177013/".to_owned()));
+    assert_eq!(manga.title, "[ShindoLA] METAMORPHOSIS (Complete) [English]");
+    assert_eq!(manga.author.as_deref(), Some("shindo l"));
+    assert_eq!(
+        manga.genre.as_deref(),
+        Some("parody:original, full color, language:english, category:doujinshi")
+    );
+    assert_eq!(
+        manga.thumbnail_url.as_deref(),
+        Some("https://t3.nhentai.net/galleries/987560/cover.jpg")
+    );
+    assert_eq!(manga.status, MangaStatus::Completed);
+    // the JSON is escaped inside a JavaScript string, including the Japanese title
+    let description = manga.description.unwrap();
+    assert!(
+        description.contains("[ShindoLA] メタモルフォーゼ"),
+        "{description}"
+    );
+    assert!(
+        description.contains("Pages: 3\nFavorited by: 53718"),
+        "{description}"
+    );
+
+    let short = nhentai(false).parse_gallery(&document, "177013").unwrap();
+    assert_eq!(short.title, "METAMORPHOSIS");
+}
+
+#[test]
+fn parses_the_single_chapter() {
+    let document = document("nhentai/gallery.html");
+    let manga = nhentai(true).parse_gallery(&document, "177013").unwrap();
+    let chapters = NHentai::parse_chapters(&document, &manga).unwrap();
+    assert_eq!(chapters.len(), 1);
+    assert_eq!(chapters[0].url, manga.url);
+    assert_eq!(chapters[0].date_upload, Some(1_476_793_729_000));
+}
+
+#[test]
+fn parses_page_urls() {
+    let pages = NHentai::parse_pages(&document("nhentai/gallery.html")).unwrap();
+    let urls = pages
+        .iter()
+        .map(|x| x.image_url.clone())
+        .collect::<Vec<_>>();
+    let url = |file| ImageUrl::Web(format!("https://i7.nhentai.net/galleries/987560/{file}"));
+    assert_eq!(urls, [url("1.jpg"), url("2.png"), url("3.webp")]);
+}
+
+#[test]
+fn parses_listings() {
+    let document = document("nhentai/search.html");
+    let page = nhentai(true).parse_listing(&document);
+    assert!(page.has_next_page);
+    assert_eq!(page.mangas.len(), 2);
+    assert_eq!(page.mangas[0].url, ImageUrl::Web("/g/177013/".to_owned()));
+    assert_eq!(
+        page.mangas[0].thumbnail_url.as_deref(),
+        Some("https://t3.nhentai.net/galleries/987560/thumb.jpg")
+    );
+    assert_eq!(
+        page.mangas[1].title,
+        "(C93) [Circle (Artist)] Summer Story {Translator}"
+    );
+    let short = nhentai(false).parse_listing(&document);
+    assert_eq!(short.mangas[1].title, "Summer Story");
+}
+
+#[test]
+fn replays_a_recorded_session() {
+    let dir = temp_dir("nhentai-replay");
+    let gallery = fixture("nhentai/gallery.html");
+    record(&dir, "https://nhentai.net/g/177013", "text/html", gallery);
+    // Cloudflare challenges, then an outage that new credentials won't fix
+    let challenge = [("cf-mitigated", "challenge")];
+    record_status(&dir, "https://nhentai.net/g/1", 503, &challenge, "");
+    let page = "<title>Just a moment...</title>";
+    record_status(&dir, "https://nhentai.net/g/2", 403, &[], page);
+    record_status(&dir, "https://nhentai.net/g/3", 503, &[], "maintenance");
+    record_status(&dir, "https://nhentai.net/g/4", 403, &[], "forbidden");
+    http::set_recording(Recording::Replay(dir));
+
+    let source = nhentai(true);
+    run(async {
+        let manga = source.search_by_id("177013").await.unwrap();
+        let chapters = source.fetch_chapters(&manga).await.unwrap();
+        let pages = source.fetch_pages(&chapters[0]).await.unwrap();
+        assert_eq!(pages.len(), 3);
+        assert!(source.search_by_id("228922").await.is_err());
+
+        let error = async |id| SourceError::from(source.search_by_id(id).await.unwrap_err());
+        assert!(matches!(error("1").await, SourceError::NeedsCredentials(_)));
+        assert!(matches!(error("2").await, SourceError::NeedsCredentials(_)));
+        assert!(matches!(
+            error("3").await,
+            SourceError::HttpStatus { status: 503, .. }
+        ));
+        assert!(matches!(
+            error("4").await,
+            SourceError::HttpStatus { status: 403, .. }
+        ));
+    });
+    http::set_recording(Recording::Off);
+}
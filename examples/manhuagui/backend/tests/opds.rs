@@ -0,0 +1,274 @@
+//! Runs the OPDS source against a stand-in catalog served from a local socket.
+mod common;
+
+use std::{io::Write, path::PathBuf};
+
+use backend::{ImageUrl, MangaBackend, SChapter, SearchFilter, opds::Opds};
+use common::{run, serve};
+
+const ATOM: &str = "application/atom+xml;profile=opds-catalog";
+
+fn feed(title: &str, links: &str, entries: &str) -> Vec<u8> {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:pse="http://vaemendis.net/opds-pse/ns">
+  <id>urn:feed</id><title>{title}</title>{links}{entries}
+</feed>"#
+    )
+    .into_bytes()
+}
+
+fn publication(id: &str, title: &str, links: &str) -> String {
+    format!(
+        r#"<entry><id>{id}</id><title>{title}</title><author><name>Author</name></author>
+<summary>About {title}</summary><category term="action" label="Action"/>{links}</entry>"#
+    )
+}
+
+fn catalog(path: &str) -> Option<(&'static str, Vec<u8>)> {
+    let navigation = |title: &str, href: &str| {
+        format!(
+            r#"<entry><id>{href}</id><title>{title}</title>
+<link rel="subsection" href="{href}" type="{ATOM};kind=acquisition"/></entry>"#
+        )
+    };
+    Some(match path {
+        "/opds" => (
+            ATOM,
+            feed(
+                "Library",
+                r#"<link rel="search" href="/opds/search.xml" type="application/opensearchdescription+xml"/>
+<link rel="next" href="/opds?page=2"/>"#,
+                &navigation("Series A", "/opds/series/a"),
+            ),
+        ),
+        "/opds?page=2" => (
+            ATOM,
+            feed("Library", "", &navigation("Series B", "/opds/series/b")),
+        ),
+        "/opds/search.xml" => (
+            "application/opensearchdescription+xml",
+            br#"<?xml version="1.0"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+  <Url type="application/atom+xml" template="/opds/search?q={searchTerms}&amp;p={startPage?}"/>
+</OpenSearchDescription>"#
+                .to_vec(),
+        ),
+        "/opds/search?q=series%20a&p=" => (
+            ATOM,
+            feed("Results", "", &navigation("Series A", "/opds/series/a")),
+        ),
+        "/opds/series/a" => (
+            ATOM,
+            feed(
+                "Series A",
+                "",
+                &(publication(
+                    "urn:a1",
+                    "Volume 1",
+                    r#"<link rel="http://vaemendis.net/opds-pse/stream" type="image/jpeg" pse:count="3"
+href="/opds/page/a1/{pageNumber}?width={maxWidth}"/>"#,
+                ) + &publication(
+                    "urn:a2",
+                    "Volume 2",
+                    r#"<link rel="http://opds-spec.org/acquisition" type="application/vnd.comicbook+zip"
+href="/opds/download/a2"/>"#,
+                )),
+            ),
+        ),
+        "/opds/page/a1/1?width=1404" => ("image/png", b"page 1".to_vec()),
+        "/opds/download/a2" => ("application/vnd.comicbook+zip", cbz()),
+        _ => return None,
+    })
+}
+
+fn cbz() -> Vec<u8> {
+    let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for name in ["10.png", "2.png", "1.png"] {
+        archive
+            .start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(name.as_bytes()).unwrap();
+    }
+    archive.finish().unwrap().into_inner()
+}
+
+fn directory(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("mangarr-opds-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    directory
+}
+
+fn chapter_of(url: ImageUrl) -> SChapter {
+    SChapter {
+        url,
+        name: String::new(),
+        chapter_number: 1.0,
+        date_upload: None,
+    }
+}
+
+#[test]
+fn lists_and_paginates_the_root_feed() {
+    let opds = Opds::new(
+        format!("{}/opds", serve(|x| catalog(&x.path))),
+        directory("listing"),
+    );
+    run(async {
+        let first = opds.popular(1).await.unwrap();
+        assert!(first.has_next_page);
+        assert_eq!(first.mangas[0].title, "Series A");
+        let second = opds.popular(2).await.unwrap();
+        assert!(!second.has_next_page);
+        assert_eq!(second.mangas[0].title, "Series B");
+        assert!(opds.popular(3).await.unwrap().mangas.is_empty());
+    });
+}
+
+#[test]
+fn searches_with_opensearch() {
+    let opds = Opds::new(
+        format!("{}/opds", serve(|x| catalog(&x.path))),
+        directory("search"),
+    );
+    run(async {
+        let results = opds
+            .search("series a", 1, &SearchFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(results.mangas.len(), 1);
+        assert_eq!(results.mangas[0].title, "Series A");
+    });
+}
+
+#[test]
+fn reads_chapters_and_metadata_by_id() {
+    let opds = Opds::new(
+        format!("{}/opds", serve(|x| catalog(&x.path))),
+        directory("chapters"),
+    );
+    run(async {
+        let series = &opds.popular(1).await.unwrap().mangas[0];
+        let ImageUrl::Web(id) = &series.url else {
+            panic!("expected an id, got {:?}", series.url);
+        };
+        assert!(!id.contains('/'));
+        let manga = opds.search_by_id(id).await.unwrap();
+        assert_eq!(manga.title, "Series A");
+        let chapters = opds.fetch_chapters(&manga).await.unwrap();
+        let names = chapters.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Volume 1", "Volume 2"]);
+        assert_eq!(chapters[1].chapter_number, 2.0);
+    });
+}
+
+#[test]
+fn streams_pages_with_pse() {
+    let opds = Opds::new(
+        format!("{}/opds", serve(|x| catalog(&x.path))),
+        directory("pse"),
+    );
+    run(async {
+        let manga = &opds.popular(1).await.unwrap().mangas[0];
+        let chapters = opds.fetch_chapters(manga).await.unwrap();
+        let pages = opds.fetch_pages(&chapters[0]).await.unwrap();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(
+            opds.fetch_image(&pages[1].image_url).await.unwrap(),
+            b"page 1"
+        );
+    });
+}
+
+#[test]
+fn downloads_acquisitions_into_the_library() {
+    let directory = directory("download");
+    let opds = Opds::new(
+        format!("{}/opds", serve(|x| catalog(&x.path))),
+        directory.clone(),
+    );
+    run(async {
+        let manga = &opds.popular(1).await.unwrap().mangas[0];
+        let chapters = opds.fetch_chapters(manga).await.unwrap();
+        let pages = opds.fetch_pages(&chapters[1]).await.unwrap();
+        assert!(directory.join("Series A/Volume 2.cbz").is_file());
+        let images = [&pages[0], &pages[1], &pages[2]].map(|x| opds.read_local_image(&x.image_url));
+        let images = images.map(Result::unwrap);
+        assert_eq!(
+            images,
+            [b"1.png".to_vec(), b"2.png".to_vec(), b"10.png".to_vec()]
+        );
+        // a second read uses the downloaded file
+        assert_eq!(
+            opds.fetch_pages(&chapter_of(chapters[1].url.clone()))
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+    });
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+fn catalog_json(path: &str) -> Option<(&'static str, Vec<u8>)> {
+    let json = match path {
+        "/opds2" => serde_json::json!({
+            "metadata": {"title": "Library"},
+            "links": [
+                {"rel": "search", "href": "/opds2/search{?query}", "type": "application/opds+json", "templated": true}
+            ],
+            "navigation": [
+                {"title": "Series C", "href": "/opds2/series/c", "type": "application/opds+json"}
+            ],
+            "publications": [{
+                "metadata": {"identifier": "urn:d", "title": "One-shot D", "author": [{"name": "Writer"}]},
+                "links": [{"rel": "http://opds-spec.org/acquisition", "href": "/opds2/d.cbz", "type": "application/vnd.comicbook+zip"}],
+                "images": [{"href": "/opds2/d.jpg", "type": "image/jpeg"}]
+            }]
+        }),
+        "/opds2/search?query=d" => serde_json::json!({
+            "metadata": {"title": "Results"},
+            "publications": [{
+                "metadata": {"identifier": "urn:d", "title": "One-shot D"},
+                "links": [{"rel": "http://opds-spec.org/acquisition", "href": "/opds2/d.cbz", "type": "application/vnd.comicbook+zip"}]
+            }]
+        }),
+        _ => return None,
+    };
+    Some(("application/opds+json", json.to_string().into_bytes()))
+}
+
+#[test]
+fn browses_opds2_catalogs() {
+    let base = serve(|x| catalog_json(&x.path));
+    let opds = Opds::new(format!("{base}/opds2"), directory("opds2"));
+    run(async {
+        let listing = opds.popular(1).await.unwrap();
+        let titles = listing
+            .mangas
+            .iter()
+            .map(|x| x.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["Series C", "One-shot D"]);
+        assert_eq!(listing.mangas[1].author.as_deref(), Some("Writer"));
+        assert_eq!(
+            listing.mangas[1].thumbnail_url.as_deref(),
+            Some(format!("{base}/opds2/d.jpg").as_str())
+        );
+        let chapters = opds.fetch_chapters(&listing.mangas[1]).await.unwrap();
+        assert_eq!(chapters.len(), 1);
+
+        let results = opds.search("d", 1, &SearchFilter::default()).await.unwrap();
+        assert_eq!(results.mangas[0].title, "One-shot D");
+    });
+}
+
+#[test]
+fn reports_missing_pages() {
+    let opds = Opds::new(
+        format!("{}/missing", serve(|x| catalog(&x.path))),
+        directory("missing"),
+    );
+    assert!(run(opds.popular(1)).is_err());
+}
@@ -8,23 +8,7 @@ GridView {
     anchors.fill: parent
     cellWidth: parent.width
     cellHeight: 100
-    model: backendModel
-
-    ListModel {
-        id: backendModel
-        ListElement {
-            name: "NHentai"
-            desc: "Requires manual setup of the cf authenticatino token."
-        }
-        ListElement {
-            name: "Manhuagui"
-            desc: "The default backend. Works without any configuration."
-        }
-        ListElement {
-            name: "Epub"
-            desc: "Reading books from an epub, requires that you place according to folder structure"
-        }
-    }
+    model: StateManager.sources
 
     header: Rectangle {
         width: backendSelection.width
@@ -46,7 +30,7 @@ GridView {
             Text {
                 anchors.centerIn: parent
                 font.pointSize: 36
-                text: name
+                text: modelData.name
             }
         }
         Rectangle {
@@ -57,8 +41,8 @@ GridView {
             MouseArea {
                 anchors.fill: parent
                 onClicked: () => {
-                    StateManager.activeBackend = name;
-                    BackendController.sendMessage(11, name);
+                    StateManager.activeBackend = modelData.name;
+                    BackendController.sendMessage(11, modelData.id);
                     StateManager.activePage = StateManager.ActivePage.Search
                     StateManager.pages = new Map();
                     StateManager.manga_id = "";
@@ -72,7 +56,7 @@ GridView {
                 anchors.centerIn: parent
                 anchors.leftMargin: 20
                 font.pointSize: 36
-                text: desc
+                text: modelData.description
             }
         }
     }
@@ -25,6 +25,11 @@ GridView {
                 bookshelfModel.append(values[i]);
             }
         }
+
+        // re-requesting the bookshelf makes the backend apply the new chapters first
+        function onUpdatesReceived(updates) {
+            BackendController.sendMessage(14, "");
+        }
     }
 
     header: Rectangle {
@@ -36,6 +41,24 @@ GridView {
             font.pointSize: 24
             text: "Bookshelf"
         }
+        Rectangle {
+            anchors.right: parent.right
+            anchors.verticalCenter: parent.verticalCenter
+            anchors.rightMargin: 20
+            width: 300
+            height: 60
+            border.width: 2
+            border.color: "black"
+            MouseArea {
+                anchors.fill: parent
+                onClicked: () => { BackendController.checkUpdates() }
+            }
+            Text {
+                anchors.centerIn: parent
+                font.pointSize: 20
+                text: "Check for updates"
+            }
+        }
     }
 
     delegate: RowLayout {
@@ -94,7 +117,7 @@ GridView {
                         anchors.verticalCenter: parent.verticalCenter
                         anchors.leftMargin: 30
                         font.pointSize: 36
-                        text: title
+                        text: newChapters > 0 ? `${title} (${newChapters} new)` : title
                     }
                 }
                 Rectangle {
@@ -153,7 +153,7 @@ ColumnLayout {
                     Image {
                         anchors.fill: parent
                         fillMode: Image.PreserveAspectFit
-                        source: pageUrl.includes("file:") ? `file:/tmp/mangarr/preview/${pageUrl.slice(18, -1)}g` : (pageUrl)
+                        source: pageUrl.includes("file:") ? pageUrl.replace(/\.png$/, ".preview.png") : pageUrl
                         asynchronous: true
                     }
 
@@ -81,10 +81,80 @@ QtObject {
                     const array = JSON.parse(contents);
                     const key = array.url;
                     StateManager.addBook(key, array);
+                    break;
+                case 18:
+                    StateManager.capabilities = JSON.parse(contents);
+                    break;
+                case 19:
+                    StateManager.setSearchResults(JSON.parse(contents));
+                    break;
+                case 20:
+                    StateManager.sources = JSON.parse(contents);
+                    break;
+                case 21:
+                    StateManager.sourceSettings = JSON.parse(contents);
+                    StateManager.sourceSettingsUpdated();
+                    break;
+                case 22:
+                    const request = JSON.parse(contents);
+                    StateManager.errorMessage = request.message;
+                    StateManager.credentialsRequested(request);
+                    break;
+                case 23:
+                    StateManager.updates = JSON.parse(contents);
+                    StateManager.updatesReceived(StateManager.updates);
+                    break;
+                case 24:
+                    StateManager.setDownloadProgress(JSON.parse(contents));
+                    break;
+                case 1000:
+                    const error = JSON.parse(contents);
+                    StateManager.errorMessage = error.message;
+                    StateManager.errorReceived(error);
+                    break;
             }
         }
     }
     function sendMessage(type, contents) {
         appload.sendMessage(type, contents)
     }
+    // the id "image-cache" stands for the size limit of the page cache instead of a source
+    function requestSettings(sourceId) {
+        appload.sendMessage(18, sourceId)
+    }
+    function updateSetting(sourceId, key, value) {
+        const values = Object.assign({}, StateManager.sourceSettings.values);
+        values[key] = value;
+        appload.sendMessage(19, `${sourceId}\n${JSON.stringify(values)}`)
+        requestSettings(sourceId)
+    }
+    // the answer arrives as an updatesReceived signal, and only when something is new
+    function checkUpdates() {
+        appload.sendMessage(21, "")
+    }
+    // chapters are indices counted from 0, all chapters of the open manga when empty
+    function downloadChapters(chapters) {
+        appload.sendMessage(22, (chapters || []).join("\n"))
+    }
+    function pauseDownloads() {
+        StateManager.downloadsPaused = true;
+        appload.sendMessage(23, "")
+    }
+    function resumeDownloads() {
+        StateManager.downloadsPaused = false;
+        appload.sendMessage(24, "")
+    }
+    // format is "cbz" or "epub", chapters are downloaded ones, every downloaded chapter when empty
+    function exportChapters(format, chapters) {
+        appload.sendMessage(26, [format].concat(chapters || []).join("\n"))
+    }
+    // every download when id is undefined
+    function cancelDownload(id) {
+        appload.sendMessage(25, id === undefined ? "" : `${id}`)
+    }
+    // cookies maps cookie names to values, e.g. { cf_clearance: "..." }
+    function setCredentials(sourceId, cookies, userAgent) {
+        const credentials = { cookies: cookies, user_agent: userAgent || null };
+        appload.sendMessage(20, `${sourceId}\n${JSON.stringify(credentials)}`)
+    }
 }
@@ -16,6 +16,9 @@ QtObject {
     property var chapters: []
     property int activePage: StateManager.ActivePage.BookshelfView
     property string activeBackend: "Manhuagui"
+    property var sources: []
+    // schema and values of the source whose settings were last requested
+    property var sourceSettings: ({ id: "", fields: [], values: {} })
     property var pages: ({})
     property var bookshelf: ({})
     property int currPage: 0
@@ -28,6 +31,15 @@ QtObject {
     property string mangaDescription: ""
     property string mangaDate: ""
     property string errorMessage: ""
+    // { total, series: [{ url, backend, title, newChapters }] } of the last check that found chapters
+    property var updates: ({ total: 0, series: [] })
+    // { id, title, chapter, state, downloaded, total, message } of the latest download change
+    property var download: null
+    property bool downloadsPaused: false
+    property var capabilities: ({ search_by_id: true, search: false, popular: false, latest: false, status_filter: false, genres: [] })
+    property var searchResults: []
+    property int searchPage: 1
+    property bool searchHasNextPage: false
 
     Component.onCompleted: {
         pages = new Map();
@@ -38,6 +50,16 @@ QtObject {
     signal backendInitialized()
     signal pageViewUpdated()
     signal pagesUpdated(int chapter, int page)
+    signal searchResultsUpdated()
+    signal sourceSettingsUpdated()
+    // emitted with { id, url, cookies, message } when a source needs a fresh token
+    signal credentialsRequested(var request)
+    // emitted with StateManager.updates when the bookshelf has new chapters
+    signal updatesReceived(var updates)
+    // emitted with StateManager.download whenever it changes
+    signal downloadUpdated(var download)
+    // emitted with { kind, message, transient } when a request failed
+    signal errorReceived(var error)
 
     function updateOrCreatePage(chapter, page, data) {
         let map = pages.get(chapter);
@@ -56,4 +78,27 @@ QtObject {
 
         bookshelfUpdated();
     }
+
+    function setDownloadProgress(progress) {
+        // state changes of the same download do not repeat the page counts
+        if (download && download.id === progress.id && progress.total === null) {
+            progress.downloaded = download.downloaded;
+            progress.total = download.total;
+        }
+        download = progress;
+        if (progress.state === "failed") {
+            errorMessage = `${progress.chapter}: ${progress.message}`;
+        }
+
+        downloadUpdated(progress);
+    }
+
+    function setSearchResults(results) {
+        // later pages extend the list so it can be scrolled as one
+        searchResults = results.page > 1 ? searchResults.concat(results.mangas) : results.mangas;
+        searchPage = results.page;
+        searchHasNextPage = results.hasNextPage;
+
+        searchResultsUpdated();
+    }
 }
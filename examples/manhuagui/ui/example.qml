@@ -86,7 +86,7 @@ Rectangle {
                     backendImage.source = url;
                     break;
                 case 1000:
-                    error.text = contents;
+                    error.text = JSON.parse(contents).message;
                     break;
                 case 11:
                     // stat.text = `${contents}`
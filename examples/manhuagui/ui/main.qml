@@ -227,7 +227,138 @@ Rectangle {
                         text: "View Bookshelf"
                     }
                 }
+                Rectangle {
+                    width: 300
+                    height: 45
+                    border.width: 2
+                    border.color: "black"
+                    MouseArea {
+                        anchors.fill: parent
+                        onClicked: () => {
+                            settings.visible = false;
+                            BackendController.downloadChapters([StateManager.currChpt - 1]);
+                        }
+                    }
+                    Text {
+                        font.pointSize: 24
+                        text: "Download chapter"
+                    }
+                }
+                Rectangle {
+                    width: 300
+                    height: 45
+                    border.width: 2
+                    border.color: "black"
+                    MouseArea {
+                        anchors.fill: parent
+                        onClicked: () => {
+                            settings.visible = false;
+                            BackendController.downloadChapters();
+                        }
+                    }
+                    Text {
+                        font.pointSize: 24
+                        text: "Download all"
+                    }
+                }
+                Rectangle {
+                    width: 300
+                    height: 45
+                    border.width: 2
+                    border.color: "black"
+                    MouseArea {
+                        anchors.fill: parent
+                        onClicked: () => {
+                            settings.visible = false;
+                            BackendController.exportChapters("cbz");
+                        }
+                    }
+                    Text {
+                        font.pointSize: 24
+                        text: "Export as CBZ"
+                    }
+                }
+                Rectangle {
+                    width: 300
+                    height: 45
+                    border.width: 2
+                    border.color: "black"
+                    MouseArea {
+                        anchors.fill: parent
+                        onClicked: () => {
+                            settings.visible = false;
+                            BackendController.exportChapters("epub");
+                        }
+                    }
+                    Text {
+                        font.pointSize: 24
+                        text: "Export as EPUB"
+                    }
+                }
             }
         }
     }
+
+    // progress of the latest download, tap to pause or resume, hold to cancel everything
+    Rectangle {
+        z: 100000
+        readonly property var download: StateManager.download
+        visible: download !== null && (download.state === "downloading" || download.state === "queued" || download.state === "paused")
+        anchors.top: parent.top
+        anchors.horizontalCenter: parent.horizontalCenter
+        anchors.topMargin: 20
+        width: parent.width * 0.5
+        height: 60
+        border.width: 2
+        border.color: "black"
+        Text {
+            anchors.centerIn: parent
+            font.pointSize: 18
+            text: {
+                const download = parent.download;
+                if (!download) {
+                    return "";
+                }
+                const pages = download.total === null ? "" : ` ${download.downloaded}/${download.total}`;
+                const state = StateManager.downloadsPaused ? "Paused" : "Downloading";
+                return `${state} ${download.title} - ${download.chapter}${pages}`;
+            }
+        }
+        MouseArea {
+            anchors.fill: parent
+            onClicked: () => {
+                if (StateManager.downloadsPaused) {
+                    BackendController.resumeDownloads();
+                } else {
+                    BackendController.pauseDownloads();
+                }
+            }
+            onPressAndHold: () => { BackendController.cancelDownload() }
+        }
+    }
+
+    // shows the last failed request until tapped
+    Rectangle {
+        z: 100000
+        visible: StateManager.errorMessage !== ""
+        anchors.bottom: parent.bottom
+        anchors.horizontalCenter: parent.horizontalCenter
+        anchors.bottomMargin: 40
+        width: parent.width * 0.8
+        height: errorText.implicitHeight + 40
+        border.width: 2
+        border.color: "black"
+        Text {
+            id: errorText
+            anchors.fill: parent
+            anchors.margins: 20
+            wrapMode: Text.Wrap
+            font.pointSize: 18
+            text: StateManager.errorMessage
+        }
+        MouseArea {
+            anchors.fill: parent
+            onClicked: () => { StateManager.errorMessage = "" }
+        }
+    }
 }
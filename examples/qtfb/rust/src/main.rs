@@ -1,12 +1,19 @@
-use qtfb_client::ClientConnection;
+use qtfb_client::{convert::ConvertOptions, ClientConnection};
 
 fn main() {
-    let client = ClientConnection::new(
+    let mut client = ClientConnection::new(
         qtfb_client::constants::DEFAULT_SCENE,
-        qtfb_client::constants::FBFMT_RMPP_RGB888
-    ).unwrap();
-    let file_contents = std::fs::read("a.raw").unwrap();
-    client.shm[0..file_contents.len()].copy_from_slice(&file_contents);
+        qtfb_client::constants::FBFMT_RMPP_RGB888,
+        None,
+    )
+    .unwrap();
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "a.png".to_string());
+    let file_contents = std::fs::read(path).unwrap();
+    client
+        .draw_encoded_image(&file_contents, &ConvertOptions::default())
+        .unwrap();
     client.send_complete_update().unwrap();
     std::thread::sleep(std::time::Duration::from_secs(10));
 }